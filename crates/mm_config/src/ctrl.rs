@@ -0,0 +1,549 @@
+// The configuration of the control thread (ctrl_cfg.json).
+// Older files are upgraded by migrate::ctrl before they are read.
+//
+// Optional fields and their defaults:
+//   ws_cfg.led_rise_time       80 [us]
+//   ws_cfg.wall_edge_enable    false
+//   ws_cfg.wall_edge_position  0.06 [m]
+//   *_pid.dead_zone            0.0
+//   profiles                   []
+// All other fields are required.
+//
+// Profiles are named sets of values (speeds, PID gains, ...) layered over the rest of the
+// file, e.g. for slow and fast runs. One of them is picked at boot with the hand gestures.
+//   "profiles": [
+//     { "name": "slow", "overrides": { "search_ctrl_cfg": { "vel_fwd": 0.3 } } },
+//     { "name": "fast", "overrides": {
+//         "search_ctrl_cfg": { "vel_fwd": 0.6, "v_pid": { "p": 6 } }
+//     } }
+//   ]
+
+use crate::migrate::CTRL_VERSION;
+use crate::{join, Object, Problem, Problems, Reader};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+pub const ADC_MAX: u16 = 4095; // 12 bit ADC of the wall sensors
+pub const MAX_PROFILES: usize = 5; // Counted by the blinks of the LED at boot
+
+#[derive(Debug, Serialize, Deserialize, Default, PartialEq, Clone)]
+#[serde(try_from = "Value")]
+pub struct ControlThreadConfig {
+    pub version: u32, // See migrate.rs
+
+    pub ws_cfg: WsConfig,
+    pub gyro_cfg: GyroConfig,
+    pub mech_param: MechanicalParameter,
+    pub battery_cfg: BatteryConfig,
+
+    pub search_ctrl_cfg: SearchControlConfig,
+
+    pub judge_position: f32,
+
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub profiles: Vec<Profile>,
+}
+
+#[derive(Debug, Serialize, Default, PartialEq, Clone)]
+pub struct Profile {
+    pub name: String,
+    pub overrides: Map<String, Value>, // Merged over the rest of the file
+}
+
+#[derive(Debug, Serialize, Default, PartialEq, Clone)]
+pub struct WsConfig {
+    pub led_rise_time: u32,
+
+    pub rs_reference: u16,
+    pub ls_reference: u16,
+
+    pub rs_threshold: u16,
+    pub rf_threshold: u16,
+    pub lf_threshold: u16,
+    pub ls_threshold: u16,
+
+    pub wall_edge_enable: bool,
+    pub wall_edge_position: f32,
+
+    pub ls_correction_table: Vec<(u16, f32)>,
+    pub rs_correction_table: Vec<(u16, f32)>,
+}
+
+#[derive(Debug, Serialize, Default, PartialEq, Clone)]
+pub struct GyroConfig {
+    pub correction_table: Vec<(i16, f32)>,
+}
+
+#[derive(Debug, Serialize, Default, PartialEq, Clone)]
+pub struct MechanicalParameter {
+    pub wheel_diameter: f32,
+    pub gear_ratio: f32,
+}
+
+#[derive(Debug, Serialize, Default, PartialEq, Clone)]
+pub struct BatteryConfig {
+    pub correction_table: Vec<(i16, f32)>,
+}
+
+#[derive(Debug, Serialize, Default, PartialEq, Clone)]
+pub struct SearchControlConfig {
+    pub vel_fwd: f32,
+    pub theta_pid: PidParameter,
+    pub omega_pid: PidParameter,
+    pub v_pid: PidParameter,
+    pub pos_pid: PidParameter,
+    pub wall_pid: PidParameter,
+}
+
+#[derive(Debug, Serialize, Default, PartialEq, Clone, Copy)]
+pub struct PidParameter {
+    pub p: f32,
+    pub i: f32,
+    pub d: f32,
+    pub i_limit: f32,
+    pub dead_zone: f32,
+}
+
+impl ControlThreadConfig {
+    // The configuration with the defaults filled in, and all the problems of `value`.
+    // Fields with problems are left at the default of their type.
+    // Each profile is checked with the rest of the file too.
+    pub fn load(value: &Value) -> (Self, Vec<Problem>) {
+        let (config, mut problems) = ControlThreadConfig::read(value);
+        for profile in config.profiles.iter() {
+            let (_, layered) = ControlThreadConfig::read(&layer(value, &profile.overrides));
+            for mut problem in layered {
+                if !problems.contains(&problem) {
+                    problem.message = format!("{} (profile '{}')", problem.message, profile.name);
+                    problems.push(problem);
+                }
+            }
+        }
+        (config, problems)
+    }
+
+    // The configuration with the profile `name` layered over the rest of `value`
+    pub fn with_profile(value: &Value, name: &str) -> Result<Self, Problems> {
+        let profile = value["profiles"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .find(|p| p["name"] == name)
+            .and_then(|p| p["overrides"].as_object());
+        match profile {
+            Some(overrides) => ControlThreadConfig::try_from(layer(value, overrides)),
+            None => Err(Problems(vec![Problem {
+                path: "profiles".to_string(),
+                message: format!("no profile '{}'", name),
+            }])),
+        }
+    }
+
+    fn read(value: &Value) -> (Self, Vec<Problem>) {
+        let mut r = Reader::default();
+        let mut o = r.root(value, "");
+        let config = ControlThreadConfig {
+            version: r.field(&mut o, "version"),
+            ws_cfg: WsConfig::read(&mut r, &mut o),
+            gyro_cfg: GyroConfig::read(&mut r, &mut o),
+            mech_param: MechanicalParameter::read(&mut r, &mut o),
+            battery_cfg: BatteryConfig::read(&mut r, &mut o),
+            search_ctrl_cfg: SearchControlConfig::read(&mut r, &mut o),
+            judge_position: r.field(&mut o, "judge_position"),
+            profiles: Profile::read_all(&mut r, &mut o),
+        };
+        r.end(o);
+
+        // A field that could not be read is not checked again
+        let mut problems = r.problems;
+        for problem in config.validate() {
+            if !problems.iter().any(|p| problem.path.starts_with(&p.path)) {
+                problems.push(problem);
+            }
+        }
+        (config, problems)
+    }
+
+    // Invariants of the values, e.g. the correction tables must be sorted
+    pub fn validate(&self) -> Vec<Problem> {
+        let mut r = Reader::default();
+        if self.version != CTRL_VERSION {
+            r.problem(
+                "version",
+                format!(
+                    "{} is not the current version {}",
+                    self.version, CTRL_VERSION
+                ),
+            );
+        }
+        let ws = &self.ws_cfg;
+        for (key, value) in [
+            ("rs_reference", ws.rs_reference),
+            ("ls_reference", ws.ls_reference),
+            ("rs_threshold", ws.rs_threshold),
+            ("rf_threshold", ws.rf_threshold),
+            ("lf_threshold", ws.lf_threshold),
+            ("ls_threshold", ws.ls_threshold),
+        ] {
+            if value > ADC_MAX {
+                r.problem(
+                    &join("ws_cfg", key),
+                    format!("{} is out of the ADC range 0 - {}", value, ADC_MAX),
+                );
+            }
+        }
+        check_table(
+            &mut r,
+            "ws_cfg.ls_correction_table",
+            &ws.ls_correction_table,
+        );
+        check_table(
+            &mut r,
+            "ws_cfg.rs_correction_table",
+            &ws.rs_correction_table,
+        );
+        check_table(
+            &mut r,
+            "gyro_cfg.correction_table",
+            &self.gyro_cfg.correction_table,
+        );
+        check_table(
+            &mut r,
+            "battery_cfg.correction_table",
+            &self.battery_cfg.correction_table,
+        );
+
+        check_positive(
+            &mut r,
+            "mech_param.wheel_diameter",
+            self.mech_param.wheel_diameter,
+        );
+        check_positive(&mut r, "mech_param.gear_ratio", self.mech_param.gear_ratio);
+
+        let search = &self.search_ctrl_cfg;
+        check_positive(&mut r, "search_ctrl_cfg.vel_fwd", search.vel_fwd);
+        for (key, pid) in [
+            ("theta_pid", &search.theta_pid),
+            ("omega_pid", &search.omega_pid),
+            ("v_pid", &search.v_pid),
+            ("pos_pid", &search.pos_pid),
+            ("wall_pid", &search.wall_pid),
+        ] {
+            pid.check(&mut r, &join("search_ctrl_cfg", key));
+        }
+        r.problems
+    }
+}
+
+impl TryFrom<Value> for ControlThreadConfig {
+    type Error = Problems;
+
+    fn try_from(value: Value) -> Result<Self, Problems> {
+        let (config, problems) = ControlThreadConfig::load(&value);
+        Reader { problems }.finish(config)
+    }
+}
+
+// `value` without its profiles, with `overrides` merged over it
+fn layer(value: &Value, overrides: &Map<String, Value>) -> Value {
+    fn merge(base: &mut Value, overrides: &Map<String, Value>) {
+        for (key, value) in overrides.iter() {
+            match (base.get_mut(key), value) {
+                (Some(base @ Value::Object(_)), Value::Object(o)) => merge(base, o),
+                _ => {
+                    if let Some(map) = base.as_object_mut() {
+                        map.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+        }
+    }
+    let mut layered = value.clone();
+    if let Some(map) = layered.as_object_mut() {
+        map.remove("profiles");
+    }
+    merge(&mut layered, overrides);
+    layered
+}
+
+// The raw values of a correction table must be in ascending order for misc::correct_value
+fn check_table<T: PartialOrd + std::fmt::Display>(r: &mut Reader, path: &str, table: &[(T, f32)]) {
+    if table.is_empty() {
+        r.problem(path, "must not be empty");
+    }
+    for (i, pair) in table.windows(2).enumerate() {
+        if pair[1].0 <= pair[0].0 {
+            r.problem(
+                &format!("{}.{}.0", path, i + 1),
+                format!("{} must be greater than {} before it", pair[1].0, pair[0].0),
+            );
+        }
+    }
+}
+
+fn check_positive(r: &mut Reader, path: &str, value: f32) {
+    if value <= 0.0 {
+        r.problem(path, format!("{} must be positive", value));
+    }
+}
+
+impl Profile {
+    fn read_all(r: &mut Reader, parent: &mut Object) -> Vec<Self> {
+        let values: Vec<Value> = r.optional(parent, "profiles", Vec::new());
+        if values.len() > MAX_PROFILES {
+            r.problem(
+                "profiles",
+                format!("{} profiles, at most {}", values.len(), MAX_PROFILES),
+            );
+        }
+        let mut profiles: Vec<Profile> = Vec::new();
+        for (i, value) in values.iter().enumerate() {
+            let mut o = r.root(value, &join("profiles", &i.to_string()));
+            let profile = Profile {
+                name: r.field(&mut o, "name"),
+                overrides: r.field(&mut o, "overrides"),
+            };
+            if profiles.iter().any(|p| p.name == profile.name) {
+                let message = format!("'{}' is used by another profile", profile.name);
+                r.problem(&join(&o.path, "name"), message);
+            }
+            r.end(o);
+            profiles.push(profile);
+        }
+        profiles
+    }
+}
+
+impl WsConfig {
+    fn read(r: &mut Reader, parent: &mut Object) -> Self {
+        let mut o = r.object(parent, "ws_cfg");
+        let config = WsConfig {
+            led_rise_time: r.optional(&mut o, "led_rise_time", 80),
+            rs_reference: r.field(&mut o, "rs_reference"),
+            ls_reference: r.field(&mut o, "ls_reference"),
+            rs_threshold: r.field(&mut o, "rs_threshold"),
+            rf_threshold: r.field(&mut o, "rf_threshold"),
+            lf_threshold: r.field(&mut o, "lf_threshold"),
+            ls_threshold: r.field(&mut o, "ls_threshold"),
+            wall_edge_enable: r.optional(&mut o, "wall_edge_enable", false),
+            wall_edge_position: r.optional(&mut o, "wall_edge_position", 0.06),
+            ls_correction_table: r.field(&mut o, "ls_correction_table"),
+            rs_correction_table: r.field(&mut o, "rs_correction_table"),
+        };
+        r.end(o);
+        config
+    }
+}
+
+impl GyroConfig {
+    fn read(r: &mut Reader, parent: &mut Object) -> Self {
+        let mut o = r.object(parent, "gyro_cfg");
+        let config = GyroConfig {
+            correction_table: r.field(&mut o, "correction_table"),
+        };
+        r.end(o);
+        config
+    }
+}
+
+impl MechanicalParameter {
+    fn read(r: &mut Reader, parent: &mut Object) -> Self {
+        let mut o = r.object(parent, "mech_param");
+        let config = MechanicalParameter {
+            wheel_diameter: r.field(&mut o, "wheel_diameter"),
+            gear_ratio: r.field(&mut o, "gear_ratio"),
+        };
+        r.end(o);
+        config
+    }
+}
+
+impl BatteryConfig {
+    fn read(r: &mut Reader, parent: &mut Object) -> Self {
+        let mut o = r.object(parent, "battery_cfg");
+        let config = BatteryConfig {
+            correction_table: r.field(&mut o, "correction_table"),
+        };
+        r.end(o);
+        config
+    }
+}
+
+impl SearchControlConfig {
+    fn read(r: &mut Reader, parent: &mut Object) -> Self {
+        let mut o = r.object(parent, "search_ctrl_cfg");
+        let config = SearchControlConfig {
+            vel_fwd: r.field(&mut o, "vel_fwd"),
+            theta_pid: PidParameter::read(r, &mut o, "theta_pid"),
+            omega_pid: PidParameter::read(r, &mut o, "omega_pid"),
+            v_pid: PidParameter::read(r, &mut o, "v_pid"),
+            pos_pid: PidParameter::read(r, &mut o, "pos_pid"),
+            wall_pid: PidParameter::read(r, &mut o, "wall_pid"),
+        };
+        r.end(o);
+        config
+    }
+}
+
+impl PidParameter {
+    fn read(r: &mut Reader, parent: &mut Object, key: &'static str) -> Self {
+        let mut o = r.object(parent, key);
+        let config = PidParameter {
+            p: r.field(&mut o, "p"),
+            i: r.field(&mut o, "i"),
+            d: r.field(&mut o, "d"),
+            i_limit: r.field(&mut o, "i_limit"),
+            dead_zone: r.optional(&mut o, "dead_zone", 0.0),
+        };
+        r.end(o);
+        config
+    }
+
+    // Negative gains or limits turn the feedback around
+    fn check(&self, r: &mut Reader, path: &str) {
+        for (key, value) in [
+            ("p", self.p),
+            ("i", self.i),
+            ("d", self.d),
+            ("i_limit", self.i_limit),
+            ("dead_zone", self.dead_zone),
+        ] {
+            if value < 0.0 {
+                r.problem(&join(path, key), format!("{} must not be negative", value));
+            }
+        }
+        if self.i > 0.0 && self.i_limit == 0.0 {
+            r.problem(&join(path, "i_limit"), "must be positive when i is used");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn repository_file() -> Value {
+        let text = include_str!("../../../ctrl_cfg.json");
+        serde_json::from_str(text).unwrap()
+    }
+
+    fn paths(problems: &[Problem]) -> Vec<&str> {
+        problems.iter().map(|p| p.path.as_str()).collect()
+    }
+
+    #[test]
+    fn the_file_of_the_repository_is_valid() {
+        let (config, problems) = ControlThreadConfig::load(&repository_file());
+        assert_eq!(problems, []);
+        assert_eq!(config.search_ctrl_cfg.v_pid.p, 5.0);
+        assert_eq!(config.ws_cfg.rs_correction_table, [(0, 0.0), (790, 1000.0)]);
+    }
+
+    #[test]
+    fn optional_fields_get_their_defaults() {
+        let mut value = repository_file();
+        value["ws_cfg"]
+            .as_object_mut()
+            .unwrap()
+            .remove("led_rise_time");
+        value["search_ctrl_cfg"]["v_pid"]
+            .as_object_mut()
+            .unwrap()
+            .remove("dead_zone");
+        let config: ControlThreadConfig = serde_json::from_value(value).unwrap();
+        assert_eq!(config.ws_cfg.led_rise_time, 80);
+        assert_eq!(config.search_ctrl_cfg.v_pid.dead_zone, 0.0);
+    }
+
+    #[test]
+    fn every_problem_is_reported_with_its_path() {
+        let mut value = repository_file();
+        value["mech_param"]
+            .as_object_mut()
+            .unwrap()
+            .remove("gear_ratio");
+        value["mech_param"]["wheel_diameter"] = serde_json::json!(0.0);
+        value["ws_cfg"]["rs_threshold"] = serde_json::json!("high");
+        value["ws_cfg"]["lf_threshold"] = serde_json::json!(5000);
+        value["ws_cfg"]["ls_treshold"] = serde_json::json!(150);
+        value["gyro_cfg"]["correction_table"] = serde_json::json!([[100, 1], [-100, -1]]);
+        value["search_ctrl_cfg"]["v_pid"]["i_limit"] = serde_json::json!(-1.0);
+        value.as_object_mut().unwrap().remove("battery_cfg");
+
+        let (_, problems) = ControlThreadConfig::load(&value);
+        assert_eq!(
+            paths(&problems),
+            [
+                "ws_cfg.rs_threshold",
+                "ws_cfg.ls_treshold",
+                "mech_param.gear_ratio",
+                "battery_cfg",
+                "ws_cfg.lf_threshold",
+                "gyro_cfg.correction_table.1.0",
+                "mech_param.wheel_diameter",
+                "search_ctrl_cfg.v_pid.i_limit",
+            ]
+        );
+        assert_eq!(problems[2].message, "missing");
+        assert_eq!(problems[1].message, "unknown key");
+    }
+
+    #[test]
+    fn invalid_files_are_refused() {
+        let mut value = repository_file();
+        value["search_ctrl_cfg"]["theta_pid"]["i_limit"] = serde_json::json!(0.0);
+        let err = serde_json::from_value::<ControlThreadConfig>(value).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "search_ctrl_cfg.theta_pid.i_limit: must be positive when i is used"
+        );
+    }
+
+    #[test]
+    fn profiles_are_layered_over_the_rest() {
+        let mut value = repository_file();
+        value["profiles"] = serde_json::json!([
+            { "name": "slow", "overrides": {} },
+            { "name": "fast", "overrides": {
+                "search_ctrl_cfg": { "vel_fwd": 0.6, "v_pid": { "p": 6 } }
+            } },
+        ]);
+        let (config, problems) = ControlThreadConfig::load(&value);
+        assert_eq!(problems, []);
+        assert_eq!(config.profiles[1].name, "fast");
+        assert_eq!(config.search_ctrl_cfg.vel_fwd, 0.4);
+
+        let fast = ControlThreadConfig::with_profile(&value, "fast").unwrap();
+        assert_eq!(fast.search_ctrl_cfg.vel_fwd, 0.6);
+        assert_eq!(fast.search_ctrl_cfg.v_pid.p, 6.0);
+        assert_eq!(fast.search_ctrl_cfg.v_pid.i, 0.04);
+        assert_eq!(fast.profiles, []);
+        let slow = ControlThreadConfig::with_profile(&value, "slow").unwrap();
+        assert_eq!(slow.search_ctrl_cfg, config.search_ctrl_cfg);
+
+        let err = ControlThreadConfig::with_profile(&value, "turbo").unwrap_err();
+        assert_eq!(err.to_string(), "profiles: no profile 'turbo'");
+    }
+
+    #[test]
+    fn problems_of_profiles_are_reported() {
+        let mut value = repository_file();
+        value["profiles"] = serde_json::json!([
+            { "name": "a", "overrides": { "mech_param": { "gear_ratio": -2.0 } } },
+            { "name": "a", "overrides": { "search_ctrl_cfg": { "vel_fast": 1.0 } } },
+            { "overrides": [] },
+        ]);
+        let (_, problems) = ControlThreadConfig::load(&value);
+        let messages: Vec<String> = problems.iter().map(|p| p.to_string()).collect();
+        assert_eq!(
+            messages,
+            [
+                "profiles.1.name: 'a' is used by another profile",
+                "profiles.2.name: missing",
+                "profiles.2.overrides: invalid type: sequence, expected a map",
+                "mech_param.gear_ratio: -2 must be positive (profile 'a')",
+                "search_ctrl_cfg.vel_fast: unknown key (profile 'a')",
+            ]
+        );
+    }
+}
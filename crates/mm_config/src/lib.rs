@@ -0,0 +1,160 @@
+// Configuration files of the micromouse, shared by the firmware and the host tools.
+//
+// The files are read field by field, so every problem is reported with its JSON path
+// (e.g. "search_ctrl_cfg.v_pid.i_limit: must not be negative") instead of the first
+// error of serde.
+
+pub mod ctrl;
+pub mod migrate;
+
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Problem {
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+// All the problems of a file, one per line
+#[derive(Debug, Clone, PartialEq)]
+pub struct Problems(pub Vec<Problem>);
+
+impl fmt::Display for Problems {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, problem) in self.0.iter().enumerate() {
+            if i != 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Problems {}
+
+// "a.b" from "a" and "b"
+pub fn join(path: &str, key: &str) -> String {
+    match path {
+        "" => key.to_string(),
+        _ => format!("{}.{}", path, key),
+    }
+}
+
+// Reads JSON objects into structs and collects the problems
+#[derive(Default)]
+pub struct Reader {
+    pub problems: Vec<Problem>,
+}
+
+// An object being read. The keys that are read are remembered to report the unknown ones.
+pub struct Object<'v> {
+    map: Option<&'v Map<String, Value>>,
+    path: String,
+    read: Vec<&'static str>,
+}
+
+impl Reader {
+    pub fn problem(&mut self, path: &str, message: impl fmt::Display) {
+        self.problems.push(Problem {
+            path: path.to_string(),
+            message: message.to_string(),
+        });
+    }
+
+    // The member `key` of `parent` as an object
+    pub fn object<'v>(&mut self, parent: &mut Object<'v>, key: &'static str) -> Object<'v> {
+        parent.read.push(key);
+        let path = join(&parent.path, key);
+        match parent.map.and_then(|m| m.get(key)) {
+            Some(value) => self.root(value, &path),
+            None => {
+                self.problem(&path, "missing");
+                Object {
+                    map: None,
+                    path,
+                    read: Vec::new(),
+                }
+            }
+        }
+    }
+
+    // `value` as an object, e.g. the whole file
+    pub fn root<'v>(&mut self, value: &'v Value, path: &str) -> Object<'v> {
+        let map = value.as_object();
+        if map.is_none() {
+            self.problem(path, format!("expected an object, found {}", value));
+        }
+        Object {
+            map,
+            path: path.to_string(),
+            read: Vec::new(),
+        }
+    }
+
+    // A required field. The default of the type is returned with a problem if it is missing.
+    pub fn field<T: DeserializeOwned + Default>(&mut self, o: &mut Object, key: &'static str) -> T {
+        self.read(o, key, None)
+    }
+
+    // An optional field with its default
+    pub fn optional<T: DeserializeOwned + Default>(
+        &mut self,
+        o: &mut Object,
+        key: &'static str,
+        default: T,
+    ) -> T {
+        self.read(o, key, Some(default))
+    }
+
+    fn read<T: DeserializeOwned + Default>(
+        &mut self,
+        o: &mut Object,
+        key: &'static str,
+        default: Option<T>,
+    ) -> T {
+        o.read.push(key);
+        let path = join(&o.path, key);
+        match (o.map.and_then(|m| m.get(key)), default) {
+            (Some(value), _) => match T::deserialize(value) {
+                Ok(v) => v,
+                Err(e) => {
+                    self.problem(&path, e);
+                    T::default()
+                }
+            },
+            (None, Some(default)) => default,
+            (None, None) => {
+                // A missing object is reported once, not for each of its fields
+                if o.map.is_some() {
+                    self.problem(&path, "missing");
+                }
+                T::default()
+            }
+        }
+    }
+
+    // Report the keys of `o` that were not read, e.g. misspelled ones
+    pub fn end(&mut self, o: Object) {
+        for key in o.map.into_iter().flat_map(|m| m.keys()) {
+            if !o.read.contains(&key.as_str()) {
+                self.problem(&join(&o.path, key), "unknown key");
+            }
+        }
+    }
+
+    pub fn finish<T>(self, value: T) -> Result<T, Problems> {
+        match self.problems.is_empty() {
+            true => Ok(value),
+            false => Err(Problems(self.problems)),
+        }
+    }
+}
@@ -0,0 +1,140 @@
+// Upgrades of older configuration files to the current structs.
+//
+// Each file has a "version" field. Files without it are version 0, i.e. the files from
+// before the versioning. STEPS[n] upgrades a document of version n to n + 1, so a change
+// of a struct that breaks old files adds a step here and bumps the version.
+//
+// ctrl_cfg.json
+//   1: "version" is added
+// ope_cfg.json
+//   1: "version" is added
+
+use crate::Problem;
+use serde_json::{Map, Value};
+
+type Step = fn(&mut Map<String, Value>);
+
+const CTRL_STEPS: [Step; 1] = [ctrl_v1];
+const OPE_STEPS: [Step; 1] = [ope_v1];
+
+pub const CTRL_VERSION: u32 = CTRL_STEPS.len() as u32;
+pub const OPE_VERSION: u32 = OPE_STEPS.len() as u32;
+
+// Upgrade ctrl_cfg.json. Returns the version of the original if it was upgraded.
+pub fn ctrl(value: &mut Value) -> Result<Option<u32>, Problem> {
+    migrate(value, &CTRL_STEPS)
+}
+
+// Upgrade ope_cfg.json. Returns the version of the original if it was upgraded.
+pub fn ope(value: &mut Value) -> Result<Option<u32>, Problem> {
+    migrate(value, &OPE_STEPS)
+}
+
+fn migrate(value: &mut Value, steps: &[Step]) -> Result<Option<u32>, Problem> {
+    let problem = |path: &str, message: String| Problem {
+        path: path.to_string(),
+        message,
+    };
+    let map = value
+        .as_object_mut()
+        .ok_or_else(|| problem("", "expected an object".to_string()))?;
+    let version = match map.get("version") {
+        Some(v) => v
+            .as_u64()
+            .ok_or_else(|| problem("version", format!("{} is not a version", v)))?
+            as usize,
+        None => 0,
+    };
+    if version > steps.len() {
+        return Err(problem(
+            "version",
+            format!("{} is newer than {} of this firmware", version, steps.len()),
+        ));
+    }
+    for step in steps[version..].iter() {
+        step(map);
+    }
+    match version == steps.len() {
+        true => Ok(None),
+        false => Ok(Some(version as u32)),
+    }
+}
+
+fn set_version(map: &mut Map<String, Value>, version: u32) {
+    map.insert("version".to_string(), Value::from(version));
+}
+
+fn ctrl_v1(map: &mut Map<String, Value>) {
+    set_version(map, 1);
+}
+
+fn ope_v1(map: &mut Map<String, Value>) {
+    set_version(map, 1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ctrl::ControlThreadConfig;
+    use serde_json::json;
+
+    fn repository_file(text: &str) -> Value {
+        serde_json::from_str(text).unwrap()
+    }
+
+    // The files of the repository without their version
+    fn version_0(text: &str) -> Value {
+        let mut value = repository_file(text);
+        value.as_object_mut().unwrap().remove("version");
+        value
+    }
+
+    #[test]
+    fn ctrl_v0_to_v1() {
+        let mut value = version_0(include_str!("../../../ctrl_cfg.json"));
+        let mut map = value.as_object().unwrap().clone();
+        ctrl_v1(&mut map);
+        assert_eq!(map["version"], 1);
+        map.remove("version");
+        assert_eq!(Value::Object(map), value);
+
+        assert_eq!(ctrl(&mut value), Ok(Some(0)));
+        let (_, problems) = ControlThreadConfig::load(&value);
+        assert_eq!(problems, []);
+    }
+
+    #[test]
+    fn ope_v0_to_v1() {
+        let mut value = version_0(include_str!("../../../ope_cfg.json"));
+        let original = value.clone();
+        assert_eq!(ope(&mut value), Ok(Some(0)));
+        assert_eq!(value["version"], 1);
+        value.as_object_mut().unwrap().remove("version");
+        assert_eq!(value, original);
+    }
+
+    #[test]
+    fn the_files_of_the_repository_are_current() {
+        let mut value = repository_file(include_str!("../../../ctrl_cfg.json"));
+        assert_eq!(ctrl(&mut value), Ok(None));
+        assert_eq!(value["version"], CTRL_VERSION);
+        let mut value = repository_file(include_str!("../../../ope_cfg.json"));
+        assert_eq!(ope(&mut value), Ok(None));
+        assert_eq!(value["version"], OPE_VERSION);
+    }
+
+    #[test]
+    fn newer_and_broken_versions_are_refused() {
+        let mut value = json!({ "version": CTRL_VERSION + 1 });
+        let err = ctrl(&mut value).unwrap_err();
+        assert_eq!(err.path, "version");
+        assert_eq!(value["version"], CTRL_VERSION + 1);
+
+        let mut value = json!({ "version": "1" });
+        assert_eq!(
+            ctrl(&mut value).unwrap_err().message,
+            "\"1\" is not a version"
+        );
+        assert!(ctrl(&mut json!([])).is_err());
+    }
+}
@@ -0,0 +1,314 @@
+// Typed events saved alongside the run log.
+//
+// File layout (all values are little endian):
+//   magic        "MMEV"
+//   version      u16
+//   name tables  for each kind: name count u8, (name length u8, name) * name count
+//                (version 1 has no table for Profile, the kinds added since)
+//   events       (time u32, kind u8, code u8, value f32) until the end of the file
+//
+// `time` has the same base as the "time" field of the run log [ms].
+// The meaning of `code` depends on the kind. The name table of a kind gives
+// a name to each code, e.g. the names of commands for CommandReceived.
+
+use std::io::{self, Read, Write};
+
+pub const MAGIC: [u8; 4] = *b"MMEV";
+pub const VERSION: u16 = 2;
+pub const EVENT_SIZE: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    CommandReceived, // code: command, value: argument of the command
+    CommandDone,     // code: command, value: argument of the command
+    Request,         // value: request id
+    WallEdge,        // code: 0 left, 1 right, value: corrected position [m]
+    PositionReset,   // value: x before the reset [m]
+    WallReference,   // code: 0 left, 1 right, value: reference of the side sensor
+    Fault,           // A fault reported by the operation thread
+    Trigger,         // code: reason of the pre-trigger capture
+    Profile,         // code: index of the profile of ctrl_cfg.json applied to the control thread
+}
+
+pub const KINDS: [EventKind; 9] = [
+    EventKind::CommandReceived,
+    EventKind::CommandDone,
+    EventKind::Request,
+    EventKind::WallEdge,
+    EventKind::PositionReset,
+    EventKind::WallReference,
+    EventKind::Fault,
+    EventKind::Trigger,
+    EventKind::Profile,
+];
+
+// Number of the kinds, and so of the name tables, in a file of `version`
+fn kinds_of(version: u16) -> usize {
+    match version {
+        1 => 8,
+        _ => KINDS.len(),
+    }
+}
+
+impl EventKind {
+    pub fn to_u8(self) -> u8 {
+        self as u8
+    }
+
+    pub fn from_u8(v: u8) -> io::Result<Self> {
+        KINDS
+            .get(v as usize)
+            .copied()
+            .ok_or_else(|| invalid_data(format!("Unknown event kind {}", v)))
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            EventKind::CommandReceived => "command",
+            EventKind::CommandDone => "done",
+            EventKind::Request => "request",
+            EventKind::WallEdge => "wall_edge",
+            EventKind::PositionReset => "position_reset",
+            EventKind::WallReference => "wall_reference",
+            EventKind::Fault => "fault",
+            EventKind::Trigger => "trigger",
+            EventKind::Profile => "profile",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Event {
+    pub time: u32,
+    pub kind: EventKind,
+    pub code: u8,
+    pub value: f32,
+}
+
+impl Event {
+    pub fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(&self.time.to_le_bytes())?;
+        w.write_all(&[self.kind.to_u8(), self.code])?;
+        w.write_all(&self.value.to_le_bytes())?;
+        Ok(())
+    }
+
+    // Returns None at the end of the file. A truncated last event is ignored.
+    pub fn read<R: Read>(r: &mut R) -> io::Result<Option<Self>> {
+        let mut b = [0u8; EVENT_SIZE];
+        match r.read_exact(&mut b) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        Ok(Some(Event {
+            time: u32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            kind: EventKind::from_u8(b[4])?,
+            code: b[5],
+            value: f32::from_le_bytes([b[6], b[7], b[8], b[9]]),
+        }))
+    }
+}
+
+// Name tables of the codes, indexed by kind
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Header {
+    pub names: Vec<Vec<String>>,
+}
+
+impl Header {
+    pub fn new(names: &[&[&str]]) -> Self {
+        Header {
+            names: names
+                .iter()
+                .map(|table| table.iter().map(|s| s.to_string()).collect())
+                .collect(),
+        }
+    }
+
+    // Name of the code of an event, or the code itself if it has no name
+    pub fn code_name(&self, event: &Event) -> String {
+        match self
+            .names
+            .get(event.kind.to_u8() as usize)
+            .and_then(|table| table.get(event.code as usize))
+        {
+            Some(name) => name.clone(),
+            None => event.code.to_string(),
+        }
+    }
+
+    pub fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(&MAGIC)?;
+        w.write_all(&VERSION.to_le_bytes())?;
+        for i in 0..KINDS.len() {
+            let table = self.names.get(i).map_or(&[][..], |t| t.as_slice());
+            w.write_all(&[table.len() as u8])?;
+            for name in table.iter() {
+                w.write_all(&[name.len() as u8])?;
+                w.write_all(name.as_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn read<R: Read>(r: &mut R) -> io::Result<Self> {
+        let mut magic = [0u8; 4];
+        r.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(invalid_data("Not an event file (bad magic)".to_string()));
+        }
+        let mut version = [0u8; 2];
+        r.read_exact(&mut version)?;
+        let version = u16::from_le_bytes(version);
+        if version > VERSION {
+            return Err(invalid_data(format!("Unsupported version {}", version)));
+        }
+        let mut names = Vec::with_capacity(KINDS.len());
+        for _ in 0..kinds_of(version) {
+            let count = read_u8(r)?;
+            let mut table = Vec::with_capacity(count as usize);
+            for _ in 0..count {
+                let mut name = vec![0u8; read_u8(r)? as usize];
+                r.read_exact(&mut name)?;
+                table.push(String::from_utf8(name).map_err(|e| invalid_data(e.to_string()))?);
+            }
+            names.push(table);
+        }
+        names.resize(KINDS.len(), Vec::new());
+        Ok(Header { names })
+    }
+}
+
+fn read_u8<R: Read>(r: &mut R) -> io::Result<u8> {
+    let mut b = [0u8; 1];
+    r.read_exact(&mut b)?;
+    Ok(b[0])
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COMMANDS: [&str; 2] = ["GyroCalibration", "StartLog"];
+
+    fn header() -> Header {
+        let mut names: Vec<&[&str]> = vec![&[]; KINDS.len()];
+        names[EventKind::CommandReceived as usize] = &COMMANDS;
+        names[EventKind::WallEdge as usize] = &["left", "right"];
+        Header::new(&names)
+    }
+
+    fn event(time: u32, kind: EventKind, code: u8, value: f32) -> Event {
+        Event {
+            time,
+            kind,
+            code,
+            value,
+        }
+    }
+
+    #[test]
+    fn file_round_trip() {
+        let events: Vec<Event> = KINDS
+            .iter()
+            .enumerate()
+            .map(|(i, kind)| event(1000 + i as u32, *kind, i as u8, i as f32 * -0.5))
+            .collect();
+        let mut bytes = Vec::new();
+        header().write(&mut bytes).unwrap();
+        for e in events.iter() {
+            e.write(&mut bytes).unwrap();
+        }
+        // An event cut off by a reset is ignored
+        bytes.extend_from_slice(&[1, 2, 3]);
+
+        let mut r = bytes.as_slice();
+        assert_eq!(Header::read(&mut r).unwrap(), header());
+        let mut read = Vec::new();
+        while let Some(e) = Event::read(&mut r).unwrap() {
+            read.push(e);
+        }
+        assert_eq!(read, events);
+    }
+
+    #[test]
+    fn short_header_tables_are_padded() {
+        // Kinds without a table are written with no names
+        let header = Header::new(&[&COMMANDS]);
+        let mut bytes = Vec::new();
+        header.write(&mut bytes).unwrap();
+        let read = Header::read(&mut bytes.as_slice()).unwrap();
+        assert_eq!(read.names.len(), KINDS.len());
+        assert_eq!(read.names[0], COMMANDS);
+        assert!(read.names[1..].iter().all(|t| t.is_empty()));
+    }
+
+    #[test]
+    fn version_1_files_are_read() {
+        // Written before Profile, without its name table
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&MAGIC);
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&[
+            2, 4, b'l', b'e', b'f', b't', 5, b'r', b'i', b'g', b'h', b't',
+        ]);
+        bytes.extend_from_slice(&[0; 7]);
+        event(5, EventKind::Trigger, 1, 0.0)
+            .write(&mut bytes)
+            .unwrap();
+
+        let mut r = bytes.as_slice();
+        let header = Header::read(&mut r).unwrap();
+        assert_eq!(header.names.len(), KINDS.len());
+        assert_eq!(header.names[0], ["left", "right"]);
+        assert_eq!(
+            Event::read(&mut r).unwrap(),
+            Some(event(5, EventKind::Trigger, 1, 0.0))
+        );
+        assert_eq!(Event::read(&mut r).unwrap(), None);
+    }
+
+    #[test]
+    fn codes_without_names_are_numbers() {
+        let header = header();
+        let name = |kind, code| header.code_name(&event(0, kind, code, 0.0));
+        assert_eq!(name(EventKind::CommandReceived, 1), "StartLog");
+        assert_eq!(name(EventKind::CommandReceived, 2), "2");
+        assert_eq!(name(EventKind::WallEdge, 0), "left");
+        assert_eq!(name(EventKind::Fault, 0), "0");
+        assert_eq!(
+            Header::default().code_name(&event(0, EventKind::Trigger, 3, 0.0)),
+            "3"
+        );
+    }
+
+    #[test]
+    fn unknown_kinds_and_headers_are_refused() {
+        let mut bytes = Vec::new();
+        event(0, EventKind::Fault, 0, 0.0)
+            .write(&mut bytes)
+            .unwrap();
+        bytes[4] = KINDS.len() as u8;
+        let err = Event::read(&mut bytes.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(EventKind::from_u8(0xff).is_err());
+        for kind in KINDS.iter() {
+            assert_eq!(EventKind::from_u8(kind.to_u8()).unwrap(), *kind);
+        }
+
+        let mut bytes = Vec::new();
+        header().write(&mut bytes).unwrap();
+        let mut bad_magic = bytes.clone();
+        bad_magic[0] = b'X';
+        assert!(Header::read(&mut bad_magic.as_slice()).is_err());
+        let mut newer = bytes.clone();
+        newer[4..6].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        assert!(Header::read(&mut newer.as_slice()).is_err());
+        assert!(Header::read(&mut &bytes[..bytes.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,241 @@
+// Packet framing for binary data sent over UART.
+//
+// Packet:  kind u8, seq u16, payload, crc u16   (little endian)
+//          The CRC is CRC16-XMODEM over kind, seq and payload.
+// Frame:   COBS encoded packet followed by a 0x00 delimiter.
+//
+// COBS removes every 0x00 from the packet, so a receiver can always
+// resynchronize at the next delimiter after garbage or a lost byte.
+
+pub const DELIMITER: u8 = 0x00;
+
+// Kinds of packets (0x10 - 0x15 are used by transfer)
+pub const KIND_HEADER: u8 = 0; // Payload is a record::Header
+pub const KIND_SAMPLE: u8 = 1; // Payload is a record
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Packet {
+    pub kind: u8,
+    pub seq: u16,
+    pub payload: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameError {
+    Cobs,
+    TooShort,
+    Crc,
+}
+
+pub fn crc(data: &[u8]) -> u16 {
+    crc16::State::<crc16::XMODEM>::calculate(data)
+}
+
+pub fn cobs_encode(data: &[u8], out: &mut Vec<u8>) {
+    let mut code_index = out.len();
+    let mut code = 1u8;
+    out.push(0); // placeholder of the first code byte
+    for &b in data {
+        if b == 0 {
+            out[code_index] = code;
+            code_index = out.len();
+            out.push(0);
+            code = 1;
+        } else {
+            out.push(b);
+            code += 1;
+            if code == 0xff {
+                out[code_index] = code;
+                code_index = out.len();
+                out.push(0);
+                code = 1;
+            }
+        }
+    }
+    out[code_index] = code;
+}
+
+// `data` must not contain the delimiter.
+pub fn cobs_decode(data: &[u8], out: &mut Vec<u8>) -> Result<(), FrameError> {
+    let mut i = 0;
+    while i < data.len() {
+        let code = data[i] as usize;
+        if code == 0 || i + code > data.len() {
+            return Err(FrameError::Cobs);
+        }
+        out.extend_from_slice(&data[i + 1..i + code]);
+        i += code;
+        if code != 0xff && i < data.len() {
+            out.push(0);
+        }
+    }
+    Ok(())
+}
+
+// Append a complete frame, including the delimiter, to `out`.
+pub fn encode_packet(kind: u8, seq: u16, payload: &[u8], out: &mut Vec<u8>) {
+    let mut packet = Vec::with_capacity(payload.len() + 5);
+    packet.push(kind);
+    packet.extend_from_slice(&seq.to_le_bytes());
+    packet.extend_from_slice(payload);
+    let crc = crc(&packet);
+    packet.extend_from_slice(&crc.to_le_bytes());
+    cobs_encode(&packet, out);
+    out.push(DELIMITER);
+}
+
+// Decode a frame without the delimiter.
+pub fn decode_packet(frame: &[u8]) -> Result<Packet, FrameError> {
+    let mut packet = Vec::with_capacity(frame.len());
+    cobs_decode(frame, &mut packet)?;
+    if packet.len() < 5 {
+        return Err(FrameError::TooShort);
+    }
+    let (body, tail) = packet.split_at(packet.len() - 2);
+    if crc(body) != u16::from_le_bytes([tail[0], tail[1]]) {
+        return Err(FrameError::Crc);
+    }
+    Ok(Packet {
+        kind: body[0],
+        seq: u16::from_le_bytes([body[1], body[2]]),
+        payload: body[3..].to_vec(),
+    })
+}
+
+// Splits a byte stream into frames.
+pub struct FrameReader {
+    buffer: Vec<u8>,
+}
+
+impl FrameReader {
+    pub fn new() -> Self {
+        FrameReader { buffer: Vec::new() }
+    }
+
+    // Feed a received byte. Returns a frame (without the delimiter) when it is complete.
+    pub fn push(&mut self, b: u8) -> Option<Vec<u8>> {
+        if b != DELIMITER {
+            self.buffer.push(b);
+            None
+        } else if self.buffer.is_empty() {
+            None
+        } else {
+            Some(std::mem::take(&mut self.buffer))
+        }
+    }
+}
+
+impl Default for FrameReader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(data: &[u8]) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        cobs_encode(data, &mut encoded);
+        assert!(!encoded.contains(&DELIMITER), "{:?}", encoded);
+        let mut decoded = Vec::new();
+        cobs_decode(&encoded, &mut decoded).unwrap();
+        assert_eq!(decoded, data);
+        encoded
+    }
+
+    #[test]
+    fn cobs_zero_runs() {
+        assert_eq!(round_trip(&[]), [1]);
+        assert_eq!(round_trip(&[0]), [1, 1]);
+        assert_eq!(round_trip(&[0, 0, 0]), [1, 1, 1, 1]);
+        assert_eq!(round_trip(&[0, 0x11, 0, 0]), [1, 2, 0x11, 1, 1]);
+        assert_eq!(round_trip(&[0x11, 0x22, 0, 0x33]), [3, 0x11, 0x22, 2, 0x33]);
+    }
+
+    #[test]
+    fn cobs_254_byte_blocks() {
+        let block: Vec<u8> = (1..=254).collect();
+        // A full block has no zero after it, but an empty block ends the data
+        let encoded = round_trip(&block);
+        assert_eq!(encoded.len(), 256);
+        assert_eq!((encoded[0], encoded[255]), (0xff, 1));
+
+        let mut data = block.clone();
+        data.push(0);
+        data.extend_from_slice(&block);
+        data.push(7);
+        round_trip(&data);
+
+        let long: Vec<u8> = (0..1000).map(|i| (i % 255 + 1) as u8).collect();
+        let encoded = round_trip(&long);
+        assert_eq!(encoded.len(), 1000 + 1000 / 254 + 1);
+        round_trip(&[0; 600]);
+    }
+
+    #[test]
+    fn cobs_errors() {
+        let mut out = Vec::new();
+        assert_eq!(cobs_decode(&[0], &mut out), Err(FrameError::Cobs));
+        assert_eq!(cobs_decode(&[5, 1, 2], &mut out), Err(FrameError::Cobs));
+    }
+
+    #[test]
+    fn packet_round_trip() {
+        let mut out = Vec::new();
+        encode_packet(KIND_SAMPLE, 0x1234, &[0, 1, 0, 2], &mut out);
+        assert_eq!(out.last(), Some(&DELIMITER));
+        assert_eq!(out.iter().filter(|&&b| b == DELIMITER).count(), 1);
+        let packet = decode_packet(&out[..out.len() - 1]).unwrap();
+        assert_eq!(
+            packet,
+            Packet {
+                kind: KIND_SAMPLE,
+                seq: 0x1234,
+                payload: vec![0, 1, 0, 2],
+            }
+        );
+    }
+
+    #[test]
+    fn crc_errors_are_rejected() {
+        let mut packet = vec![KIND_HEADER, 1, 0, 0xaa, 0xbb];
+        let crc = crc(&packet);
+        packet.extend_from_slice(&crc.to_le_bytes());
+        for i in 0..packet.len() {
+            let mut broken = packet.clone();
+            broken[i] ^= 0x10;
+            let mut frame = Vec::new();
+            cobs_encode(&broken, &mut frame);
+            assert_eq!(decode_packet(&frame), Err(FrameError::Crc), "byte {}", i);
+        }
+
+        let mut frame = Vec::new();
+        cobs_encode(&[KIND_HEADER, 1, 0, 0], &mut frame);
+        assert_eq!(decode_packet(&frame), Err(FrameError::TooShort));
+    }
+
+    #[test]
+    fn reader_resynchronizes_after_garbage() {
+        let mut stream = vec![0x12, 0x34, 0x56, DELIMITER];
+        encode_packet(KIND_SAMPLE, 1, &[1, 2], &mut stream);
+        // A frame cut off in the middle merges with the next one
+        let mut cut = Vec::new();
+        encode_packet(KIND_SAMPLE, 2, &[3, 4], &mut cut);
+        stream.extend_from_slice(&cut[..3]);
+        encode_packet(KIND_SAMPLE, 3, &[5, 6], &mut stream);
+        // Empty frames between delimiters are skipped
+        stream.extend_from_slice(&[DELIMITER, DELIMITER]);
+        encode_packet(KIND_SAMPLE, 4, &[7, 8], &mut stream);
+
+        let mut reader = FrameReader::new();
+        let frames: Vec<Vec<u8>> = stream.iter().filter_map(|&b| reader.push(b)).collect();
+        assert_eq!(frames.len(), 4);
+        let seqs: Vec<Option<u16>> = frames
+            .iter()
+            .map(|f| decode_packet(f).ok().map(|p| p.seq))
+            .collect();
+        assert_eq!(seqs, [None, Some(1), None, Some(4)]);
+    }
+}
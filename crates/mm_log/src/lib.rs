@@ -0,0 +1,7 @@
+// Data formats shared by the firmware and the host tools.
+
+pub mod event;
+pub mod frame;
+pub mod record;
+pub mod ring;
+pub mod transfer;
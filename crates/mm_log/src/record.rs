@@ -0,0 +1,410 @@
+// Packed binary record format of the run log.
+//
+// File layout (all values are little endian):
+//   magic        "MMLG"
+//   version      u16
+//   interval     u16   sample interval [ms]
+//   field count  u16
+//   fields       (name length u8, name, kind u8, scale f32) * field count
+//   metadata     length u32, UTF-8 text (usually JSON)   (version 2 or later)
+//   records      record_size() bytes each, until the end of the file
+//
+// Each field is stored as a fixed-point integer. The physical value is `raw * scale`.
+
+use std::io::{self, Read, Write};
+
+pub const MAGIC: [u8; 4] = *b"MMLG";
+pub const VERSION: u16 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldKind {
+    U8,
+    I16,
+    U16,
+    U32,
+}
+
+impl FieldKind {
+    pub const fn size(self) -> usize {
+        match self {
+            FieldKind::U8 => 1,
+            FieldKind::I16 | FieldKind::U16 => 2,
+            FieldKind::U32 => 4,
+        }
+    }
+
+    fn to_u8(self) -> u8 {
+        match self {
+            FieldKind::U8 => 0,
+            FieldKind::I16 => 1,
+            FieldKind::U16 => 2,
+            FieldKind::U32 => 3,
+        }
+    }
+
+    fn from_u8(v: u8) -> io::Result<Self> {
+        match v {
+            0 => Ok(FieldKind::U8),
+            1 => Ok(FieldKind::I16),
+            2 => Ok(FieldKind::U16),
+            3 => Ok(FieldKind::U32),
+            _ => Err(invalid_data(format!("Unknown field kind {}", v))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    pub name: String,
+    pub kind: FieldKind,
+    pub scale: f32,
+}
+
+impl Field {
+    pub fn new(name: &str, kind: FieldKind, scale: f32) -> Self {
+        Field {
+            name: name.to_string(),
+            kind,
+            scale,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Header {
+    pub version: u16,
+    pub interval: u16,
+    pub fields: Vec<Field>,
+    pub metadata: String,
+}
+
+impl Header {
+    pub fn new(interval: u16, fields: Vec<Field>) -> Self {
+        Header {
+            version: VERSION,
+            interval,
+            fields,
+            metadata: String::new(),
+        }
+    }
+
+    pub fn record_size(&self) -> usize {
+        self.fields.iter().map(|f| f.kind.size()).sum()
+    }
+
+    pub fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(&MAGIC)?;
+        w.write_all(&self.version.to_le_bytes())?;
+        w.write_all(&self.interval.to_le_bytes())?;
+        w.write_all(&(self.fields.len() as u16).to_le_bytes())?;
+        for field in self.fields.iter() {
+            let name = field.name.as_bytes();
+            w.write_all(&[name.len() as u8])?;
+            w.write_all(name)?;
+            w.write_all(&[field.kind.to_u8()])?;
+            w.write_all(&field.scale.to_le_bytes())?;
+        }
+        w.write_all(&(self.metadata.len() as u32).to_le_bytes())?;
+        w.write_all(self.metadata.as_bytes())?;
+        Ok(())
+    }
+
+    pub fn read<R: Read>(r: &mut R) -> io::Result<Self> {
+        let mut magic = [0u8; 4];
+        r.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(invalid_data("Not a log file (bad magic)".to_string()));
+        }
+        let version = read_u16(r)?;
+        if version > VERSION {
+            return Err(invalid_data(format!("Unsupported version {}", version)));
+        }
+        let interval = read_u16(r)?;
+        let count = read_u16(r)?;
+        let mut fields = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let mut len = [0u8; 1];
+            r.read_exact(&mut len)?;
+            let mut name = vec![0u8; len[0] as usize];
+            r.read_exact(&mut name)?;
+            let name = String::from_utf8(name).map_err(|e| invalid_data(e.to_string()))?;
+            let mut kind = [0u8; 1];
+            r.read_exact(&mut kind)?;
+            let kind = FieldKind::from_u8(kind[0])?;
+            let mut scale = [0u8; 4];
+            r.read_exact(&mut scale)?;
+            fields.push(Field {
+                name,
+                kind,
+                scale: f32::from_le_bytes(scale),
+            });
+        }
+        let metadata = if version >= 2 {
+            let mut len = [0u8; 4];
+            r.read_exact(&mut len)?;
+            let mut metadata = vec![0u8; u32::from_le_bytes(len) as usize];
+            r.read_exact(&mut metadata)?;
+            String::from_utf8(metadata).map_err(|e| invalid_data(e.to_string()))?
+        } else {
+            String::new()
+        };
+        Ok(Header {
+            version,
+            interval,
+            fields,
+            metadata,
+        })
+    }
+}
+
+// Append the fixed-point representation of `value` to `buf`.
+// Values out of range saturate.
+pub fn encode(buf: &mut Vec<u8>, kind: FieldKind, scale: f32, value: f32) {
+    let raw = (value / scale).round();
+    match kind {
+        FieldKind::U8 => buf.push(raw as u8),
+        FieldKind::I16 => buf.extend_from_slice(&(raw as i16).to_le_bytes()),
+        FieldKind::U16 => buf.extend_from_slice(&(raw as u16).to_le_bytes()),
+        FieldKind::U32 => buf.extend_from_slice(&(raw as u32).to_le_bytes()),
+    }
+}
+
+// Append an integer without scaling, e.g. time stamps and sensor values.
+// Values out of range saturate.
+pub fn encode_raw(buf: &mut Vec<u8>, kind: FieldKind, raw: i64) {
+    match kind {
+        FieldKind::U8 => buf.push(raw.clamp(0, u8::MAX as i64) as u8),
+        FieldKind::I16 => {
+            let v = raw.clamp(i16::MIN as i64, i16::MAX as i64) as i16;
+            buf.extend_from_slice(&v.to_le_bytes())
+        }
+        FieldKind::U16 => {
+            let v = raw.clamp(0, u16::MAX as i64) as u16;
+            buf.extend_from_slice(&v.to_le_bytes())
+        }
+        FieldKind::U32 => {
+            let v = raw.clamp(0, u32::MAX as i64) as u32;
+            buf.extend_from_slice(&v.to_le_bytes())
+        }
+    }
+}
+
+// Decode one field from the beginning of `bytes` and return the physical value.
+pub fn decode(bytes: &[u8], kind: FieldKind, scale: f32) -> f64 {
+    let raw = match kind {
+        FieldKind::U8 => bytes[0] as f64,
+        FieldKind::I16 => i16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+        FieldKind::U16 => u16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+        FieldKind::U32 => u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+    };
+    // Scales like 0.001 are not exact in f32, so divide by the integer
+    // reciprocal instead to get 0.045 rather than 0.04499999886320438.
+    let inverse = 1.0 / scale as f64;
+    if scale < 1.0 && (inverse - inverse.round()).abs() < 1e-3 {
+        raw / inverse.round()
+    } else {
+        raw * scale as f64
+    }
+}
+
+// Reads records of a log file one by one.
+pub struct Reader<R: Read> {
+    header: Header,
+    inner: R,
+    buffer: Vec<u8>,
+}
+
+impl<R: Read> Reader<R> {
+    pub fn new(mut inner: R) -> io::Result<Self> {
+        let header = Header::read(&mut inner)?;
+        let buffer = vec![0u8; header.record_size()];
+        Ok(Reader {
+            header,
+            inner,
+            buffer,
+        })
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    // Returns None at the end of the file. A truncated last record is ignored.
+    pub fn next_record(&mut self) -> io::Result<Option<Vec<f64>>> {
+        match self.inner.read_exact(&mut self.buffer) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let mut values = Vec::with_capacity(self.header.fields.len());
+        let mut offset = 0;
+        for field in self.header.fields.iter() {
+            values.push(decode(&self.buffer[offset..], field.kind, field.scale));
+            offset += field.kind.size();
+        }
+        Ok(Some(values))
+    }
+}
+
+fn read_u16<R: Read>(r: &mut R) -> io::Result<u16> {
+    let mut b = [0u8; 2];
+    r.read_exact(&mut b)?;
+    Ok(u16::from_le_bytes(b))
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FieldKind::*;
+    use super::*;
+
+    fn fields() -> Vec<Field> {
+        vec![
+            Field::new("time", U32, 1.0),
+            Field::new("x", I16, 0.0001),
+            Field::new("v_batt", U16, 0.001),
+            Field::new("walls", U8, 1.0),
+        ]
+    }
+
+    fn file(header: &Header, records: &[[f32; 4]]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        header.write(&mut bytes).unwrap();
+        for record in records.iter() {
+            for (field, value) in header.fields.iter().zip(record.iter()) {
+                encode(&mut bytes, field.kind, field.scale, *value);
+            }
+        }
+        bytes
+    }
+
+    #[test]
+    fn header_round_trip() {
+        let mut header = Header::new(2, fields());
+        header.metadata = r#"{"mode":"Search"}"#.to_string();
+        let mut bytes = Vec::new();
+        header.write(&mut bytes).unwrap();
+
+        let read = Header::read(&mut bytes.as_slice()).unwrap();
+        assert_eq!(read, header);
+        assert_eq!(read.record_size(), 4 + 2 + 2 + 1);
+    }
+
+    #[test]
+    fn version_1_has_no_metadata() {
+        let mut header = Header::new(1, fields());
+        header.version = 1;
+        let mut bytes = Vec::new();
+        header.write(&mut bytes).unwrap();
+        // Version 1 ends with the fields
+        bytes.truncate(bytes.len() - 4);
+
+        let read = Header::read(&mut bytes.as_slice()).unwrap();
+        assert_eq!(read.version, 1);
+        assert_eq!(read.fields, fields());
+        assert_eq!(read.metadata, "");
+    }
+
+    #[test]
+    fn broken_headers_are_refused() {
+        let mut bytes = Vec::new();
+        Header::new(1, fields()).write(&mut bytes).unwrap();
+
+        let mut bad_magic = bytes.clone();
+        bad_magic[0] = b'X';
+        assert!(Header::read(&mut bad_magic.as_slice()).is_err());
+
+        let mut newer = bytes.clone();
+        newer[4..6].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        assert!(Header::read(&mut newer.as_slice()).is_err());
+
+        // The kind of "time" follows magic, version, interval, count, name length and name
+        let mut bad_kind = bytes.clone();
+        bad_kind[4 + 2 + 2 + 2 + 1 + 4] = 9;
+        let err = Header::read(&mut bad_kind.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        assert!(Header::read(&mut &bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn records_round_trip() {
+        let header = Header::new(1, fields());
+        let records = [[1000.0, 0.045, 7.412, 5.0], [1001.0, -0.0312, 0.0, 15.0]];
+        let mut bytes = file(&header, &records);
+        // A record cut off by a reset is ignored
+        bytes.extend_from_slice(&[1, 2, 3]);
+
+        let mut reader = Reader::new(bytes.as_slice()).unwrap();
+        assert_eq!(reader.header(), &header);
+        assert_eq!(
+            reader.next_record().unwrap(),
+            Some(vec![1000.0, 0.045, 7.412, 5.0])
+        );
+        assert_eq!(
+            reader.next_record().unwrap(),
+            Some(vec![1001.0, -0.0312, 0.0, 15.0])
+        );
+        assert_eq!(reader.next_record().unwrap(), None);
+    }
+
+    #[test]
+    fn each_kind_round_trips() {
+        for (kind, scale, value) in [
+            (U8, 1.0, 200.0),
+            (I16, 0.001, -1.234),
+            (U16, 0.01, 655.35),
+            (U32, 1.0, 4_000_000_000.0),
+        ] {
+            let mut buf = Vec::new();
+            encode(&mut buf, kind, scale, value as f32);
+            assert_eq!(buf.len(), kind.size());
+            assert_eq!(decode(&buf, kind, scale), value, "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn scales_with_integer_reciprocals_are_exact() {
+        let mut buf = Vec::new();
+        encode(&mut buf, I16, 0.001, 0.045);
+        assert_eq!(decode(&buf, I16, 0.001), 0.045);
+        // Other scales are multiplied as they are
+        let mut buf = Vec::new();
+        encode(&mut buf, U16, 0.3, 0.9);
+        assert_eq!(decode(&buf, U16, 0.3), 3.0 * 0.3f32 as f64);
+        let mut buf = Vec::new();
+        encode(&mut buf, U16, 2.0, 10.0);
+        assert_eq!(decode(&buf, U16, 2.0), 10.0);
+    }
+
+    #[test]
+    fn out_of_range_values_saturate() {
+        let encoded = |kind, value: f32| {
+            let mut buf = Vec::new();
+            encode(&mut buf, kind, 1.0, value);
+            decode(&buf, kind, 1.0)
+        };
+        assert_eq!(encoded(U8, 300.0), 255.0);
+        assert_eq!(encoded(U8, -1.0), 0.0);
+        assert_eq!(encoded(I16, 40000.0), 32767.0);
+        assert_eq!(encoded(I16, -40000.0), -32768.0);
+        assert_eq!(encoded(U16, -5.0), 0.0);
+        assert_eq!(encoded(U32, -5.0), 0.0);
+
+        let raw = |kind, raw: i64| {
+            let mut buf = Vec::new();
+            encode_raw(&mut buf, kind, raw);
+            assert_eq!(buf.len(), FieldKind::size(kind));
+            decode(&buf, kind, 1.0)
+        };
+        assert_eq!(raw(U8, 256), 255.0);
+        assert_eq!(raw(I16, i64::MIN), -32768.0);
+        assert_eq!(raw(U16, 70000), 65535.0);
+        assert_eq!(raw(U32, 1 << 40), u32::MAX as f64);
+        assert_eq!(raw(U32, -1), 0.0);
+        assert_eq!(raw(I16, -123), -123.0);
+    }
+}
@@ -0,0 +1,439 @@
+// Ring buffer of text log entries in a small nonvolatile memory (FRAM).
+//
+// Layout of the region (all values are little endian):
+//   header   magic "MMRB", version u8, reserved u8, head u32, tail u32,
+//            first_seq u32, next_seq u32, mark u32, crc u16 (frame::crc of the preceding bytes)
+//   data     records between head and tail
+//
+// Record:    length u16 (of the whole record), seq u32, time u32, level u8, text (UTF-8)
+//
+// A record never straddles the end of the data area. When it does not fit, a wrap marker
+// (length 0xFFFF) is written in its place, or nothing if less than 2 bytes are left, and the
+// record starts over at the beginning. The oldest records are dropped to make room.
+// The header is written after each record, so a record is lost if the power fails in
+// between but the ring stays consistent.
+
+use crate::frame;
+use std::io;
+
+pub const MAGIC: [u8; 4] = *b"MMRB";
+pub const VERSION: u8 = 1;
+pub const HEADER_SIZE: u32 = 32;
+pub const RECORD_HEADER_SIZE: usize = 11;
+pub const MAX_TEXT: usize = 240; // Longer texts are truncated [bytes]
+const WRAP: u16 = 0xffff;
+
+// Byte addressed memory, e.g. FRAM over I2C
+pub trait Storage {
+    fn read(&mut self, address: u32, data: &mut [u8]) -> io::Result<()>;
+    fn write(&mut self, address: u32, data: &[u8]) -> io::Result<()>;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub seq: u32,
+    pub time: u32,
+    pub level: u8,
+    pub text: String,
+}
+
+pub struct Ring<S: Storage> {
+    storage: S,
+    base: u32,     // Address of the header
+    capacity: u32, // Size of the data area [bytes]
+    head: u32,     // Offset of the oldest record in the data area
+    tail: u32,     // Offset of the next record
+    first_seq: u32,
+    next_seq: u32,
+    mark: u32, // Sequence number kept for the user, e.g. the first entry not copied yet
+}
+
+impl<S: Storage> Ring<S> {
+    // Use `size` bytes from `base`. The ring is formatted if the header is not valid.
+    pub fn open(storage: S, base: u32, size: u32) -> io::Result<Self> {
+        if size < HEADER_SIZE + (RECORD_HEADER_SIZE + MAX_TEXT) as u32 * 2 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The ring buffer is too small",
+            ));
+        }
+        let mut ring = Ring {
+            storage,
+            base,
+            capacity: size - HEADER_SIZE,
+            head: 0,
+            tail: 0,
+            first_seq: 0,
+            next_seq: 0,
+            mark: 0,
+        };
+        if !ring.load_header()? {
+            ring.write_header()?;
+        }
+        Ok(ring)
+    }
+
+    pub fn len(&self) -> u32 {
+        self.next_seq.wrapping_sub(self.first_seq)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Sequence numbers of the oldest entry and of the next entry
+    pub fn seq_range(&self) -> (u32, u32) {
+        (self.first_seq, self.next_seq)
+    }
+
+    pub fn mark(&self) -> u32 {
+        self.mark
+    }
+
+    pub fn set_mark(&mut self, mark: u32) -> io::Result<()> {
+        self.mark = mark;
+        self.write_header()
+    }
+
+    pub fn storage_mut(&mut self) -> &mut S {
+        &mut self.storage
+    }
+
+    // Drop all entries. The sequence numbers continue.
+    pub fn clear(&mut self) -> io::Result<()> {
+        self.head = 0;
+        self.tail = 0;
+        self.first_seq = self.next_seq;
+        self.write_header()
+    }
+
+    // Append an entry and return its sequence number
+    pub fn push(&mut self, time: u32, level: u8, text: &str) -> io::Result<u32> {
+        let mut len = text.len().min(MAX_TEXT);
+        while !text.is_char_boundary(len) {
+            len -= 1;
+        }
+        let seq = self.next_seq;
+        let mut record = Vec::with_capacity(RECORD_HEADER_SIZE + len);
+        record.extend_from_slice(&((RECORD_HEADER_SIZE + len) as u16).to_le_bytes());
+        record.extend_from_slice(&seq.to_le_bytes());
+        record.extend_from_slice(&time.to_le_bytes());
+        record.push(level);
+        record.extend_from_slice(&text.as_bytes()[..len]);
+        let n = record.len() as u32;
+
+        if self.tail + n > self.capacity {
+            // Drop the records after the tail and start over at the beginning
+            while !self.is_empty() && self.head >= self.tail {
+                self.drop_oldest()?;
+            }
+            if self.capacity - self.tail >= 2 {
+                self.write_data(self.tail, &WRAP.to_le_bytes())?;
+            }
+            self.tail = 0;
+            if self.is_empty() {
+                self.head = 0;
+            }
+        }
+        while !self.is_empty() && self.head >= self.tail && self.head < self.tail + n {
+            self.drop_oldest()?;
+        }
+        if self.is_empty() {
+            self.head = self.tail;
+        }
+
+        self.write_data(self.tail, &record)?;
+        self.tail += n;
+        self.next_seq = seq.wrapping_add(1);
+        self.write_header()?;
+        Ok(seq)
+    }
+
+    // All entries, oldest first
+    pub fn entries(&mut self) -> io::Result<Vec<Entry>> {
+        let mut entries = Vec::with_capacity(self.len() as usize);
+        let mut pos = self.head;
+        for _ in 0..self.len() {
+            pos = self.skip_wrap(pos)?;
+            let mut header = [0; RECORD_HEADER_SIZE];
+            self.read_data(pos, &mut header)?;
+            let len = u16::from_le_bytes([header[0], header[1]]) as usize;
+            if len < RECORD_HEADER_SIZE || pos + len as u32 > self.capacity {
+                return Err(invalid_data("Broken record in the ring buffer"));
+            }
+            let mut text = vec![0; len - RECORD_HEADER_SIZE];
+            self.read_data(pos + RECORD_HEADER_SIZE as u32, &mut text)?;
+            entries.push(Entry {
+                seq: u32::from_le_bytes(header[2..6].try_into().unwrap()),
+                time: u32::from_le_bytes(header[6..10].try_into().unwrap()),
+                level: header[10],
+                text: String::from_utf8_lossy(&text).into_owned(),
+            });
+            pos += len as u32;
+        }
+        Ok(entries)
+    }
+
+    // The position of the record at `pos`, which is 0 after a wrap marker
+    fn skip_wrap(&mut self, pos: u32) -> io::Result<u32> {
+        if self.capacity - pos < 2 {
+            return Ok(0);
+        }
+        let mut len = [0; 2];
+        self.read_data(pos, &mut len)?;
+        Ok(if u16::from_le_bytes(len) == WRAP {
+            0
+        } else {
+            pos
+        })
+    }
+
+    fn drop_oldest(&mut self) -> io::Result<()> {
+        self.head = self.skip_wrap(self.head)?;
+        let mut len = [0; 2];
+        self.read_data(self.head, &mut len)?;
+        let len = u16::from_le_bytes(len) as u32;
+        if len < RECORD_HEADER_SIZE as u32 || self.head + len > self.capacity {
+            return Err(invalid_data("Broken record in the ring buffer"));
+        }
+        self.first_seq = self.first_seq.wrapping_add(1);
+        self.head = if self.is_empty() {
+            self.tail
+        } else {
+            self.skip_wrap(self.head + len)?
+        };
+        Ok(())
+    }
+
+    fn read_data(&mut self, offset: u32, data: &mut [u8]) -> io::Result<()> {
+        self.storage.read(self.base + HEADER_SIZE + offset, data)
+    }
+
+    fn write_data(&mut self, offset: u32, data: &[u8]) -> io::Result<()> {
+        self.storage.write(self.base + HEADER_SIZE + offset, data)
+    }
+
+    // Returns false if there is no valid header
+    fn load_header(&mut self) -> io::Result<bool> {
+        let mut b = [0; HEADER_SIZE as usize];
+        self.storage.read(self.base, &mut b)?;
+        let word = |i: usize| u32::from_le_bytes(b[i..i + 4].try_into().unwrap());
+        let crc = u16::from_le_bytes([b[26], b[27]]);
+        if b[..4] != MAGIC || b[4] != VERSION || crc != frame::crc(&b[..26]) {
+            return Ok(false);
+        }
+        let (head, tail) = (word(6), word(10));
+        if head > self.capacity || tail > self.capacity {
+            return Ok(false);
+        }
+        self.head = head;
+        self.tail = tail;
+        self.first_seq = word(14);
+        self.next_seq = word(18);
+        self.mark = word(22);
+        Ok(true)
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        let mut b = [0; HEADER_SIZE as usize];
+        b[..4].copy_from_slice(&MAGIC);
+        b[4] = VERSION;
+        for (i, v) in [
+            self.head,
+            self.tail,
+            self.first_seq,
+            self.next_seq,
+            self.mark,
+        ]
+        .iter()
+        .enumerate()
+        {
+            b[6 + i * 4..10 + i * 4].copy_from_slice(&v.to_le_bytes());
+        }
+        let crc = frame::crc(&b[..26]);
+        b[26..28].copy_from_slice(&crc.to_le_bytes());
+        self.storage.write(self.base, &b)
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: u32 = 0x100;
+    const SIZE: u32 = 1024;
+
+    // FRAM in memory. Writes outside the region of the ring fail the test.
+    struct Memory {
+        bytes: Vec<u8>,
+        writes: usize,
+    }
+
+    impl Memory {
+        fn new() -> Self {
+            Memory {
+                bytes: vec![0; (BASE + SIZE + 0x100) as usize],
+                writes: 0,
+            }
+        }
+    }
+
+    impl Storage for Memory {
+        fn read(&mut self, address: u32, data: &mut [u8]) -> io::Result<()> {
+            let a = address as usize;
+            data.copy_from_slice(&self.bytes[a..a + data.len()]);
+            Ok(())
+        }
+
+        fn write(&mut self, address: u32, data: &[u8]) -> io::Result<()> {
+            assert!(address >= BASE && address + data.len() as u32 <= BASE + SIZE);
+            let a = address as usize;
+            self.bytes[a..a + data.len()].copy_from_slice(data);
+            self.writes += 1;
+            Ok(())
+        }
+    }
+
+    fn texts(ring: &mut Ring<Memory>) -> Vec<String> {
+        ring.entries()
+            .unwrap()
+            .into_iter()
+            .map(|e| e.text)
+            .collect()
+    }
+
+    #[test]
+    fn blank_memory_is_formatted() {
+        let mut ring = Ring::open(Memory::new(), BASE, SIZE).unwrap();
+        assert!(ring.is_empty());
+        assert!(ring.entries().unwrap().is_empty());
+        assert_eq!(
+            &ring.storage_mut().bytes[BASE as usize..BASE as usize + 4],
+            b"MMRB"
+        );
+    }
+
+    #[test]
+    fn entries_are_read_oldest_first() {
+        let mut ring = Ring::open(Memory::new(), BASE, SIZE).unwrap();
+        assert_eq!(ring.push(10, 3, "first").unwrap(), 0);
+        assert_eq!(ring.push(20, 1, "second").unwrap(), 1);
+        let entries = ring.entries().unwrap();
+        assert_eq!(
+            entries,
+            vec![
+                Entry {
+                    seq: 0,
+                    time: 10,
+                    level: 3,
+                    text: "first".to_string()
+                },
+                Entry {
+                    seq: 1,
+                    time: 20,
+                    level: 1,
+                    text: "second".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn oldest_entries_are_dropped_when_full() {
+        let mut ring = Ring::open(Memory::new(), BASE, SIZE).unwrap();
+        for i in 0..500 {
+            ring.push(i, 3, &format!("entry {}", i)).unwrap();
+        }
+        let entries = ring.entries().unwrap();
+        // Records are 20 bytes, at most one is lost at the wrap point
+        assert!(entries.len() as u32 >= (SIZE - HEADER_SIZE) / 20 - 1);
+        assert_eq!(entries.last().unwrap().seq, 499);
+        for (i, e) in entries.iter().enumerate() {
+            let seq = 500 - entries.len() as u32 + i as u32;
+            assert_eq!(e.seq, seq);
+            assert_eq!(e.text, format!("entry {}", seq));
+        }
+        assert_eq!(ring.seq_range(), (500 - entries.len() as u32, 500));
+    }
+
+    #[test]
+    fn records_of_every_size_wrap() {
+        // Cover every distance of the tail from the end of the data area
+        let mut memory = Memory::new();
+        let mut expected = Vec::new();
+        let mut max_len = 0;
+        for i in 0..2000 {
+            // Reopen each time to check the saved header
+            let mut ring = Ring::open(memory, BASE, SIZE).unwrap();
+            let text = "x".repeat(i % 97);
+            ring.push(i as u32, 3, &text).unwrap();
+            expected.push(text);
+            let texts = texts(&mut ring);
+            assert_eq!(texts[..], expected[expected.len() - texts.len()..]);
+            max_len = max_len.max(texts.len());
+            memory = ring.storage;
+        }
+        assert!(max_len > 20);
+    }
+
+    #[test]
+    fn entries_persist_across_open() {
+        let mut ring = Ring::open(Memory::new(), BASE, SIZE).unwrap();
+        for i in 0..100 {
+            ring.push(i, 3, &format!("boot 1 entry {}", i)).unwrap();
+        }
+        ring.set_mark(42).unwrap();
+        let before = ring.entries().unwrap();
+
+        let mut ring = Ring::open(ring.storage, BASE, SIZE).unwrap();
+        assert_eq!(ring.entries().unwrap(), before);
+        assert_eq!(ring.mark(), 42);
+        assert_eq!(ring.push(0, 3, "boot 2").unwrap(), 100);
+        assert_eq!(texts(&mut ring).last().unwrap(), "boot 2");
+    }
+
+    #[test]
+    fn broken_header_is_formatted() {
+        let mut ring = Ring::open(Memory::new(), BASE, SIZE).unwrap();
+        ring.push(0, 3, "entry").unwrap();
+        let mut memory = ring.storage;
+        memory.bytes[BASE as usize + 8] ^= 0x55;
+
+        let mut ring = Ring::open(memory, BASE, SIZE).unwrap();
+        assert!(ring.is_empty());
+        assert_eq!(ring.push(0, 3, "new").unwrap(), 0);
+        assert_eq!(texts(&mut ring), vec!["new"]);
+    }
+
+    #[test]
+    fn long_text_is_truncated_at_a_char_boundary() {
+        let mut ring = Ring::open(Memory::new(), BASE, SIZE).unwrap();
+        let text = "é".repeat(MAX_TEXT);
+        ring.push(0, 3, &text).unwrap();
+        let stored = texts(&mut ring).remove(0);
+        assert_eq!(stored, "é".repeat(MAX_TEXT / 2));
+    }
+
+    #[test]
+    fn clear_keeps_the_sequence() {
+        let mut ring = Ring::open(Memory::new(), BASE, SIZE).unwrap();
+        ring.push(0, 3, "a").unwrap();
+        ring.push(0, 3, "b").unwrap();
+        ring.clear().unwrap();
+        assert!(ring.entries().unwrap().is_empty());
+        assert_eq!(ring.push(0, 3, "c").unwrap(), 2);
+        assert_eq!(texts(&mut ring), vec!["c"]);
+    }
+
+    #[test]
+    fn each_push_writes_the_record_and_the_header() {
+        let mut ring = Ring::open(Memory::new(), BASE, SIZE).unwrap();
+        let writes = ring.storage_mut().writes;
+        ring.push(0, 3, "entry").unwrap();
+        assert_eq!(ring.storage_mut().writes - writes, 2);
+    }
+}
@@ -0,0 +1,416 @@
+// File transfer over the console UART, used by `ft` (upload) and `dl` (download).
+//
+// The packets of `frame` are sent with their own kinds, one at a time (stop and wait):
+//   seq 0          START  size u32
+//   seq 1..=n      DATA   up to CHUNK_SIZE bytes
+//   seq n + 1      END    size u32, crc u16 (frame::crc of the whole file)
+//
+// The receiver answers a valid packet with ACK (payload empty, seq of the packet), also when
+// it is a duplicate because an ACK was lost, and a broken frame with NAK (seq expected next).
+// The sender sends a packet again on a NAK of its seq or when no ACK comes in ACK_TIMEOUT.
+// Either side may give up with ABORT (payload is the reason in UTF-8).
+//
+// Each frame is preceded by a delimiter, so text before it, e.g. the echo of the command
+// line, ends up in a broken frame of its own and is ignored.
+
+use crate::frame::{self, FrameError, FrameReader, Packet, DELIMITER};
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+
+pub const KIND_START: u8 = 0x10;
+pub const KIND_DATA: u8 = 0x11;
+pub const KIND_END: u8 = 0x12;
+pub const KIND_ACK: u8 = 0x13;
+pub const KIND_NAK: u8 = 0x14;
+pub const KIND_ABORT: u8 = 0x15;
+
+pub const CHUNK_SIZE: usize = 256;
+pub const ACK_TIMEOUT: u32 = 500; // [ms]
+pub const LINGER: u32 = 2 * ACK_TIMEOUT; // The receiver answers a repeated END until quiet [ms]
+pub const RETRIES: u32 = 10; // Sending a packet again
+pub const IDLE_TIMEOUT: u32 = 10_000; // The receiver gives up when nothing comes [ms]
+
+// Byte stream to the other side, e.g. a UART
+pub trait Link {
+    fn write(&mut self, data: &[u8]) -> io::Result<()>;
+    // Wait up to `timeout` [ms] for at least a byte. Returns 0 on timeout.
+    fn read(&mut self, data: &mut [u8], timeout: u32) -> io::Result<usize>;
+}
+
+struct Channel<'a, L: Link> {
+    link: &'a mut L,
+    reader: FrameReader,
+    buffer: [u8; 64],
+    pos: usize,
+    len: usize,
+}
+
+impl<'a, L: Link> Channel<'a, L> {
+    fn new(link: &'a mut L) -> Self {
+        Channel {
+            link,
+            reader: FrameReader::new(),
+            buffer: [0; 64],
+            pos: 0,
+            len: 0,
+        }
+    }
+
+    fn send(&mut self, kind: u8, seq: u16, payload: &[u8]) -> io::Result<()> {
+        let mut out = vec![DELIMITER];
+        frame::encode_packet(kind, seq, payload, &mut out);
+        self.link.write(&out)
+    }
+
+    // The next frame, or None if none is complete in `timeout` [ms]
+    fn receive(&mut self, timeout: u32) -> io::Result<Option<Result<Packet, FrameError>>> {
+        let deadline = Instant::now() + Duration::from_millis(timeout as u64);
+        loop {
+            while self.pos < self.len {
+                let b = self.buffer[self.pos];
+                self.pos += 1;
+                if let Some(f) = self.reader.push(b) {
+                    return Ok(Some(frame::decode_packet(&f)));
+                }
+            }
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Ok(None);
+            }
+            self.len = self
+                .link
+                .read(&mut self.buffer, left.as_millis().max(1) as u32)?;
+            self.pos = 0;
+        }
+    }
+}
+
+// Send `size` bytes from `input`. On an error the receiver is told to abort.
+pub fn send<L: Link, R: Read>(link: &mut L, input: &mut R, size: u32) -> io::Result<()> {
+    let mut channel = Channel::new(link);
+    let result = send_packets(&mut channel, input, size);
+    if let Err(e) = &result {
+        if e.kind() != io::ErrorKind::ConnectionAborted {
+            let _ = channel.send(KIND_ABORT, 0, e.to_string().as_bytes());
+        }
+    }
+    result
+}
+
+// Receive a file into `output` and return its size. On an error the sender is told to abort.
+pub fn receive<L: Link, W: Write>(link: &mut L, output: &mut W) -> io::Result<u32> {
+    let mut channel = Channel::new(link);
+    let result = receive_packets(&mut channel, output);
+    if let Err(e) = &result {
+        if e.kind() != io::ErrorKind::ConnectionAborted {
+            let _ = channel.send(KIND_ABORT, 0, e.to_string().as_bytes());
+        }
+    }
+    result
+}
+
+// Refuse a transfer before it starts, e.g. when the file is not found
+pub fn abort<L: Link>(link: &mut L, reason: &str) -> io::Result<()> {
+    Channel::new(link).send(KIND_ABORT, 0, reason.as_bytes())
+}
+
+fn send_packets<L: Link, R: Read>(
+    channel: &mut Channel<L>,
+    input: &mut R,
+    size: u32,
+) -> io::Result<()> {
+    send_reliably(channel, KIND_START, 0, &size.to_le_bytes())?;
+
+    let mut crc = crc16::State::<crc16::XMODEM>::new();
+    let mut chunk = [0u8; CHUNK_SIZE];
+    let mut sent = 0u32;
+    let mut seq = 1u16;
+    while sent < size {
+        let len = ((size - sent) as usize).min(CHUNK_SIZE);
+        input.read_exact(&mut chunk[..len])?;
+        crc.update(&chunk[..len]);
+        send_reliably(channel, KIND_DATA, seq, &chunk[..len])?;
+        sent += len as u32;
+        seq = seq.wrapping_add(1);
+    }
+
+    let mut end = size.to_le_bytes().to_vec();
+    end.extend_from_slice(&crc.get().to_le_bytes());
+    send_reliably(channel, KIND_END, seq, &end)
+}
+
+fn send_reliably<L: Link>(
+    channel: &mut Channel<L>,
+    kind: u8,
+    seq: u16,
+    payload: &[u8],
+) -> io::Result<()> {
+    for _ in 0..=RETRIES {
+        channel.send(kind, seq, payload)?;
+        let deadline = Instant::now() + Duration::from_millis(ACK_TIMEOUT as u64);
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            let packet = match channel.receive(left.as_millis() as u32)? {
+                None => break, // Timeout
+                Some(Err(_)) => continue,
+                Some(Ok(p)) => p,
+            };
+            match packet.kind {
+                KIND_ACK if packet.seq == seq => return Ok(()),
+                KIND_NAK if packet.seq == seq => break,
+                KIND_ABORT => return Err(aborted(&packet)),
+                _ => {}
+            }
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::TimedOut,
+        format!("No ACK for packet {}", seq),
+    ))
+}
+
+fn receive_packets<L: Link, W: Write>(channel: &mut Channel<L>, output: &mut W) -> io::Result<u32> {
+    let mut crc = crc16::State::<crc16::XMODEM>::new();
+    let mut size = 0u32;
+    let mut received = 0u32;
+    let mut expected = 0u16;
+
+    loop {
+        let packet = match channel.receive(IDLE_TIMEOUT)? {
+            None => return Err(io::Error::new(io::ErrorKind::TimedOut, "Nothing received")),
+            Some(Err(_)) => {
+                channel.send(KIND_NAK, expected, &[])?;
+                continue;
+            }
+            Some(Ok(p)) => p,
+        };
+        if packet.kind == KIND_ABORT {
+            return Err(aborted(&packet));
+        }
+        if packet.seq != expected {
+            if expected != 0 && packet.seq == expected.wrapping_sub(1) {
+                channel.send(KIND_ACK, packet.seq, &[])?; // Our ACK was lost
+            } else {
+                channel.send(KIND_NAK, expected, &[])?;
+            }
+            continue;
+        }
+
+        match (packet.kind, expected) {
+            (KIND_START, 0) => {
+                size = read_u32(&packet.payload)?;
+            }
+            (KIND_DATA, e) if e != 0 => {
+                if received as usize + packet.payload.len() > size as usize {
+                    return Err(invalid_data("More data than the size".to_string()));
+                }
+                output.write_all(&packet.payload)?;
+                crc.update(&packet.payload);
+                received += packet.payload.len() as u32;
+            }
+            (KIND_END, e) if e != 0 => {
+                let end_size = read_u32(&packet.payload)?;
+                let end_crc = match packet.payload.get(4..6) {
+                    Some(b) => u16::from_le_bytes([b[0], b[1]]),
+                    None => return Err(invalid_data("Short END packet".to_string())),
+                };
+                if end_size != size || received != size {
+                    return Err(invalid_data(format!(
+                        "Size mismatch: {} bytes received, {} expected",
+                        received, end_size
+                    )));
+                }
+                if end_crc != crc.get() {
+                    return Err(invalid_data(format!(
+                        "CRC mismatch: {:04X} received, {:04X} expected",
+                        crc.get(),
+                        end_crc
+                    )));
+                }
+                output.flush()?;
+                channel.send(KIND_ACK, packet.seq, &[])?;
+                linger(channel, packet.seq);
+                return Ok(size);
+            }
+            _ => {
+                channel.send(KIND_NAK, expected, &[])?;
+                continue;
+            }
+        }
+        channel.send(KIND_ACK, packet.seq, &[])?;
+        expected = expected.wrapping_add(1);
+    }
+}
+
+// Answer END again until the line is quiet, in case the last ACK was lost.
+// The file is complete, so errors of the line do not matter any more.
+// LINGER is longer than ACK_TIMEOUT, after which the sender sends END again.
+fn linger<L: Link>(channel: &mut Channel<L>, seq: u16) {
+    while let Ok(Some(packet)) = channel.receive(LINGER) {
+        if let Ok(p) = packet {
+            if p.kind == KIND_END && p.seq == seq {
+                let _ = channel.send(KIND_ACK, seq, &[]);
+            }
+        }
+    }
+}
+
+fn read_u32(payload: &[u8]) -> io::Result<u32> {
+    match payload.get(0..4) {
+        Some(b) => Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+        None => Err(invalid_data("Short packet".to_string())),
+    }
+}
+
+fn aborted(packet: &Packet) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::ConnectionAborted,
+        format!("Aborted: {}", String::from_utf8_lossy(&packet.payload)),
+    )
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serialport::{SerialPort, TTYPort};
+    use std::thread;
+
+    // Both ends of the transfer run on a pseudo terminal pair
+    impl Link for TTYPort {
+        fn write(&mut self, data: &[u8]) -> io::Result<()> {
+            Write::write_all(self, data)
+        }
+
+        fn read(&mut self, data: &mut [u8], timeout: u32) -> io::Result<usize> {
+            self.set_timeout(Duration::from_millis(timeout as u64))?;
+            match Read::read(self, data) {
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => Ok(0),
+                result => result,
+            }
+        }
+    }
+
+    // Corrupts the frames written with the given numbers
+    struct Lossy {
+        port: TTYPort,
+        frames: usize,
+        corrupt: Vec<usize>,
+    }
+
+    impl Link for Lossy {
+        fn write(&mut self, data: &[u8]) -> io::Result<()> {
+            self.frames += 1;
+            if self.corrupt.contains(&self.frames) {
+                let mut data = data.to_vec();
+                let i = data.len() / 2;
+                data[i] ^= 0x5a;
+                return Link::write(&mut self.port, &data);
+            }
+            Link::write(&mut self.port, data)
+        }
+
+        fn read(&mut self, data: &mut [u8], timeout: u32) -> io::Result<usize> {
+            Link::read(&mut self.port, data, timeout)
+        }
+    }
+
+    fn file(size: usize) -> Vec<u8> {
+        (0..size).map(|i| (i * 7 + i / 256) as u8).collect()
+    }
+
+    fn transfer<S: Link + Send + 'static, R: Link + Send + 'static>(
+        mut sender: S,
+        mut receiver: R,
+        data: Vec<u8>,
+    ) -> (io::Result<()>, io::Result<Vec<u8>>) {
+        let size = data.len() as u32;
+        let handle = thread::spawn(move || send(&mut sender, &mut data.as_slice(), size));
+        let mut output = Vec::new();
+        let received = receive(&mut receiver, &mut output).map(|_| output);
+        (handle.join().unwrap(), received)
+    }
+
+    #[test]
+    fn transfer_files_of_various_sizes() {
+        for size in [0, 1, CHUNK_SIZE - 1, CHUNK_SIZE, CHUNK_SIZE * 3 + 17] {
+            let (a, b) = TTYPort::pair().unwrap();
+            let (sent, received) = transfer(a, b, file(size));
+            sent.unwrap();
+            assert_eq!(received.unwrap(), file(size), "size {}", size);
+        }
+    }
+
+    #[test]
+    fn ignore_text_before_the_first_frame() {
+        let (a, mut b) = TTYPort::pair().unwrap();
+        let mut a = a;
+        Link::write(&mut a, b"dl /sf/test.bin\r\n").unwrap();
+        let handle = thread::spawn(move || {
+            let data = file(1000);
+            send(&mut a, &mut data.as_slice(), 1000)
+        });
+        let mut output = Vec::new();
+        receive(&mut b, &mut output).unwrap();
+        handle.join().unwrap().unwrap();
+        assert_eq!(output, file(1000));
+    }
+
+    #[test]
+    fn retransmit_corrupted_data_and_acks() {
+        let (a, b) = TTYPort::pair().unwrap();
+        let sender = Lossy {
+            port: a,
+            frames: 0,
+            corrupt: vec![1, 3, 4, 7],
+        };
+        let receiver = Lossy {
+            port: b,
+            frames: 0,
+            corrupt: vec![2, 5],
+        };
+        let (sent, received) = transfer(sender, receiver, file(CHUNK_SIZE * 5));
+        sent.unwrap();
+        assert_eq!(received.unwrap(), file(CHUNK_SIZE * 5));
+    }
+
+    #[test]
+    fn lost_last_ack_is_answered_again() {
+        let (a, b) = TTYPort::pair().unwrap();
+        // Frames of the receiver: ACK 0, ACK 1, ACK 2 (END)
+        let receiver = Lossy {
+            port: b,
+            frames: 0,
+            corrupt: vec![3],
+        };
+        let (sent, received) = transfer(a, receiver, file(100));
+        sent.unwrap();
+        assert_eq!(received.unwrap(), file(100));
+    }
+
+    #[test]
+    fn abort_is_reported_to_the_other_side() {
+        let (mut a, mut b) = TTYPort::pair().unwrap();
+        abort(&mut a, "File not found").unwrap();
+        let err = receive(&mut b, &mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionAborted);
+        assert!(err.to_string().contains("File not found"));
+    }
+
+    #[test]
+    fn short_input_aborts_the_receiver() {
+        let (mut a, mut b) = TTYPort::pair().unwrap();
+        let handle = thread::spawn(move || {
+            let data = file(100);
+            // Keep the port open until the receiver has read the ABORT
+            (send(&mut a, &mut data.as_slice(), 200), a)
+        });
+        let err = receive(&mut b, &mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionAborted);
+        let (sent, _) = handle.join().unwrap();
+        assert_eq!(sent.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
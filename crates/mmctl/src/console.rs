@@ -0,0 +1,116 @@
+// Commands on the console in its JSON mode ("mode json").
+//
+// Each command is answered by a line with a JSON object followed by PROMPT:
+//   {"status":"ok","command":"ls","error":null,"output":["..."],"data":...}
+
+use crate::serial::Port;
+use mm_log::transfer::Link;
+use std::time::{Duration, Instant};
+
+pub const PROMPT: &str = "@> ";
+pub const TIMEOUT: u32 = 10_000; // Of a command [ms]
+
+pub struct Reply {
+    pub json: serde_json::Value,
+}
+
+impl Reply {
+    pub fn output(&self) -> Vec<&str> {
+        match self.json["output"].as_array() {
+            Some(lines) => lines.iter().filter_map(|l| l.as_str()).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn data(&self) -> &serde_json::Value {
+        &self.json["data"]
+    }
+
+    // Err with the message of the console if the command failed
+    pub fn check(&self) -> anyhow::Result<()> {
+        match self.json["status"].as_str() {
+            Some("ok") => Ok(()),
+            _ => Err(anyhow::anyhow!(
+                "{}",
+                self.json["error"].as_str().unwrap_or("Unknown error")
+            )),
+        }
+    }
+}
+
+pub struct Session<'a> {
+    port: &'a mut Port,
+}
+
+impl<'a> Session<'a> {
+    // Switch the console to JSON mode
+    pub fn start(port: &'a mut Port) -> anyhow::Result<Self> {
+        port.refresh()?;
+        port.write_line("mode json")?;
+        read_until_prompt(port, TIMEOUT)?;
+        Ok(Session { port })
+    }
+
+    pub fn run(&mut self, line: &str) -> anyhow::Result<Reply> {
+        self.port.write_line(line)?;
+        let text = read_until_prompt(self.port, TIMEOUT)?;
+        // The reply is the last line, after the output of a command streaming to the UART
+        let json = text
+            .lines()
+            .rev()
+            .find(|l| l.starts_with('{'))
+            .ok_or_else(|| anyhow::anyhow!("No reply to '{}'", line))?;
+        Ok(Reply {
+            json: serde_json::from_str(json)?,
+        })
+    }
+
+    // Back to the text mode for a person at the terminal
+    pub fn end(self) -> anyhow::Result<()> {
+        self.port.write_line("mode text")?;
+        self.port.drain(100)?;
+        Ok(())
+    }
+}
+
+// Text received before PROMPT
+fn read_until_prompt(port: &mut Port, timeout: u32) -> anyhow::Result<String> {
+    let deadline = Instant::now() + Duration::from_millis(timeout as u64);
+    let mut text = Vec::new();
+    let mut buffer = [0u8; 256];
+    while !text.ends_with(PROMPT.as_bytes()) {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(anyhow::anyhow!("No prompt from the console"));
+        }
+        let size = Link::read(port, &mut buffer, left.as_millis().max(1) as u32)?;
+        text.extend_from_slice(&buffer[..size]);
+    }
+    text.truncate(text.len() - PROMPT.len());
+    Ok(String::from_utf8_lossy(&text).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::Sim;
+
+    #[test]
+    fn run_commands_in_json_mode() {
+        let (mut port, sim) = Sim::start();
+        let mut session = Session::start(&mut port).unwrap();
+
+        let reply = session.run("echo a \"b c\"").unwrap();
+        reply.check().unwrap();
+        assert_eq!(reply.output(), ["a", "b c"]);
+
+        let reply = session.run("foo").unwrap();
+        let err = reply.check().unwrap_err();
+        assert_eq!(err.to_string(), "Command not found: 'foo'");
+
+        session.end().unwrap();
+        let commands = sim.commands.lock().unwrap();
+        assert_eq!(commands.first().unwrap(), "mode json");
+        assert_eq!(commands.last().unwrap(), "mode text");
+    }
+}
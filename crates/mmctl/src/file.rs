@@ -0,0 +1,187 @@
+// Files on the micromouse: upload and download with the `ft` and `dl` console commands,
+// configs and logs.
+
+use crate::console::Session;
+use crate::serial::{quote, Port};
+use mm_log::transfer;
+use std::io;
+use std::path::Path;
+
+pub const CONFIGS: [&str; 2] = ["ctrl_cfg.json", "ope_cfg.json"];
+
+pub fn put(port: &mut Port, data: &[u8], remote: &str) -> anyhow::Result<()> {
+    port.refresh()?;
+    port.write_line(&format!("ft {}", quote(remote)))?;
+    transfer::send(port, &mut &data[..], data.len() as u32)?;
+    // The console answers a lost last ACK for LINGER before it prints the prompt
+    port.drain(transfer::LINGER + 100)?;
+    Ok(())
+}
+
+// None if the console refused, e.g. because the file does not exist
+pub fn get(port: &mut Port, remote: &str) -> anyhow::Result<Option<Vec<u8>>> {
+    port.refresh()?;
+    port.write_line(&format!("dl {}", quote(remote)))?;
+    let mut data = Vec::new();
+    let result = transfer::receive(port, &mut data);
+    port.drain(100)?;
+    match result {
+        Ok(_) => Ok(Some(data)),
+        Err(e) if e.kind() == io::ErrorKind::ConnectionAborted => {
+            eprintln!("{}: {}", remote, e);
+            Ok(None)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub fn upload(port: &mut Port, local: &str, remote: &str) -> anyhow::Result<()> {
+    let data = std::fs::read(local).map_err(|e| anyhow::anyhow!("{}: {}", local, e))?;
+    put(port, &data, remote)?;
+    eprintln!("{} -> {} ({} bytes)", local, remote, data.len());
+    Ok(())
+}
+
+pub fn download(port: &mut Port, remote: &str, local: &str) -> anyhow::Result<()> {
+    let data = get(port, remote)?.ok_or_else(|| anyhow::anyhow!("Download failed"))?;
+    save(local, &data)?;
+    eprintln!("{} -> {} ({} bytes)", remote, local, data.len());
+    Ok(())
+}
+
+// Upload the configs in `dir` that differ from the ones on the micromouse.
+// Returns the number of uploaded files.
+pub fn sync(port: &mut Port, dir: &str) -> anyhow::Result<usize> {
+    let mut uploaded = 0;
+    for name in CONFIGS {
+        let local = Path::new(dir).join(name);
+        let data = match std::fs::read(&local) {
+            Ok(data) => data,
+            Err(_) => {
+                eprintln!("{}: not found, skipped", local.display());
+                continue;
+            }
+        };
+        let remote = format!("/sf/{}", name);
+        if get(port, &remote)?.as_deref() == Some(&data[..]) {
+            eprintln!("{}: up to date", remote);
+            continue;
+        }
+        put(port, &data, &remote)?;
+        eprintln!("{} -> {} ({} bytes)", local.display(), remote, data.len());
+        uploaded += 1;
+    }
+    Ok(uploaded)
+}
+
+// Download the latest `count` run logs with their events, and the latest text log
+pub fn logs(port: &mut Port, count: usize, dir: &str) -> anyhow::Result<()> {
+    let mut session = Session::start(port)?;
+    let reply = session.run("runs")?;
+    session.end()?;
+    reply.check()?;
+
+    let mut paths: Vec<String> = Vec::new();
+    for run in reply.data().as_array().into_iter().flatten().take(count) {
+        if let Some(path) = run["path"].as_str() {
+            paths.push(path.to_string());
+            paths.push(path.replace(".bin", ".evt"));
+        }
+    }
+    paths.push("/sf/log00.txt".to_string());
+
+    for remote in paths.iter() {
+        if let Some(data) = get(port, remote)? {
+            let local = Path::new(dir).join(remote.rsplit('/').next().unwrap_or(remote));
+            save(&local.to_string_lossy(), &data)?;
+            eprintln!("{} -> {} ({} bytes)", remote, local.display(), data.len());
+        }
+    }
+    Ok(())
+}
+
+fn save(local: &str, data: &[u8]) -> anyhow::Result<()> {
+    if let Some(dir) = Path::new(local).parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(local, data).map_err(|e| anyhow::anyhow!("{}: {}", local, e))
+}
+
+// "/sf/" + the file name of `local`
+pub fn remote_path(local: &str) -> String {
+    let name = Path::new(local)
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    format!("/sf/{}", name)
+}
+
+// "downloads/" + the file name of `remote`
+pub fn local_path(remote: &str) -> String {
+    let name = remote.rsplit('/').next().unwrap_or(remote);
+    format!("downloads/{}", name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::Sim;
+
+    fn temp_dir(name: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("mmctl-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn put_and_get() {
+        let (mut port, sim) = Sim::start();
+        let data: Vec<u8> = (0..1000).map(|i| i as u8).collect();
+        put(&mut port, &data, "/sf/a file.bin").unwrap();
+        assert_eq!(sim.file("/sf/a file.bin").unwrap(), data);
+        assert_eq!(get(&mut port, "/sf/a file.bin").unwrap().unwrap(), data);
+        assert_eq!(get(&mut port, "/sf/missing").unwrap(), None);
+    }
+
+    #[test]
+    fn sync_uploads_changed_configs() {
+        let dir = temp_dir("sync");
+        std::fs::write(Path::new(&dir).join("ctrl_cfg.json"), "{\"a\": 1}").unwrap();
+        std::fs::write(Path::new(&dir).join("ope_cfg.json"), "{\"b\": 2}").unwrap();
+        let (mut port, sim) = Sim::start();
+        sim.add_file("/sf/ctrl_cfg.json", b"{\"a\": 1}");
+        sim.add_file("/sf/ope_cfg.json", b"{\"b\": 1}");
+
+        assert_eq!(sync(&mut port, &dir).unwrap(), 1);
+        assert_eq!(sim.file("/sf/ope_cfg.json").unwrap(), b"{\"b\": 2}");
+        assert!(!sim
+            .commands
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|c| c == "ft /sf/ctrl_cfg.json"));
+        assert_eq!(sync(&mut port, &dir).unwrap(), 0);
+    }
+
+    #[test]
+    fn logs_downloads_the_latest_runs() {
+        let dir = temp_dir("logs");
+        let (mut port, sim) = Sim::start();
+        sim.add_file("/sf/run00.bin", b"run 0");
+        sim.add_file("/sf/run00.evt", b"events 0");
+        sim.add_file("/sf/run01.bin", b"run 1");
+        sim.add_file("/sf/log00.txt", b"log");
+
+        logs(&mut port, 1, &dir).unwrap();
+        let read = |name: &str| std::fs::read(Path::new(&dir).join(name)).ok();
+        assert_eq!(read("run00.bin").unwrap(), b"run 0");
+        assert_eq!(read("run00.evt").unwrap(), b"events 0");
+        assert_eq!(read("log00.txt").unwrap(), b"log");
+        assert_eq!(read("run01.bin"), None);
+        assert_eq!(
+            sim.commands.lock().unwrap().last().unwrap(),
+            "dl /sf/log00.txt"
+        );
+    }
+}
@@ -0,0 +1,127 @@
+// Host-side tool to control the micromouse over its console UART.
+//
+// Usage:
+//   mmctl [--port <port>] [--baud <baudrate>] <command>
+//     upload <file> [remote]         Upload a file (default: /sf/<file name>)
+//     download <remote> [-o <file>]  Download a file (default: downloads/<file name>)
+//     cmd [--json] <command> [args...]
+//                                    Run a console command and print its output
+//     sync [dir] [--no-reset]        Upload the configs that differ and reset the mouse
+//     logs [count] [-o <dir>]        Download the latest run logs and log00.txt
+//     term                           Interactive terminal, Ctrl-] quits
+//
+// The port is also taken from the environment variable MMCTL_PORT.
+
+mod console;
+mod file;
+mod serial;
+#[cfg(test)]
+mod sim;
+mod term;
+
+use console::Session;
+use serial::{quote, Port};
+
+const USAGE: &str = "Usage:
+  mmctl [--port <port>] [--baud <baudrate>] <command>
+    upload <file> [remote]         Upload a file (default: /sf/<file name>)
+    download <remote> [-o <file>]  Download a file (default: downloads/<file name>)
+    cmd [--json] <command> [args...]
+                                   Run a console command and print its output
+    sync [dir] [--no-reset]        Upload the configs that differ and reset the mouse
+    logs [count] [-o <dir>]        Download the latest run logs and log00.txt
+    term                           Interactive terminal, Ctrl-] quits
+
+The port is also taken from the environment variable MMCTL_PORT.";
+
+fn usage() -> anyhow::Error {
+    anyhow::anyhow!(USAGE)
+}
+
+fn main() -> anyhow::Result<()> {
+    let mut port = std::env::var("MMCTL_PORT").unwrap_or(serial::DEFAULT_PORT.to_string());
+    let mut baud = serial::DEFAULT_BAUD;
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--port" => port = args.get(i + 1).ok_or_else(usage)?.clone(),
+            "--baud" => baud = args.get(i + 1).ok_or_else(usage)?.parse()?,
+            _ => break,
+        }
+        i += 2;
+    }
+    let command = args.get(i).ok_or_else(usage)?.as_str();
+    let args = &args[i + 1..];
+
+    // Options of the command. Everything after 'cmd' belongs to the console command.
+    let mut output = None;
+    let mut flags = Vec::new();
+    let mut positional: Vec<&str> = Vec::new();
+    let mut i = 0;
+    while i < args.len() && command != "cmd" {
+        match args[i].as_str() {
+            "-o" => {
+                i += 1;
+                output = Some(args.get(i).ok_or_else(usage)?.clone());
+            }
+            s if s.starts_with("--") => flags.push(s),
+            s => positional.push(s),
+        }
+        i += 1;
+    }
+
+    let mut port = Port::open(&port, baud)?;
+    match (command, positional.as_slice()) {
+        ("upload", [local]) => file::upload(&mut port, local, &file::remote_path(local)),
+        ("upload", [local, remote]) => file::upload(&mut port, local, remote),
+        ("download", [remote]) => {
+            let local = output.unwrap_or_else(|| file::local_path(remote));
+            file::download(&mut port, remote, &local)
+        }
+        ("cmd", _) => match args.first().map(|s| s.as_str()) {
+            Some("--json") => cmd(&mut port, &args[1..], true),
+            _ => cmd(&mut port, args, false),
+        },
+        ("sync", [] | [_]) => {
+            let dir = positional.first().copied().unwrap_or(".");
+            let uploaded = file::sync(&mut port, dir)?;
+            if uploaded != 0 && !flags.contains(&"--no-reset") {
+                port.refresh()?;
+                port.write_line("reset")?;
+                eprintln!("Reset");
+            }
+            Ok(())
+        }
+        ("logs", [] | [_]) => {
+            let count = match positional.first() {
+                Some(n) => n.parse()?,
+                None => 1,
+            };
+            file::logs(&mut port, count, output.as_deref().unwrap_or("downloads"))
+        }
+        ("term", []) => term::term(&mut port),
+        _ => Err(usage()),
+    }
+}
+
+// Run a console command in JSON mode. The output is printed, or the whole reply with --json.
+fn cmd(port: &mut Port, args: &[String], json: bool) -> anyhow::Result<()> {
+    if args.is_empty() {
+        return Err(usage());
+    }
+    let line: Vec<String> = args.iter().map(|a| quote(a)).collect();
+    let mut session = Session::start(port)?;
+    let reply = session.run(&line.join(" "))?;
+    session.end()?;
+
+    if json {
+        println!("{}", reply.json);
+    } else {
+        for line in reply.output() {
+            println!("{}", line);
+        }
+    }
+    reply.check()
+}
@@ -0,0 +1,87 @@
+// Serial port to the console of the micromouse.
+
+use mm_log::transfer::Link;
+use serialport::SerialPort;
+use std::io::{self, Read, Write};
+use std::time::Duration;
+
+pub const DEFAULT_PORT: &str = "/dev/ttyUSB0";
+pub const DEFAULT_BAUD: u32 = 921600;
+
+pub struct Port {
+    port: Box<dyn SerialPort>,
+}
+
+impl Port {
+    pub fn open(name: &str, baud: u32) -> anyhow::Result<Self> {
+        let port = serialport::new(name, baud)
+            .timeout(Duration::from_millis(100))
+            .open()
+            .map_err(|e| anyhow::anyhow!("{}: {}", name, e))?;
+        Ok(Port::new(port))
+    }
+
+    pub fn new(port: Box<dyn SerialPort>) -> Self {
+        Port { port }
+    }
+
+    pub fn try_clone(&self) -> io::Result<Self> {
+        Ok(Port::new(self.port.try_clone()?))
+    }
+
+    pub fn write_line(&mut self, line: &str) -> io::Result<()> {
+        self.port.write_all(line.as_bytes())?;
+        self.port.write_all(b"\n")
+    }
+
+    // Cancel the line being typed on the console and discard what it has sent
+    pub fn refresh(&mut self) -> io::Result<()> {
+        self.port.write_all(&[0x1b])?;
+        std::thread::sleep(Duration::from_millis(50)); // Longer than an escape sequence
+        self.drain(100)?;
+        Ok(())
+    }
+
+    // Read until nothing comes for `quiet` [ms]
+    pub fn drain(&mut self, quiet: u32) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        let mut buffer = [0u8; 256];
+        loop {
+            match Link::read(self, &mut buffer, quiet)? {
+                0 => return Ok(data),
+                size => data.extend_from_slice(&buffer[..size]),
+            }
+        }
+    }
+}
+
+impl Link for Port {
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.port.write_all(data)
+    }
+
+    fn read(&mut self, data: &mut [u8], timeout: u32) -> io::Result<usize> {
+        self.port
+            .set_timeout(Duration::from_millis(timeout.max(1) as u64))?;
+        match self.port.read(data) {
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => Ok(0),
+            result => result,
+        }
+    }
+}
+
+// Quote an argument for the console tokenizer
+pub fn quote(arg: &str) -> String {
+    if !arg.is_empty() && !arg.contains([' ', '"', '\'', '\\']) {
+        return arg.to_string();
+    }
+    let mut quoted = String::from("\"");
+    for c in arg.chars() {
+        if c == '"' || c == '\\' {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
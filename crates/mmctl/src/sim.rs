@@ -0,0 +1,180 @@
+// Simulated console of the micromouse for the tests, on the other end of a pseudo terminal.
+//
+// It behaves like src/console of the firmware for the commands used by mmctl: the text and
+// JSON modes, ft and dl with mm_log::transfer, runs, echo and reset. Files are kept in memory.
+
+use crate::console::PROMPT;
+use crate::serial::Port;
+use mm_log::transfer::{self, Link};
+use serialport::TTYPort;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+const TEXT_PROMPT: &str = "4.00[V] > ";
+
+#[derive(Clone, Default)]
+pub struct Sim {
+    pub files: Arc<Mutex<BTreeMap<String, Vec<u8>>>>,
+    pub commands: Arc<Mutex<Vec<String>>>, // Lines received
+}
+
+impl Sim {
+    // The port of the host and the console running in a thread
+    pub fn start() -> (Port, Sim) {
+        let (host, mouse) = TTYPort::pair().unwrap();
+        let sim = Sim::default();
+        let console = sim.clone();
+        std::thread::spawn(move || console.run(Port::new(Box::new(mouse))));
+        (Port::new(Box::new(host)), sim)
+    }
+
+    pub fn add_file(&self, path: &str, data: &[u8]) {
+        self.files
+            .lock()
+            .unwrap()
+            .insert(path.to_string(), data.to_vec());
+    }
+
+    pub fn file(&self, path: &str) -> Option<Vec<u8>> {
+        self.files.lock().unwrap().get(path).cloned()
+    }
+
+    // Until the host closes the port
+    fn run(self, mut port: Port) {
+        let mut json = false;
+        loop {
+            let prompt = if json { PROMPT } else { TEXT_PROMPT };
+            if Link::write(&mut port, prompt.as_bytes()).is_err() {
+                return;
+            }
+            let line = match read_line(&mut port, !json) {
+                Ok(Some(line)) => line,
+                Ok(None) => continue, // Escape
+                Err(_) => return,
+            };
+            self.commands.lock().unwrap().push(line.clone());
+            let args: Vec<String> = tokenize(&line);
+            let Some(command) = args.first() else {
+                continue;
+            };
+
+            let mut output = Vec::new();
+            let mut data = serde_json::Value::Null;
+            let was_json = json;
+            let result = match (command.as_str(), &args[1..]) {
+                ("mode", [mode]) => {
+                    json = mode == "json";
+                    output.push(format!("Mode: {}", mode));
+                    Ok(())
+                }
+                ("echo", rest) => {
+                    output.extend(rest.iter().cloned());
+                    Ok(())
+                }
+                ("reset", []) => Ok(()),
+                ("runs", []) => {
+                    let files = self.files.lock().unwrap();
+                    let runs: Vec<serde_json::Value> = files
+                        .iter()
+                        .filter(|(p, _)| p.starts_with("/sf/run") && p.ends_with(".bin"))
+                        .map(|(p, d)| serde_json::json!({ "path": p, "size": d.len() }))
+                        .collect();
+                    data = serde_json::json!(runs);
+                    Ok(())
+                }
+                ("ft", [name]) => {
+                    let mut file = Vec::new();
+                    match transfer::receive(&mut port, &mut file) {
+                        Ok(size) => {
+                            self.add_file(name, &file);
+                            output.push(format!("Received {} bytes", size));
+                            Ok(())
+                        }
+                        Err(e) => Err(e.to_string()),
+                    }
+                }
+                ("dl", [name]) => match self.file(name) {
+                    Some(file) => {
+                        transfer::send(&mut port, &mut file.as_slice(), file.len() as u32)
+                            .map_err(|e| e.to_string())
+                    }
+                    None => {
+                        let message = format!("{}: No such file or directory", name);
+                        let _ = transfer::abort(&mut port, &message);
+                        Err(message)
+                    }
+                },
+                _ => Err(format!("Command not found: '{}'", command)),
+            };
+
+            let text = if was_json {
+                let reply = serde_json::json!({
+                    "status": if result.is_ok() { "ok" } else { "error" },
+                    "command": command,
+                    "error": result.err(),
+                    "output": output,
+                    "data": data,
+                });
+                format!("{}\n", reply)
+            } else {
+                if let Err(e) = result {
+                    output.push(format!("Error: {}", e));
+                }
+                output.iter().map(|l| format!("{}\n", l)).collect()
+            };
+            if Link::write(&mut port, text.as_bytes()).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+// None when the line is cancelled with escape
+fn read_line(port: &mut Port, echo: bool) -> std::io::Result<Option<String>> {
+    let mut line = Vec::new();
+    let mut byte = [0u8; 1];
+    loop {
+        if Link::read(port, &mut byte, 100)? == 0 {
+            continue;
+        }
+        match byte[0] {
+            b'\r' | b'\n' if line.is_empty() => continue,
+            b'\r' | b'\n' => break,
+            0x1b => {
+                Link::write(port, b"\r\n")?;
+                return Ok(None);
+            }
+            c => {
+                line.push(c);
+                if echo {
+                    Link::write(port, &byte)?;
+                }
+            }
+        }
+    }
+    if echo {
+        Link::write(port, b"\r\n")?;
+    }
+    Ok(Some(String::from_utf8_lossy(&line).to_string()))
+}
+
+// Spaces, "..." and backslashes, enough for the lines of mmctl
+fn tokenize(line: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut token: Option<String> = None;
+    let mut quoted = false;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => token.get_or_insert_with(String::new).extend(chars.next()),
+            '"' => {
+                quoted = !quoted;
+                token.get_or_insert_with(String::new);
+            }
+            ' ' if !quoted => tokens.extend(token.take()),
+            c => token.get_or_insert_with(String::new).push(c),
+        }
+    }
+    tokens.extend(token);
+    tokens
+}
@@ -0,0 +1,98 @@
+// Interactive terminal to the console.
+//
+// The keys are sent as they are typed, so the line editor of the console works
+// (history, completion). Ctrl-] quits.
+
+use crate::serial::Port;
+use mm_log::transfer::Link;
+use std::io::{Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+const QUIT: u8 = 0x1d; // Ctrl-]
+
+pub fn term(port: &mut Port) -> anyhow::Result<()> {
+    let mut reader = port.try_clone()?;
+    let quit = Arc::new(AtomicBool::new(false));
+    let quit_reader = quit.clone();
+    let handle = std::thread::spawn(move || -> std::io::Result<()> {
+        let mut stdout = std::io::stdout();
+        let mut buffer = [0u8; 256];
+        while !quit_reader.load(Ordering::Relaxed) {
+            let size = Link::read(&mut reader, &mut buffer, 100)?;
+            stdout.write_all(&buffer[..size])?;
+            stdout.flush()?;
+        }
+        Ok(())
+    });
+
+    eprintln!("Connected. Ctrl-] to quit.");
+    let raw = RawMode::enable();
+    let mut stdin = std::io::stdin();
+    let mut key = [0u8; 1];
+    let result = loop {
+        match stdin.read(&mut key) {
+            Ok(0) => break Ok(()),
+            Ok(_) if key[0] == QUIT => break Ok(()),
+            Ok(_) => {
+                if let Err(e) = Link::write(port, &key) {
+                    break Err(e);
+                }
+            }
+            Err(e) => break Err(e),
+        }
+    };
+    drop(raw);
+    quit.store(true, Ordering::Relaxed);
+    let reader_result = handle.join().unwrap_or(Ok(()));
+    eprintln!();
+    result?;
+    reader_result?;
+    Ok(())
+}
+
+// The terminal without line editing and echo, restored when dropped
+#[cfg(unix)]
+struct RawMode {
+    original: libc::termios,
+}
+
+#[cfg(unix)]
+impl RawMode {
+    fn enable() -> Option<Self> {
+        // SAFETY: termios is a plain C struct filled by tcgetattr
+        unsafe {
+            if libc::isatty(0) == 0 {
+                return None;
+            }
+            let mut original = std::mem::zeroed::<libc::termios>();
+            if libc::tcgetattr(0, &mut original) != 0 {
+                return None;
+            }
+            let mut raw = original;
+            libc::cfmakeraw(&mut raw);
+            libc::tcsetattr(0, libc::TCSANOW, &raw);
+            Some(RawMode { original })
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        // SAFETY: restores the settings read in enable
+        unsafe {
+            libc::tcsetattr(0, libc::TCSANOW, &self.original);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+struct RawMode {}
+
+#[cfg(not(unix))]
+impl RawMode {
+    fn enable() -> Option<Self> {
+        None
+    }
+}
@@ -0,0 +1,631 @@
+// Statistics, slicing and plots of a saved run.
+
+use crate::format_value;
+use crate::maze::Maze;
+use crate::path;
+use crate::run::{parse_time, Run};
+use crate::svg::{Svg, COLORS};
+use mm_log::event::{self, Event, EventKind};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+fn stats_usage() -> anyhow::Error {
+    anyhow::anyhow!("Usage: mmlog stats <log.bin> [--from <ms>] [--to <ms>]")
+}
+
+fn slice_usage() -> anyhow::Error {
+    anyhow::anyhow!(
+        "Usage: mmlog slice <log.bin> [--from <ms>] [--to <ms>] [--channels <a,b,...>] [-o <output>]"
+    )
+}
+
+fn plot_usage() -> anyhow::Error {
+    anyhow::anyhow!(
+        "Usage: mmlog plot <log.bin> <channel[,channel...]>... [--from <ms>] [--to <ms>] [--no-events] [-o <output.svg>]"
+    )
+}
+
+fn path_usage() -> anyhow::Error {
+    anyhow::anyhow!(
+        "Usage: mmlog path <log.bin> [maze.txt] [--from <ms>] [--to <ms>] [--no-events] [-o <output.svg>]"
+    )
+}
+
+// Options common to the commands in this file
+struct Options {
+    input: String,
+    positional: Vec<String>,
+    from: Option<f64>,
+    to: Option<f64>,
+    channels: Option<Vec<String>>,
+    output: Option<String>,
+    no_events: bool,
+}
+
+fn parse(args: &[String], usage: fn() -> anyhow::Error) -> anyhow::Result<Options> {
+    let mut input = None;
+    let mut options = Options {
+        input: String::new(),
+        positional: Vec::new(),
+        from: None,
+        to: None,
+        channels: None,
+        output: None,
+        no_events: false,
+    };
+
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--from" => {
+                i += 1;
+                options.from = Some(parse_time(args.get(i))?);
+            }
+            "--to" => {
+                i += 1;
+                options.to = Some(parse_time(args.get(i))?);
+            }
+            "--channels" => {
+                i += 1;
+                let list = args.get(i).ok_or_else(usage)?;
+                options.channels = Some(list.split(',').map(|s| s.to_string()).collect());
+            }
+            "-o" => {
+                i += 1;
+                options.output = Some(args.get(i).ok_or_else(usage)?.clone());
+            }
+            "--no-events" => options.no_events = true,
+            s if s.starts_with('-') => return Err(usage()),
+            s if input.is_none() => input = Some(s.to_string()),
+            s => options.positional.push(s.to_string()),
+        }
+        i += 1;
+    }
+    options.input = input.ok_or_else(usage)?;
+    Ok(options)
+}
+
+fn load(options: &Options) -> anyhow::Result<Run> {
+    let mut run = Run::load(&options.input)?;
+    run.slice(options.from, options.to);
+    if run.records.is_empty() {
+        return Err(anyhow::anyhow!("No records in the range"));
+    }
+    Ok(run)
+}
+
+fn output(path: &Option<String>) -> anyhow::Result<Box<dyn Write>> {
+    Ok(match path {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(BufWriter::new(io::stdout().lock())),
+    })
+}
+
+/********** stats **********/
+
+pub fn stats(args: &[String]) -> anyhow::Result<()> {
+    let options = parse(args, stats_usage)?;
+    if !options.positional.is_empty() {
+        return Err(stats_usage());
+    }
+    let run = load(&options)?;
+
+    let first = run.records.first().unwrap()[0];
+    let last = run.records.last().unwrap()[0];
+    println!(
+        "{}: {} records, interval {}[ms], {:.3} - {:.3}[s]",
+        options.input,
+        run.records.len(),
+        run.header.interval,
+        first / 1000.0,
+        last / 1000.0
+    );
+    if let Ok(metadata) = serde_json::from_str::<serde_json::Value>(&run.header.metadata) {
+        if let Some(dropped) = metadata.get("dropped").and_then(|v| v.as_u64()) {
+            println!("dropped records    {}", dropped);
+        }
+    }
+
+    match error_stats(&run, "target_v", "v") {
+        Some((max, rms)) => println!("velocity error     max {:.4}, rms {:.4} [m/s]", max, rms),
+        None => println!("velocity error     n/a (needs target_v and v)"),
+    }
+    match error_stats(&run, "target_theta", "theta") {
+        Some((max, rms)) => println!("heading error      max {:.4}, rms {:.4} [rad]", max, rms),
+        None => println!("heading error      n/a (needs target_theta and theta)"),
+    }
+    if let (Some(theta), Some(target)) = (run.column("theta"), run.column("target_theta")) {
+        // Heading error left at the end of the run
+        let drift = run.records.last().unwrap();
+        println!(
+            "heading drift      {:.4} [rad] at the end",
+            drift[target] - drift[theta]
+        );
+    }
+    match run.column("wall_error") {
+        Some(c) => {
+            let all: Vec<f64> = run.values(c).collect();
+            let active: Vec<f64> = all.iter().copied().filter(|v| *v != 0.0).collect();
+            println!(
+                "wall_error         rms {:.2}, rms while active {:.2} ({:.0}% of the time)",
+                rms(&all),
+                rms(&active),
+                active.len() as f64 * 100.0 / all.len() as f64
+            );
+        }
+        None => println!("wall_error         n/a (needs wall_error)"),
+    }
+    match run.column("v_batt") {
+        Some(c) => {
+            let (min, max) = min_max(run.values(c));
+            let first = run.records.first().unwrap()[c];
+            println!(
+                "battery            start {:.2}, min {:.2}, max {:.2}, sag {:.2} [V]",
+                first,
+                min,
+                max,
+                max - min
+            );
+        }
+        None => println!("battery            n/a (needs v_batt)"),
+    }
+
+    match &run.events {
+        Some((header, events)) => {
+            let durations = command_durations(header, events);
+            println!(
+                "{:<24}{:>8}{:>10}{:>10}{:>10}",
+                "command [ms]", "count", "min", "avg", "max"
+            );
+            for (name, d) in durations.iter() {
+                let (min, max) = min_max(d.iter().copied());
+                println!(
+                    "  {:<22}{:>8}{:>10}{:>10.1}{:>10}",
+                    name,
+                    d.len(),
+                    min,
+                    d.iter().sum::<f64>() / d.len() as f64,
+                    max
+                );
+            }
+            let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+            for e in events.iter() {
+                *counts.entry(e.kind.name()).or_default() += 1;
+            }
+            let counts: Vec<String> = counts.iter().map(|(k, n)| format!("{} {}", k, n)).collect();
+            println!("events             {}", counts.join(", "));
+        }
+        None => println!("commands           n/a (no event file)"),
+    }
+    Ok(())
+}
+
+// Time from CommandReceived to CommandDone [ms] by the name of the command.
+// Commands are executed one by one, so each Done closes the last Received.
+fn command_durations(header: &event::Header, events: &[Event]) -> BTreeMap<String, Vec<f64>> {
+    let mut durations: BTreeMap<String, Vec<f64>> = BTreeMap::new();
+    let mut received = None;
+    for e in events.iter() {
+        match e.kind {
+            EventKind::CommandReceived => received = Some((e.code, e.time)),
+            EventKind::CommandDone => {
+                if let Some((code, time)) = received.take() {
+                    if code == e.code {
+                        durations
+                            .entry(header.code_name(e))
+                            .or_default()
+                            .push(e.time.wrapping_sub(time) as f64);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    durations
+}
+
+// Max of the absolute error and the RMS error of `actual` against `target`
+fn error_stats(run: &Run, target: &str, actual: &str) -> Option<(f64, f64)> {
+    let (t, a) = (run.column(target)?, run.column(actual)?);
+    let errors: Vec<f64> = run.records.iter().map(|r| r[t] - r[a]).collect();
+    let max = errors.iter().fold(0.0f64, |m, e| m.max(e.abs()));
+    Some((max, rms(&errors)))
+}
+
+fn rms(values: &[f64]) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    (values.iter().map(|v| v * v).sum::<f64>() / values.len() as f64).sqrt()
+}
+
+fn min_max(values: impl Iterator<Item = f64>) -> (f64, f64) {
+    values.fold((f64::MAX, f64::MIN), |(min, max), v| {
+        (min.min(v), max.max(v))
+    })
+}
+
+/********** slice **********/
+
+pub fn slice(args: &[String]) -> anyhow::Result<()> {
+    let options = parse(args, slice_usage)?;
+    if !options.positional.is_empty() {
+        return Err(slice_usage());
+    }
+    let run = load(&options)?;
+
+    let columns: Vec<usize> = match &options.channels {
+        Some(names) => {
+            // "time" is always included
+            let mut columns = vec![0];
+            for name in names.iter().filter(|n| n.as_str() != "time") {
+                columns.push(run.require(name)?);
+            }
+            columns
+        }
+        None => (0..run.header.fields.len()).collect(),
+    };
+
+    let mut out = output(&options.output)?;
+    let names: Vec<&str> = columns
+        .iter()
+        .map(|c| run.header.fields[*c].name.as_str())
+        .collect();
+    writeln!(out, "{}", names.join(","))?;
+    for r in run.records.iter() {
+        let row: Vec<String> = columns.iter().map(|c| format_value(r[*c])).collect();
+        writeln!(out, "{}", row.join(","))?;
+    }
+    out.flush()?;
+    eprintln!("{} records", run.records.len());
+    Ok(())
+}
+
+/********** plot **********/
+
+const PLOT_WIDTH: f64 = 1000.0;
+const PANEL_HEIGHT: f64 = 180.0;
+const MARGIN_LEFT: f64 = 70.0;
+const MARGIN_RIGHT: f64 = 20.0;
+const MARGIN_TOP: f64 = 30.0;
+const PANEL_GAP: f64 = 30.0;
+
+pub fn plot(args: &[String]) -> anyhow::Result<()> {
+    let options = parse(args, plot_usage)?;
+    if options.positional.is_empty() {
+        return Err(plot_usage());
+    }
+    let run = load(&options)?;
+
+    // Each argument is a panel. Channels separated by commas share the panel.
+    let mut panels = Vec::new();
+    for arg in options.positional.iter() {
+        let mut columns = Vec::new();
+        for name in arg.split(',') {
+            columns.push(run.require(name)?);
+        }
+        panels.push(columns);
+    }
+
+    let t0 = run.records.first().unwrap()[0];
+    let t1 = run.records.last().unwrap()[0].max(t0 + 1.0);
+    let inner_width = PLOT_WIDTH - MARGIN_LEFT - MARGIN_RIGHT;
+    let x_of = |t: f64| MARGIN_LEFT + (t - t0) / (t1 - t0) * inner_width;
+
+    let height = MARGIN_TOP + panels.len() as f64 * (PANEL_HEIGHT + PANEL_GAP) + 10.0;
+    let mut svg = Svg::new(PLOT_WIDTH, height);
+    svg.text(MARGIN_LEFT, 18.0, &options.input, "font-weight=\"bold\"");
+
+    for (p, columns) in panels.iter().enumerate() {
+        let top = MARGIN_TOP + p as f64 * (PANEL_HEIGHT + PANEL_GAP);
+        let bottom = top + PANEL_HEIGHT;
+
+        let (mut min, mut max) = (f64::MAX, f64::MIN);
+        for c in columns.iter() {
+            let (a, b) = min_max(run.values(*c));
+            min = min.min(a);
+            max = max.max(b);
+        }
+        if max - min < 1e-9 {
+            min -= 1.0;
+            max += 1.0;
+        }
+        let pad = (max - min) * 0.05;
+        let (min, max) = (min - pad, max + pad);
+        let y_of = |v: f64| bottom - (v - min) / (max - min) * PANEL_HEIGHT;
+
+        // Frame and ticks
+        svg.rect(
+            MARGIN_LEFT,
+            top,
+            inner_width,
+            PANEL_HEIGHT,
+            "fill=\"none\" stroke=\"black\"",
+        );
+        for i in 0..=4 {
+            let v = min + (max - min) * i as f64 / 4.0;
+            let y = y_of(v);
+            svg.line(
+                MARGIN_LEFT,
+                y,
+                MARGIN_LEFT + inner_width,
+                y,
+                "stroke=\"#ddd\"",
+            );
+            svg.text(
+                MARGIN_LEFT - 5.0,
+                y + 4.0,
+                &format!("{:.3}", v),
+                "text-anchor=\"end\" font-size=\"10\"",
+            );
+        }
+        for i in 0..=10 {
+            let t = t0 + (t1 - t0) * i as f64 / 10.0;
+            let x = x_of(t);
+            svg.line(x, top, x, bottom, "stroke=\"#eee\"");
+            svg.text(
+                x,
+                bottom + 12.0,
+                &format!("{:.2}", t / 1000.0),
+                "text-anchor=\"middle\" font-size=\"10\"",
+            );
+        }
+
+        // Events
+        if let (Some((header, events)), false) = (&run.events, options.no_events) {
+            for e in events.iter() {
+                let color = match e.kind {
+                    EventKind::CommandReceived => "#bbb",
+                    EventKind::Fault | EventKind::Trigger => "#d62728",
+                    EventKind::WallEdge | EventKind::PositionReset => "#2ca02c",
+                    _ => continue,
+                };
+                let x = x_of(e.time as f64);
+                svg.line(
+                    x,
+                    top,
+                    x,
+                    bottom,
+                    &format!("stroke=\"{}\" stroke-dasharray=\"3,3\"", color),
+                );
+                if p == 0 && e.kind == EventKind::CommandReceived {
+                    svg.text(
+                        x + 2.0,
+                        top + 10.0,
+                        &header.code_name(e),
+                        "font-size=\"8\" fill=\"#888\"",
+                    );
+                }
+            }
+        }
+
+        // Series
+        for (i, c) in columns.iter().enumerate() {
+            let color = COLORS[i % COLORS.len()];
+            let points = decimate(
+                run.records.iter().map(|r| (x_of(r[0]), y_of(r[*c]))),
+                inner_width as usize,
+            );
+            svg.polyline(&points, &format!("stroke=\"{}\" stroke-width=\"1\"", color));
+            svg.text(
+                MARGIN_LEFT + 5.0 + i as f64 * 100.0,
+                top - 4.0,
+                &run.header.fields[*c].name,
+                &format!("fill=\"{}\"", color),
+            );
+        }
+    }
+
+    let path = options
+        .output
+        .clone()
+        .unwrap_or_else(|| "plot.svg".to_string());
+    std::fs::write(&path, svg.finish())?;
+    eprintln!("Saved {}", path);
+    Ok(())
+}
+
+// Keep the min and the max of each pixel column so that spikes stay visible.
+fn decimate(points: impl Iterator<Item = (f64, f64)>, width: usize) -> Vec<(f64, f64)> {
+    let points: Vec<(f64, f64)> = points.collect();
+    if points.len() <= width * 2 {
+        return points;
+    }
+    let mut result = Vec::with_capacity(width * 2);
+    let mut column = f64::NAN;
+    let mut low = (0.0, f64::MAX);
+    let mut high = (0.0, f64::MIN);
+    for &(x, y) in points.iter() {
+        if x.floor() != column {
+            if !column.is_nan() {
+                push_ordered(&mut result, low, high);
+            }
+            column = x.floor();
+            low = (x, y);
+            high = (x, y);
+        } else {
+            if y < low.1 {
+                low = (x, y);
+            }
+            if y > high.1 {
+                high = (x, y);
+            }
+        }
+    }
+    push_ordered(&mut result, low, high);
+    result
+}
+
+fn push_ordered(result: &mut Vec<(f64, f64)>, a: (f64, f64), b: (f64, f64)) {
+    if a.0 <= b.0 {
+        result.push(a);
+        result.push(b);
+    } else {
+        result.push(b);
+        result.push(a);
+    }
+}
+
+/********** path **********/
+
+pub fn path(args: &[String]) -> anyhow::Result<()> {
+    let options = parse(args, path_usage)?;
+    if options.positional.len() > 1 {
+        return Err(path_usage());
+    }
+    let maze = match options.positional.first() {
+        Some(path) => Some(Maze::load(path)?),
+        None => None,
+    };
+
+    // The blocks are chained from the start of the log, so the range is applied afterwards
+    let mut run = Run::load(&options.input)?;
+    if let Ok(metadata) = serde_json::from_str::<serde_json::Value>(&run.header.metadata) {
+        if metadata.get("trigger").is_some_and(|t| !t.is_null()) {
+            eprintln!("Warning: a pre-trigger capture does not start in the start block");
+        }
+    }
+    let mut poses = path::reconstruct(&run)?;
+    let from = options.from.unwrap_or(f64::MIN);
+    let to = options.to.unwrap_or(f64::MAX);
+    poses.retain(|p| p.time >= from && p.time <= to);
+    run.slice(options.from, options.to);
+    let (first, last) = match (poses.first(), poses.last()) {
+        (Some(first), Some(last)) => (first.block(), last.block()),
+        _ => return Err(anyhow::anyhow!("No records in the range")),
+    };
+    println!("from block ({}, {}) heading {}", first.0, first.1, first.2);
+    println!("to   block ({}, {}) heading {}", last.0, last.1, last.2);
+
+    let events = match (&run.events, options.no_events) {
+        (Some((_, events)), false) => events.as_slice(),
+        _ => &[],
+    };
+    let svg = path::render(&poses, maze.as_ref(), events, &options.input);
+    let path = options
+        .output
+        .clone()
+        .unwrap_or_else(|| "path.svg".to_string());
+    std::fs::write(&path, svg.finish())?;
+    eprintln!("Saved {}", path);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mm_log::record::{Field, FieldKind, Header};
+
+    fn event(time: u32, kind: EventKind, code: u8) -> Event {
+        Event {
+            time,
+            kind,
+            code,
+            value: 0.0,
+        }
+    }
+
+    fn synthetic_run() -> Run {
+        let header = Header::new(
+            1,
+            vec![
+                Field::new("time", FieldKind::U32, 1.0),
+                Field::new("target_v", FieldKind::I16, 0.001),
+                Field::new("v", FieldKind::I16, 0.001),
+            ],
+        );
+        let records = (0..10)
+            .map(|i| vec![100.0 + i as f64, 0.5, 0.5 - i as f64 * 0.01])
+            .collect();
+        let events = vec![
+            event(99, EventKind::Request, 0),
+            event(100, EventKind::CommandReceived, 0),
+            event(105, EventKind::CommandDone, 0),
+            event(109, EventKind::Fault, 0),
+            event(110, EventKind::Request, 1),
+        ];
+        Run {
+            header,
+            records,
+            events: Some((event::Header::default(), events)),
+        }
+    }
+
+    #[test]
+    fn command_durations_pair_received_and_done() {
+        let names: Vec<&[&str]> = vec![&["SForward", "SStop"], &["SForward", "SStop"]];
+        let header = event::Header::new(&names);
+        let events = [
+            event(100, EventKind::CommandReceived, 0),
+            event(100, EventKind::Request, 0),
+            event(150, EventKind::CommandDone, 0),
+            event(150, EventKind::CommandReceived, 0),
+            event(210, EventKind::CommandDone, 0),
+            event(210, EventKind::CommandReceived, 1),
+            event(300, EventKind::CommandDone, 1),
+            // Done without Received, e.g. the start of the run was sliced off
+            event(310, EventKind::CommandDone, 0),
+            // Done of another command does not close Received
+            event(320, EventKind::CommandReceived, 1),
+            event(330, EventKind::CommandDone, 0),
+            event(340, EventKind::CommandDone, 1),
+            // Unnamed codes are shown as numbers
+            event(350, EventKind::CommandReceived, 7),
+            event(352, EventKind::CommandDone, 7),
+        ];
+        let durations = command_durations(&header, &events);
+        assert_eq!(durations.len(), 3);
+        assert_eq!(durations["SForward"], [50.0, 60.0]);
+        assert_eq!(durations["SStop"], [90.0]);
+        assert_eq!(durations["7"], [2.0]);
+
+        // The time stamp wraps around
+        let events = [
+            event(u32::MAX - 1, EventKind::CommandReceived, 0),
+            event(3, EventKind::CommandDone, 0),
+        ];
+        assert_eq!(command_durations(&header, &events)["SForward"], [5.0]);
+    }
+
+    #[test]
+    fn slice_keeps_both_bounds() {
+        let mut run = synthetic_run();
+        run.slice(Some(102.0), Some(105.0));
+        let times: Vec<f64> = run.values(0).collect();
+        assert_eq!(times, [102.0, 103.0, 104.0, 105.0]);
+        let (_, events) = run.events.as_ref().unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].kind, EventKind::CommandDone);
+
+        // Open ends keep everything on that side
+        let mut run = synthetic_run();
+        run.slice(None, Some(100.0));
+        assert_eq!(run.records.len(), 1);
+        assert_eq!(run.events.as_ref().unwrap().1.len(), 2);
+        let mut run = synthetic_run();
+        run.slice(Some(109.0), None);
+        assert_eq!(run.records.len(), 1);
+        assert_eq!(run.events.as_ref().unwrap().1.len(), 2);
+        let mut run = synthetic_run();
+        run.slice(None, None);
+        assert_eq!(run.records.len(), 10);
+
+        // load() reports an empty range as an error
+        let mut run = synthetic_run();
+        run.slice(Some(200.0), None);
+        assert!(run.records.is_empty());
+    }
+
+    #[test]
+    fn error_statistics() {
+        let run = synthetic_run();
+        let (max, rms) = error_stats(&run, "target_v", "v").unwrap();
+        assert!((max - 0.09).abs() < 1e-12);
+        let expected = ((0..10).map(|i| (i * i) as f64).sum::<f64>() / 10.0).sqrt() * 0.01;
+        assert!((rms - expected).abs() < 1e-12);
+        assert_eq!(error_stats(&run, "target_theta", "theta"), None);
+        assert_eq!(super::rms(&[]), 0.0);
+        assert_eq!(min_max([3.0, -1.0, 2.0].into_iter()), (-1.0, 3.0));
+    }
+}
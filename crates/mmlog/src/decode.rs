@@ -0,0 +1,132 @@
+// Convert a saved log file to CSV or JSON.
+
+use crate::format_value;
+use mm_log::record::Reader;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+
+fn usage() -> anyhow::Error {
+    anyhow::anyhow!("Usage: mmlog decode <log.bin> [--json] [-o <output>]")
+}
+
+pub fn decode(args: &[String]) -> anyhow::Result<()> {
+    let mut input = None;
+    let mut output = None;
+    let mut json = false;
+
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--json" => json = true,
+            "-o" => {
+                i += 1;
+                output = Some(args.get(i).ok_or_else(usage)?.clone());
+            }
+            s if input.is_none() => input = Some(s.to_string()),
+            _ => return Err(usage()),
+        }
+        i += 1;
+    }
+    let input = input.ok_or_else(usage)?;
+
+    let mut reader = Reader::new(BufReader::new(File::open(&input)?))?;
+    let mut out: Box<dyn Write> = match output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(BufWriter::new(io::stdout().lock())),
+    };
+
+    let count = if json {
+        write_json(&mut reader, &mut out)?
+    } else {
+        write_csv(&mut reader, &mut out)?
+    };
+    out.flush()?;
+
+    let header = reader.header();
+    eprintln!(
+        "{}: version {}, interval {}[ms], {} fields, {} records",
+        input,
+        header.version,
+        header.interval,
+        header.fields.len(),
+        count
+    );
+    Ok(())
+}
+
+fn write_csv<R: io::Read>(reader: &mut Reader<R>, out: &mut dyn Write) -> anyhow::Result<usize> {
+    let names: Vec<&str> = reader
+        .header()
+        .fields
+        .iter()
+        .map(|f| f.name.as_str())
+        .collect();
+    writeln!(out, "{}", names.join(","))?;
+
+    let mut count = 0;
+    while let Some(values) = reader.next_record()? {
+        let row: Vec<String> = values.iter().map(|v| format_value(*v)).collect();
+        writeln!(out, "{}", row.join(","))?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+fn write_json<R: io::Read>(reader: &mut Reader<R>, out: &mut dyn Write) -> anyhow::Result<usize> {
+    let names: Vec<String> = reader
+        .header()
+        .fields
+        .iter()
+        .map(|f| f.name.clone())
+        .collect();
+
+    writeln!(out, "[")?;
+    let mut count = 0;
+    while let Some(values) = reader.next_record()? {
+        let record: serde_json::Map<String, serde_json::Value> = names
+            .iter()
+            .cloned()
+            .zip(values.iter().map(|v| {
+                serde_json::from_str(&format_value(*v)).unwrap_or(serde_json::Value::Null)
+            }))
+            .collect();
+        if count != 0 {
+            writeln!(out, ",")?;
+        }
+        write!(out, "  {}", serde_json::Value::Object(record))?;
+        count += 1;
+    }
+    writeln!(out, "\n]")?;
+    Ok(count)
+}
+
+// Print the header and the metadata of a log file.
+pub fn info(args: &[String]) -> anyhow::Result<()> {
+    let path = match args {
+        [path] => path,
+        _ => return Err(anyhow::anyhow!("Usage: mmlog info <log.bin>")),
+    };
+    let mut reader = Reader::new(BufReader::new(File::open(path)?))?;
+    let mut count = 0;
+    while reader.next_record()?.is_some() {
+        count += 1;
+    }
+
+    let header = reader.header();
+    println!("version:  {}", header.version);
+    println!("interval: {}[ms]", header.interval);
+    println!("records:  {}", count);
+    println!("fields:");
+    for field in header.fields.iter() {
+        println!("  {:<16} {:?} x {}", field.name, field.kind, field.scale);
+    }
+    // The metadata of a log that was not closed properly is left blank
+    if !header.metadata.trim().is_empty() {
+        println!("metadata:");
+        match serde_json::from_str::<serde_json::Value>(&header.metadata) {
+            Ok(v) => println!("{}", serde_json::to_string_pretty(&v)?),
+            Err(_) => println!("{}", header.metadata),
+        }
+    }
+    Ok(())
+}
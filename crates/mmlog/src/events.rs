@@ -0,0 +1,80 @@
+// Convert a saved event file to CSV or JSON.
+
+use crate::format_value;
+use mm_log::event::{Event, Header};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+
+fn usage() -> anyhow::Error {
+    anyhow::anyhow!("Usage: mmlog events <events.evt> [--json] [-o <output>]")
+}
+
+pub fn events(args: &[String]) -> anyhow::Result<()> {
+    let mut input = None;
+    let mut output = None;
+    let mut json = false;
+
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--json" => json = true,
+            "-o" => {
+                i += 1;
+                output = Some(args.get(i).ok_or_else(usage)?.clone());
+            }
+            s if input.is_none() => input = Some(s.to_string()),
+            _ => return Err(usage()),
+        }
+        i += 1;
+    }
+    let input = input.ok_or_else(usage)?;
+
+    let (header, events) = read(&input)?;
+    let mut out: Box<dyn Write> = match output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(BufWriter::new(io::stdout().lock())),
+    };
+
+    if json {
+        let rows: Vec<serde_json::Value> = events
+            .iter()
+            .map(|e| {
+                serde_json::json!({
+                    "time": e.time,
+                    "kind": e.kind.name(),
+                    "name": header.code_name(e),
+                    "value": serde_json::from_str::<serde_json::Value>(&format_value(e.value as f64))
+                        .unwrap_or(serde_json::Value::Null),
+                })
+            })
+            .collect();
+        writeln!(out, "{}", serde_json::to_string_pretty(&rows)?)?;
+    } else {
+        writeln!(out, "time,kind,name,value")?;
+        for e in events.iter() {
+            writeln!(
+                out,
+                "{},{},{},{}",
+                e.time,
+                e.kind.name(),
+                header.code_name(e),
+                format_value(e.value as f64)
+            )?;
+        }
+    }
+    out.flush()?;
+
+    eprintln!("{}: {} events", input, events.len());
+    Ok(())
+}
+
+// Read all events of an event file
+pub fn read(path: &str) -> anyhow::Result<(Header, Vec<Event>)> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let header = Header::read(&mut reader)?;
+    let mut events = Vec::new();
+    while let Some(e) = Event::read(&mut reader)? {
+        events.push(e);
+    }
+    Ok((header, events))
+}
@@ -0,0 +1,60 @@
+// Host-side tool for the run logs saved by the micromouse.
+//
+// Usage:
+//   mmlog decode <log.bin> [--json] [-o <output>]
+//   mmlog info <log.bin>
+//   mmlog events <events.evt> [--json] [-o <output>]
+//   mmlog stats <log.bin> [--from <ms>] [--to <ms>]
+//   mmlog slice <log.bin> [--from <ms>] [--to <ms>] [--channels <a,b,...>] [-o <output>]
+//   mmlog plot <log.bin> <channel[,channel...]>... [--from <ms>] [--to <ms>] [--no-events] [-o <output.svg>]
+//   mmlog path <log.bin> [maze.txt] [--from <ms>] [--to <ms>] [--no-events] [-o <output.svg>]
+//   mmlog telem <port> [--baud <baudrate>] [-o <output>]
+
+mod analyze;
+mod decode;
+mod events;
+mod maze;
+mod path;
+mod run;
+mod svg;
+mod telem;
+
+const USAGE: &str = "Usage:
+  mmlog decode <log.bin> [--json] [-o <output>]
+  mmlog info <log.bin>
+  mmlog events <events.evt> [--json] [-o <output>]
+  mmlog stats <log.bin> [--from <ms>] [--to <ms>]
+  mmlog slice <log.bin> [--from <ms>] [--to <ms>] [--channels <a,b,...>] [-o <output>]
+  mmlog plot <log.bin> <channel[,channel...]>... [--from <ms>] [--to <ms>] [--no-events] [-o <output.svg>]
+  mmlog path <log.bin> [maze.txt] [--from <ms>] [--to <ms>] [--no-events] [-o <output.svg>]
+  mmlog telem <port> [--baud <baudrate>] [-o <output>]";
+
+fn main() -> anyhow::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(|s| s.as_str()) {
+        Some("decode") => decode::decode(&args[1..]),
+        Some("info") => decode::info(&args[1..]),
+        Some("events") => events::events(&args[1..]),
+        Some("stats") => analyze::stats(&args[1..]),
+        Some("slice") => analyze::slice(&args[1..]),
+        Some("plot") => analyze::plot(&args[1..]),
+        Some("path") => analyze::path(&args[1..]),
+        Some("telem") => telem::telem(&args[1..]),
+        _ => Err(anyhow::anyhow!(USAGE)),
+    }
+}
+
+// Print integers without a fractional part and trim the noise of f32 scales.
+pub fn format_value(v: f64) -> String {
+    if v.fract() == 0.0 {
+        format!("{}", v as i64)
+    } else {
+        let s = format!("{:.6}", v);
+        let s = s.trim_end_matches('0').trim_end_matches('.');
+        if s == "-0" {
+            "0".to_string()
+        } else {
+            s.to_string()
+        }
+    }
+}
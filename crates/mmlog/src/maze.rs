@@ -0,0 +1,152 @@
+// Maze text files in the common ASCII format, e.g.
+//
+//   +---+---+
+//   |       |
+//   +   +---+
+//   |   |   |
+//   +---+---+
+//
+// Posts are '+' or 'o'. The top line is the north side and cell (0, 0) is the bottom left.
+
+pub struct Wall {
+    // Posts at both ends, counted from the bottom left corner
+    pub from: (usize, usize),
+    pub to: (usize, usize),
+}
+
+pub struct Maze {
+    pub width: usize,
+    pub height: usize,
+    pub walls: Vec<Wall>,
+}
+
+impl Maze {
+    pub fn load(path: &str) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        Self::parse(&text).map_err(|e| anyhow::anyhow!("{}: {}", path, e))
+    }
+
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        let lines: Vec<&[u8]> = text
+            .lines()
+            .map(|l| l.trim_end().as_bytes())
+            .filter(|l| !l.is_empty())
+            .collect();
+        if lines.len() < 3 || !lines.iter().step_by(2).all(|l| is_post(l[0])) {
+            return Err(anyhow::anyhow!("Not a maze text file"));
+        }
+        let height = (lines.len() - 1) / 2;
+        let width = (lines.iter().map(|l| l.len()).max().unwrap() - 1) / 4;
+        let at = |line: &[u8], i: usize| line.get(i).copied().unwrap_or(b' ');
+
+        let mut walls = Vec::new();
+        for (row, line) in lines.iter().enumerate().take(height * 2 + 1) {
+            if row % 2 == 0 {
+                // Horizontal walls on a row of posts
+                let y = height - row / 2;
+                for x in 0..width {
+                    if at(line, x * 4 + 2) != b' ' {
+                        walls.push(Wall {
+                            from: (x, y),
+                            to: (x + 1, y),
+                        });
+                    }
+                }
+            } else {
+                // Vertical walls on a row of cells
+                let y = height - 1 - row / 2;
+                for x in 0..=width {
+                    if at(line, x * 4) == b'|' {
+                        walls.push(Wall {
+                            from: (x, y),
+                            to: (x, y + 1),
+                        });
+                    }
+                }
+            }
+        }
+
+        Ok(Maze {
+            width,
+            height,
+            walls,
+        })
+    }
+}
+
+fn is_post(c: u8) -> bool {
+    c == b'+' || c == b'o'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn walls(maze: &Maze) -> Vec<((usize, usize), (usize, usize))> {
+        let mut walls: Vec<_> = maze.walls.iter().map(|w| (w.from, w.to)).collect();
+        walls.sort();
+        walls
+    }
+
+    #[test]
+    fn parse_a_maze() {
+        let text = "\
++---+---+
+|       |
++   +---+
+|   |   |
++---+---+
+";
+        let maze = Maze::parse(text).unwrap();
+        assert_eq!((maze.width, maze.height), (2, 2));
+        assert_eq!(
+            walls(&maze),
+            [
+                ((0, 0), (0, 1)),
+                ((0, 0), (1, 0)),
+                ((0, 1), (0, 2)),
+                ((0, 2), (1, 2)),
+                ((1, 0), (1, 1)),
+                ((1, 0), (2, 0)),
+                ((1, 1), (2, 1)),
+                ((1, 2), (2, 2)),
+                ((2, 0), (2, 1)),
+                ((2, 1), (2, 2)),
+            ]
+        );
+    }
+
+    #[test]
+    fn posts_blank_lines_and_short_lines() {
+        // 'o' posts, trailing spaces and blank lines are accepted.
+        // Missing characters at the end of a line are spaces.
+        let text = "\n\
+o---o---o   \n\
+|\n\
+\n\
+o   o---o\n\
+|   |   |\n\
+o---o---o\n\n";
+        let maze = Maze::parse(text).unwrap();
+        assert_eq!((maze.width, maze.height), (2, 2));
+        assert_eq!(maze.walls.len(), 9);
+        assert!(!maze
+            .walls
+            .iter()
+            .any(|w| w.from == (2, 1) && w.to == (2, 2)));
+    }
+
+    #[test]
+    fn malformed_mazes_are_refused() {
+        for text in [
+            "",
+            "+---+\n",
+            "+---+\n|   |\n",
+            "+---+\n|   |\n|   |\n",
+            " +---+\n |   |\n +---+\n",
+            "maze\n|   |\n+---+\n",
+        ] {
+            assert!(Maze::parse(text).is_err(), "{:?}", text);
+        }
+    }
+}
@@ -0,0 +1,314 @@
+// Global path of the mouse reconstructed from a run log.
+//
+// The firmware keeps x/y/theta in the frame of the current block: the mouse heads to +y
+// (theta = pi/2) and x = 0, y = 0 are the left and the rear sides of the block.
+// At the end of a straight the frame moves one block ahead (y -= BLOCK_LENGTH), and after
+// a pivot it is turned (theta is reset to pi/2). The jumps of y and theta chain the frames.
+
+use crate::maze::Maze;
+use crate::run::Run;
+use crate::svg::{Svg, COLORS};
+use mm_log::event::{Event, EventKind};
+use std::f64::consts::FRAC_PI_2;
+
+pub const BLOCK_LENGTH: f64 = 0.09; // Same as mm_const::BLOCK_LENGTH [m]
+const MAZE_SIZE: usize = 16; // Drawn when no maze is given
+const CELL: f64 = 40.0; // [px]
+const MARGIN: f64 = 30.0;
+const MARGIN_TOP: f64 = 50.0;
+
+// Direction of each heading in quarter turns counterclockwise from north
+const HEADINGS: [(i32, i32); 4] = [(0, 1), (-1, 0), (0, -1), (1, 0)];
+const HEADING_NAMES: [&str; 4] = ["north", "west", "south", "east"];
+
+#[derive(Debug, Clone, Copy)]
+pub struct Pose {
+    pub time: f64,  // [ms]
+    pub x: f64,     // [m] from the bottom left corner of the maze
+    pub y: f64,     // [m]
+    pub theta: f64, // [rad]
+}
+
+impl Pose {
+    // Block and heading
+    pub fn block(&self) -> (i32, i32, &'static str) {
+        let heading = (self.theta / FRAC_PI_2 - 1.0).round() as i32;
+        (
+            (self.x / BLOCK_LENGTH).floor() as i32,
+            (self.y / BLOCK_LENGTH).floor() as i32,
+            HEADING_NAMES[heading.rem_euclid(4) as usize],
+        )
+    }
+}
+
+// The log must start in the start block, i.e. at ResetController.
+pub fn reconstruct(run: &Run) -> anyhow::Result<Vec<Pose>> {
+    let (cx, cy, ct) = (run.require("x")?, run.require("y")?, run.require("theta")?);
+    let mut block = (0, 0);
+    let mut heading = 0;
+    let mut prev: Option<(f64, f64)> = None;
+    let mut poses = Vec::with_capacity(run.records.len());
+
+    for r in run.records.iter() {
+        let (x, y, theta) = (r[cx], r[cy], r[ct]);
+        if let Some((prev_y, prev_theta)) = prev {
+            let turn = ((prev_theta - theta) / FRAC_PI_2).round() as i32;
+            if turn != 0 {
+                // Reset after a pivot
+                heading = (heading + turn).rem_euclid(4);
+            } else if prev_y - y > BLOCK_LENGTH / 2.0 {
+                // Entered the next block
+                block.0 += HEADINGS[heading as usize].0;
+                block.1 += HEADINGS[heading as usize].1;
+            }
+        }
+        prev = Some((y, theta));
+
+        let angle = heading as f64 * FRAC_PI_2;
+        let (dx, dy) = (x - BLOCK_LENGTH / 2.0, y - BLOCK_LENGTH / 2.0);
+        poses.push(Pose {
+            time: r[0],
+            x: (block.0 as f64 + 0.5) * BLOCK_LENGTH + dx * angle.cos() - dy * angle.sin(),
+            y: (block.1 as f64 + 0.5) * BLOCK_LENGTH + dx * angle.sin() + dy * angle.cos(),
+            theta: theta + angle,
+        });
+    }
+    Ok(poses)
+}
+
+// The last pose at or before `time`
+fn pose_at(poses: &[Pose], time: f64) -> Option<&Pose> {
+    let i = poses.partition_point(|p| p.time <= time);
+    poses.get(i.checked_sub(1)?)
+}
+
+pub fn render(poses: &[Pose], maze: Option<&Maze>, events: &[Event], title: &str) -> Svg {
+    let (width, height) = maze.map_or((MAZE_SIZE, MAZE_SIZE), |m| (m.width, m.height));
+    let px = |x: f64| MARGIN + x / BLOCK_LENGTH * CELL;
+    let py = |y: f64| MARGIN_TOP + (height as f64 - y / BLOCK_LENGTH) * CELL;
+    let mut svg = Svg::new(
+        width as f64 * CELL + MARGIN * 2.0,
+        height as f64 * CELL + MARGIN_TOP + MARGIN,
+    );
+    svg.text(MARGIN, 18.0, title, "font-weight=\"bold\"");
+
+    // Grid and block numbers
+    for x in 0..=width {
+        let sx = MARGIN + x as f64 * CELL;
+        svg.line(sx, py(0.0), sx, MARGIN_TOP, "stroke=\"#eee\"");
+        if x < width {
+            svg.text(
+                sx + CELL / 2.0,
+                py(0.0) + 14.0,
+                &x.to_string(),
+                "text-anchor=\"middle\" font-size=\"9\" fill=\"#888\"",
+            );
+        }
+    }
+    for y in 0..=height {
+        let sy = MARGIN_TOP + y as f64 * CELL;
+        svg.line(
+            px(0.0),
+            sy,
+            px(0.0) + width as f64 * CELL,
+            sy,
+            "stroke=\"#eee\"",
+        );
+        if y < height {
+            svg.text(
+                MARGIN - 6.0,
+                sy + CELL / 2.0 + 3.0,
+                &(height - 1 - y).to_string(),
+                "text-anchor=\"end\" font-size=\"9\" fill=\"#888\"",
+            );
+        }
+    }
+
+    // Walls
+    if let Some(maze) = maze {
+        for wall in maze.walls.iter() {
+            let post =
+                |(x, y): (usize, usize)| (px(x as f64 * BLOCK_LENGTH), py(y as f64 * BLOCK_LENGTH));
+            let ((x1, y1), (x2, y2)) = (post(wall.from), post(wall.to));
+            svg.line(
+                x1,
+                y1,
+                x2,
+                y2,
+                "stroke=\"black\" stroke-width=\"3\" stroke-linecap=\"square\"",
+            );
+        }
+    }
+
+    // Path, skipping points closer than half a pixel
+    let mut points: Vec<(f64, f64)> = Vec::new();
+    for p in poses.iter() {
+        let point = (px(p.x), py(p.y));
+        match points.last() {
+            Some(last) if (last.0 - point.0).hypot(last.1 - point.1) < 0.5 => {}
+            _ => points.push(point),
+        }
+    }
+    svg.polyline(
+        &points,
+        &format!("stroke=\"{}\" stroke-width=\"1.5\"", COLORS[0]),
+    );
+    if let (Some(first), Some(last)) = (points.first(), points.last()) {
+        svg.circle(first.0, first.1, 4.0, "fill=\"black\"");
+        svg.circle(last.0, last.1, 4.0, "fill=\"none\" stroke=\"black\"");
+    }
+
+    // Events
+    for e in events.iter() {
+        let Some(style) = marker_style(e.kind) else {
+            continue;
+        };
+        if let Some(p) = pose_at(poses, e.time as f64) {
+            svg.circle(px(p.x), py(p.y), 3.0, style);
+        }
+    }
+
+    // Legend
+    let legend = [
+        (EventKind::WallEdge, "wall edge"),
+        (EventKind::PositionReset, "position reset"),
+        (EventKind::Fault, "fault"),
+        (EventKind::Trigger, "trigger"),
+    ];
+    for (i, (kind, name)) in legend.iter().enumerate() {
+        let x = MARGIN + i as f64 * 110.0;
+        svg.circle(x + 4.0, 32.0, 3.0, marker_style(*kind).unwrap());
+        svg.text(x + 12.0, 36.0, name, "font-size=\"10\"");
+    }
+
+    svg
+}
+
+fn marker_style(kind: EventKind) -> Option<&'static str> {
+    match kind {
+        EventKind::WallEdge => Some("fill=\"#2ca02c\""),
+        EventKind::PositionReset => Some("fill=\"#ff7f0e\""),
+        EventKind::Fault => Some("fill=\"#d62728\""),
+        EventKind::Trigger => Some("fill=\"none\" stroke=\"#d62728\" stroke-width=\"1.5\""),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mm_log::record::{Field, FieldKind, Header};
+    use std::f64::consts::PI;
+
+    // Records of the firmware, in the frame of the current block
+    struct Trace {
+        records: Vec<Vec<f64>>,
+        y: f64,
+    }
+
+    impl Trace {
+        fn record(&mut self, x: f64, y: f64, theta: f64) {
+            let time = self.records.len() as f64;
+            self.records.push(vec![time, x, y, theta]);
+        }
+
+        // Move ahead by `blocks` in steps of 5 mm, moving the frame at the end of each block
+        fn forward(&mut self, blocks: f64) {
+            for _ in 0..(blocks * 18.0).round() as usize {
+                self.y += 0.005;
+                if self.y >= BLOCK_LENGTH {
+                    self.y -= BLOCK_LENGTH;
+                }
+                self.record(BLOCK_LENGTH / 2.0, self.y, FRAC_PI_2);
+            }
+        }
+
+        // Turn at the center of the block and reset theta as a pivot does
+        fn pivot(&mut self, angle: f64) {
+            let center = BLOCK_LENGTH / 2.0;
+            for i in 1..=10 {
+                self.record(center, center, FRAC_PI_2 + angle * i as f64 / 10.0);
+            }
+            self.y = center;
+            self.record(center, center, FRAC_PI_2);
+        }
+    }
+
+    fn run(trace: Trace) -> Run {
+        let header = Header::new(
+            1,
+            ["time", "x", "y", "theta"]
+                .iter()
+                .map(|name| Field::new(name, FieldKind::I16, 0.001))
+                .collect(),
+        );
+        Run {
+            header,
+            records: trace.records,
+            events: None,
+        }
+    }
+
+    #[test]
+    fn blocks_of_a_forward_turn_pivot_trace() {
+        let mut trace = Trace {
+            records: Vec::new(),
+            y: BLOCK_LENGTH / 2.0,
+        };
+        trace.record(BLOCK_LENGTH / 2.0, trace.y, FRAC_PI_2);
+        trace.forward(1.0);
+        trace.pivot(-FRAC_PI_2); // Right
+        trace.forward(1.0);
+        trace.pivot(FRAC_PI_2); // Left
+        trace.pivot(PI);
+        trace.forward(1.0);
+
+        let poses = reconstruct(&run(trace)).unwrap();
+        let mut blocks: Vec<(i32, i32, &str)> = poses.iter().map(|p| p.block()).collect();
+        blocks.dedup();
+        assert_eq!(
+            blocks,
+            [
+                (0, 0, "north"),
+                (0, 1, "north"),
+                (0, 1, "east"),
+                (1, 1, "east"),
+                (1, 1, "north"),
+                (1, 1, "west"),
+                (1, 1, "south"),
+                (1, 0, "south"),
+            ]
+        );
+
+        // Back at the center of the block below
+        let last = poses.last().unwrap();
+        assert!((last.x - 1.5 * BLOCK_LENGTH).abs() < 1e-9, "{:?}", last);
+        assert!((last.y - 0.5 * BLOCK_LENGTH).abs() < 1e-9, "{:?}", last);
+        assert!((last.theta - 3.0 * FRAC_PI_2).abs() < 1e-9, "{:?}", last);
+    }
+
+    #[test]
+    fn pose_at_is_the_last_one_before() {
+        let pose = |time| Pose {
+            time,
+            x: time,
+            y: 0.0,
+            theta: 0.0,
+        };
+        let poses = [pose(10.0), pose(20.0), pose(30.0)];
+        assert!(pose_at(&poses, 5.0).is_none());
+        assert_eq!(pose_at(&poses, 10.0).unwrap().x, 10.0);
+        assert_eq!(pose_at(&poses, 29.0).unwrap().x, 20.0);
+        assert_eq!(pose_at(&poses, 99.0).unwrap().x, 30.0);
+    }
+
+    #[test]
+    fn channels_are_required() {
+        let mut run = run(Trace {
+            records: Vec::new(),
+            y: 0.0,
+        });
+        run.header.fields.remove(3);
+        assert!(reconstruct(&run).is_err());
+    }
+}
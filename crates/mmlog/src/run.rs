@@ -0,0 +1,68 @@
+// A saved run: records of the run log and the events saved next to it.
+
+use mm_log::event::{self, Event};
+use mm_log::record::{Header, Reader};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+pub struct Run {
+    pub header: Header,
+    pub records: Vec<Vec<f64>>,
+    pub events: Option<(event::Header, Vec<Event>)>,
+}
+
+impl Run {
+    // Load `<name>.bin` and `<name>.evt` if it exists.
+    pub fn load(path: &str) -> anyhow::Result<Self> {
+        let mut reader = Reader::new(BufReader::new(File::open(path)?))?;
+        let mut records = Vec::new();
+        while let Some(values) = reader.next_record()? {
+            records.push(values);
+        }
+        let header = reader.header().clone();
+
+        let event_path = Path::new(path).with_extension("evt");
+        let events = if event_path.exists() {
+            Some(crate::events::read(&event_path.to_string_lossy())?)
+        } else {
+            None
+        };
+
+        Ok(Run {
+            header,
+            records,
+            events,
+        })
+    }
+
+    pub fn column(&self, name: &str) -> Option<usize> {
+        self.header.fields.iter().position(|f| f.name == name)
+    }
+
+    pub fn require(&self, name: &str) -> anyhow::Result<usize> {
+        self.column(name)
+            .ok_or_else(|| anyhow::anyhow!("The log has no channel '{}'", name))
+    }
+
+    pub fn values(&self, column: usize) -> impl Iterator<Item = f64> + '_ {
+        self.records.iter().map(move |r| r[column])
+    }
+
+    // Keep the records and the events in [from, to] [ms]
+    pub fn slice(&mut self, from: Option<f64>, to: Option<f64>) {
+        let from = from.unwrap_or(f64::MIN);
+        let to = to.unwrap_or(f64::MAX);
+        // "time" is always the first field
+        self.records.retain(|r| r[0] >= from && r[0] <= to);
+        if let Some((_, events)) = self.events.as_mut() {
+            events.retain(|e| e.time as f64 >= from && e.time as f64 <= to);
+        }
+    }
+}
+
+// Parse an optional time given as an argument of an option
+pub fn parse_time(arg: Option<&String>) -> anyhow::Result<f64> {
+    let arg = arg.ok_or_else(|| anyhow::anyhow!("A time [ms] is expected"))?;
+    Ok(arg.parse()?)
+}
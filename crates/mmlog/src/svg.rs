@@ -0,0 +1,90 @@
+// Minimal SVG writer for plots.
+
+use std::fmt::Write;
+
+pub struct Svg {
+    width: f64,
+    height: f64,
+    body: String,
+}
+
+impl Svg {
+    pub fn new(width: f64, height: f64) -> Self {
+        Svg {
+            width,
+            height,
+            body: String::new(),
+        }
+    }
+
+    pub fn line(&mut self, x1: f64, y1: f64, x2: f64, y2: f64, style: &str) {
+        let _ = writeln!(
+            self.body,
+            r#"<line x1="{:.2}" y1="{:.2}" x2="{:.2}" y2="{:.2}" {}/>"#,
+            x1, y1, x2, y2, style
+        );
+    }
+
+    pub fn polyline(&mut self, points: &[(f64, f64)], style: &str) {
+        if points.is_empty() {
+            return;
+        }
+        let mut p = String::with_capacity(points.len() * 16);
+        for (x, y) in points.iter() {
+            let _ = write!(p, "{:.2},{:.2} ", x, y);
+        }
+        let _ = writeln!(
+            self.body,
+            r#"<polyline points="{}" fill="none" {}/>"#,
+            p.trim_end(),
+            style
+        );
+    }
+
+    pub fn rect(&mut self, x: f64, y: f64, width: f64, height: f64, style: &str) {
+        let _ = writeln!(
+            self.body,
+            r#"<rect x="{:.2}" y="{:.2}" width="{:.2}" height="{:.2}" {}/>"#,
+            x, y, width, height, style
+        );
+    }
+
+    pub fn circle(&mut self, x: f64, y: f64, r: f64, style: &str) {
+        let _ = writeln!(
+            self.body,
+            r#"<circle cx="{:.2}" cy="{:.2}" r="{:.2}" {}/>"#,
+            x, y, r, style
+        );
+    }
+
+    pub fn text(&mut self, x: f64, y: f64, text: &str, style: &str) {
+        let _ = writeln!(
+            self.body,
+            r#"<text x="{:.2}" y="{:.2}" {}>{}</text>"#,
+            x,
+            y,
+            style,
+            escape(text)
+        );
+    }
+
+    pub fn finish(self) -> String {
+        format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\" font-family=\"sans-serif\" font-size=\"12\">\n<rect width=\"{w}\" height=\"{h}\" fill=\"white\"/>\n{}</svg>\n",
+            self.body,
+            w = self.width,
+            h = self.height
+        )
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+// Colors of the series and the markers
+pub const COLORS: [&str; 8] = [
+    "#1f77b4", "#ff7f0e", "#2ca02c", "#d62728", "#9467bd", "#8c564b", "#e377c2", "#7f7f7f",
+];
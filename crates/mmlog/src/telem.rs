@@ -0,0 +1,242 @@
+// Receive telemetry packets streamed over UART and print them as CSV.
+
+use crate::format_value;
+use mm_log::frame::{self, FrameReader, Packet};
+use mm_log::record::{self, Header};
+use std::fs::File;
+use std::io::{self, LineWriter, Read, Write};
+use std::time::Duration;
+
+fn usage() -> anyhow::Error {
+    anyhow::anyhow!("Usage: mmlog telem <port> [--baud <baudrate>] [-o <output>]")
+}
+
+pub fn telem(args: &[String]) -> anyhow::Result<()> {
+    let mut port = None;
+    let mut baud = 921600;
+    let mut output = None;
+
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--baud" => {
+                i += 1;
+                baud = args.get(i).ok_or_else(usage)?.parse()?;
+            }
+            "-o" => {
+                i += 1;
+                output = Some(args.get(i).ok_or_else(usage)?.clone());
+            }
+            s if port.is_none() => port = Some(s.to_string()),
+            _ => return Err(usage()),
+        }
+        i += 1;
+    }
+    let port = port.ok_or_else(usage)?;
+
+    let mut serial = serialport::new(&port, baud)
+        .timeout(Duration::from_millis(100))
+        .open()?;
+    let out: Box<dyn Write> = match output {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout()),
+    };
+    let mut receiver = Receiver::new(LineWriter::new(out));
+    let mut reader = FrameReader::new();
+
+    eprintln!("Receiving telemetry from {} at {} baud", port, baud);
+    let mut buffer = [0u8; 1024];
+    loop {
+        let size = match serial.read(&mut buffer) {
+            Ok(size) => size,
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => continue,
+            Err(e) => return Err(e.into()),
+        };
+        for &b in buffer[..size].iter() {
+            if let Some(frame) = reader.push(b) {
+                receiver.on_frame(&frame)?;
+            }
+        }
+    }
+}
+
+struct Receiver<W: Write> {
+    out: W,
+    header: Option<Header>,
+    last_seq: Option<u16>,
+    received: u64,
+    dropped: u64,
+    errors: u64,
+}
+
+impl<W: Write> Receiver<W> {
+    fn new(out: W) -> Self {
+        Receiver {
+            out,
+            header: None,
+            last_seq: None,
+            received: 0,
+            dropped: 0,
+            errors: 0,
+        }
+    }
+
+    fn on_frame(&mut self, frame: &[u8]) -> anyhow::Result<()> {
+        let packet = match frame::decode_packet(frame) {
+            Ok(packet) => packet,
+            Err(e) => {
+                self.errors += 1;
+                eprintln!("Frame error: {:?} ({} errors)", e, self.errors);
+                return Ok(());
+            }
+        };
+        self.check_seq(&packet);
+        self.received += 1;
+
+        match packet.kind {
+            frame::KIND_HEADER => {
+                let header = Header::read(&mut packet.payload.as_slice())?;
+                // The header is repeated periodically. Print the CSV header only when it changes.
+                if self.header.as_ref() != Some(&header) {
+                    let names: Vec<&str> = header.fields.iter().map(|f| f.name.as_str()).collect();
+                    writeln!(self.out, "{}", names.join(","))?;
+                    self.header = Some(header);
+                }
+            }
+            frame::KIND_SAMPLE => {
+                let header = match &self.header {
+                    Some(header) => header,
+                    None => return Ok(()), // Wait for the header
+                };
+                if packet.payload.len() != header.record_size() {
+                    self.errors += 1;
+                    eprintln!("Unexpected sample size {}", packet.payload.len());
+                    return Ok(());
+                }
+                let mut offset = 0;
+                let mut row = Vec::with_capacity(header.fields.len());
+                for field in header.fields.iter() {
+                    let v = record::decode(&packet.payload[offset..], field.kind, field.scale);
+                    row.push(format_value(v));
+                    offset += field.kind.size();
+                }
+                writeln!(self.out, "{}", row.join(","))?;
+            }
+            kind => eprintln!("Unknown packet kind {}", kind),
+        }
+        Ok(())
+    }
+
+    fn check_seq(&mut self, packet: &Packet) {
+        if let Some(last) = self.last_seq {
+            let gap = packet.seq.wrapping_sub(last).wrapping_sub(1);
+            if gap != 0 {
+                self.dropped += gap as u64;
+                eprintln!(
+                    "Dropped {} frames (seq {} -> {}), {} of {} lost in total",
+                    gap,
+                    last,
+                    packet.seq,
+                    self.dropped,
+                    self.dropped + self.received + 1
+                );
+            }
+        }
+        self.last_seq = Some(packet.seq);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mm_log::record::{Field, FieldKind};
+
+    fn header() -> Header {
+        Header::new(
+            1,
+            vec![
+                Field::new("time", FieldKind::U32, 1.0),
+                Field::new("v", FieldKind::I16, 0.001),
+            ],
+        )
+    }
+
+    fn frame(kind: u8, seq: u16, payload: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        frame::encode_packet(kind, seq, payload, &mut out);
+        out.pop(); // The delimiter
+        out
+    }
+
+    fn header_frame(seq: u16) -> Vec<u8> {
+        let mut payload = Vec::new();
+        header().write(&mut payload).unwrap();
+        frame(frame::KIND_HEADER, seq, &payload)
+    }
+
+    fn sample_frame(seq: u16, time: u32, v: f32) -> Vec<u8> {
+        let mut payload = Vec::new();
+        record::encode_raw(&mut payload, FieldKind::U32, time as i64);
+        record::encode(&mut payload, FieldKind::I16, 0.001, v);
+        frame(frame::KIND_SAMPLE, seq, &payload)
+    }
+
+    fn output(receiver: Receiver<Vec<u8>>) -> String {
+        String::from_utf8(receiver.out).unwrap()
+    }
+
+    #[test]
+    fn samples_are_printed_as_csv() {
+        let mut receiver = Receiver::new(Vec::new());
+        // Samples before the header are skipped
+        receiver.on_frame(&sample_frame(0, 9, 0.0)).unwrap();
+        receiver.on_frame(&header_frame(1)).unwrap();
+        receiver.on_frame(&sample_frame(2, 10, 0.25)).unwrap();
+        // The repeated header is not printed again
+        receiver.on_frame(&header_frame(3)).unwrap();
+        receiver.on_frame(&sample_frame(4, 11, -0.5)).unwrap();
+        assert_eq!(receiver.received, 5);
+        assert_eq!(receiver.dropped, 0);
+        assert_eq!(output(receiver), "time,v\n10,0.25\n11,-0.5\n");
+    }
+
+    #[test]
+    fn seq_gaps_are_counted_as_dropped() {
+        let mut receiver = Receiver::new(Vec::new());
+        receiver.on_frame(&header_frame(10)).unwrap();
+        receiver.on_frame(&sample_frame(11, 0, 0.0)).unwrap();
+        receiver.on_frame(&sample_frame(13, 2, 0.0)).unwrap();
+        assert_eq!(receiver.dropped, 1);
+        receiver.on_frame(&sample_frame(17, 6, 0.0)).unwrap();
+        assert_eq!(receiver.dropped, 4);
+
+        // The sequence number wraps around
+        let mut receiver = Receiver::new(Vec::new());
+        receiver
+            .on_frame(&sample_frame(u16::MAX - 1, 0, 0.0))
+            .unwrap();
+        receiver.on_frame(&sample_frame(u16::MAX, 1, 0.0)).unwrap();
+        receiver.on_frame(&sample_frame(0, 2, 0.0)).unwrap();
+        assert_eq!(receiver.dropped, 0);
+        receiver.on_frame(&sample_frame(3, 5, 0.0)).unwrap();
+        assert_eq!(receiver.dropped, 2);
+        assert_eq!(receiver.received, 4);
+    }
+
+    #[test]
+    fn broken_frames_are_counted_as_errors() {
+        let mut receiver = Receiver::new(Vec::new());
+        receiver.on_frame(&header_frame(0)).unwrap();
+        let mut broken = sample_frame(1, 0, 0.0);
+        broken[2] ^= 0x10;
+        receiver.on_frame(&broken).unwrap();
+        // A sample of another size
+        receiver
+            .on_frame(&frame(frame::KIND_SAMPLE, 2, &[1, 2, 3]))
+            .unwrap();
+        assert_eq!(receiver.errors, 2);
+        // The broken frame is also a gap in the sequence
+        assert_eq!(receiver.dropped, 1);
+        assert_eq!(output(receiver), "time,v\n");
+    }
+}
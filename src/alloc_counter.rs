@@ -0,0 +1,101 @@
+// Counts heap allocations made in the control cycle.
+// The counting allocator is installed in debug builds only.
+// A control cycle ends at each timer_interrupt::sync_ms().
+
+#[cfg(debug_assertions)]
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicU32, Ordering::Relaxed};
+
+pub const ENABLED: bool = cfg!(debug_assertions);
+
+static CONTROL_TASK: AtomicPtr<esp_idf_sys::tskTaskControlBlock> =
+    AtomicPtr::new(std::ptr::null_mut());
+static ALLOWED: AtomicBool = AtomicBool::new(false); // Set while allocating intentionally
+static CYCLE_ALLOCS: AtomicU32 = AtomicU32::new(0); // In the current cycle
+static TOTAL_ALLOCS: AtomicU32 = AtomicU32::new(0);
+static FLAGGED_CYCLES: AtomicU32 = AtomicU32::new(0); // Cycles in which something was allocated
+static LAST_FLAGGED: AtomicU32 = AtomicU32::new(0); // Time of the last flagged cycle [ms]
+
+#[cfg(debug_assertions)]
+struct CountingAllocator;
+
+#[cfg(debug_assertions)]
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count();
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        count();
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count();
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[cfg(debug_assertions)]
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+// This is called for every allocation, so it must not allocate.
+#[cfg(debug_assertions)]
+fn count() {
+    let task = CONTROL_TASK.load(Relaxed);
+    if task.is_null() || ALLOWED.load(Relaxed) {
+        return;
+    }
+    if unsafe { esp_idf_sys::xTaskGetCurrentTaskHandle() } == task {
+        CYCLE_ALLOCS.fetch_add(1, Relaxed);
+    }
+}
+
+// Call from the control thread before entering the control cycle
+pub fn register_control_thread() {
+    CONTROL_TASK.store(unsafe { esp_idf_sys::xTaskGetCurrentTaskHandle() }, Relaxed);
+}
+
+// Allocations in `f` are not counted, e.g. buffers allocated on a command
+pub fn allow<R>(f: impl FnOnce() -> R) -> R {
+    ALLOWED.store(true, Relaxed);
+    let result = f();
+    ALLOWED.store(false, Relaxed);
+    result
+}
+
+pub fn end_cycle() {
+    let n = CYCLE_ALLOCS.swap(0, Relaxed);
+    if n != 0 {
+        TOTAL_ALLOCS.fetch_add(n, Relaxed);
+        FLAGGED_CYCLES.fetch_add(1, Relaxed);
+        LAST_FLAGGED.store(crate::timer_interrupt::get_ms(), Relaxed);
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Stats {
+    pub allocations: u32,
+    pub flagged_cycles: u32,
+    pub last_flagged: u32, // [ms]
+}
+
+pub fn stats() -> Stats {
+    Stats {
+        allocations: TOTAL_ALLOCS.load(Relaxed),
+        flagged_cycles: FLAGGED_CYCLES.load(Relaxed),
+        last_flagged: LAST_FLAGGED.load(Relaxed),
+    }
+}
+
+pub fn reset() {
+    TOTAL_ALLOCS.store(0, Relaxed);
+    FLAGGED_CYCLES.store(0, Relaxed);
+    LAST_FLAGGED.store(0, Relaxed);
+}
@@ -0,0 +1,79 @@
+// Reading and writing of the configuration files on the flash (ctrl_cfg.json, ope_cfg.json)
+
+use serde::Serialize;
+use serde_json::Value;
+use std::io::Write;
+use std::sync::Mutex;
+
+// The profile of ctrl_cfg.json picked at boot, see mm_config::ctrl
+static PROFILE: Mutex<Option<String>> = Mutex::new(None);
+
+pub fn set_profile(name: &str) {
+    *PROFILE.lock().unwrap() = Some(name.to_string());
+}
+
+pub fn profile() -> Option<String> {
+    PROFILE.lock().unwrap().clone()
+}
+
+pub type Migration = fn(&mut Value) -> Result<Option<u32>, mm_config::Problem>;
+
+// Read a configuration file and upgrade it to the current version with `migrate`.
+// An upgraded file is written back, and the original is kept as <path>.v<version>.
+pub fn read(path: &str, migrate: Migration) -> anyhow::Result<Value> {
+    recover(path)?;
+    let contents = std::fs::read_to_string(path)?;
+    let mut value: Value = serde_json::from_str(&contents)?;
+    if let Some(version) = migrate(&mut value).map_err(|p| anyhow::anyhow!("{}", p))? {
+        let backup = format!("{}.v{}", path, version);
+        std::fs::write(&backup, &contents)?;
+        write(path, &to_json(&value)?)?;
+        println!("{} is upgraded from version {} ({})", path, version, backup);
+    }
+    Ok(value)
+}
+
+// Pretty printed with the tabs of the files in the repository
+pub fn to_json<T: Serialize>(value: &T) -> anyhow::Result<Vec<u8>> {
+    let mut contents = Vec::new();
+    let formatter = serde_json::ser::PrettyFormatter::with_indent(b"\t");
+    let mut serializer = serde_json::Serializer::with_formatter(&mut contents, formatter);
+    value.serialize(&mut serializer)?;
+    contents.push(b'\n');
+    Ok(contents)
+}
+
+// A reset in `write` between the removal of the old file and the rename leaves only
+// the temporary file. It is complete then, so the rename is finished here.
+fn recover(path: &str) -> anyhow::Result<()> {
+    let temporary = format!("{}.tmp", path);
+    if std::path::Path::new(path).exists() || !std::path::Path::new(&temporary).exists() {
+        return Ok(());
+    }
+    let contents = std::fs::read_to_string(&temporary)?;
+    if serde_json::from_str::<Value>(&contents).is_err() {
+        return Err(anyhow::anyhow!(
+            "{} is missing and {} is broken",
+            path,
+            temporary
+        ));
+    }
+    std::fs::rename(&temporary, path)?;
+    println!("{} is restored from {}", path, temporary);
+    Ok(())
+}
+
+// Write a temporary file first, so a reset while writing keeps the old file.
+// See `recover` for a reset after the old file is removed.
+pub fn write(path: &str, contents: &[u8]) -> anyhow::Result<()> {
+    let temporary = format!("{}.tmp", path);
+    let mut file = std::fs::File::create(&temporary)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    drop(file);
+    if std::path::Path::new(path).exists() {
+        std::fs::remove_file(path)?;
+    }
+    std::fs::rename(&temporary, path)?;
+    Ok(())
+}
@@ -0,0 +1,227 @@
+// Tokenizer and argument specs of the console commands.
+//
+// A line is split on whitespace. "..." and '...' quote spaces, and a backslash escapes the
+// next character (except in '...'). Each command declares the accepted forms of its
+// arguments, which are checked before `execute` and printed by `help <cmd>`.
+
+pub fn tokenize(line: &str) -> anyhow::Result<Vec<String>> {
+    let mut tokens = Vec::new();
+    let mut token: Option<String> = None; // Some in a token, so that "" is an empty token
+    let mut quote: Option<char> = None;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some('\''), '\'') | (Some('"'), '"') => quote = None,
+            (Some('\''), c) => token.get_or_insert_with(String::new).push(c),
+            (_, '\\') => {
+                let c = chars
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("Backslash at the end of the line"))?;
+                token.get_or_insert_with(String::new).push(c);
+            }
+            (None, '"') | (None, '\'') => {
+                quote = Some(c);
+                token.get_or_insert_with(String::new);
+            }
+            (None, c) if c.is_whitespace() => {
+                if let Some(t) = token.take() {
+                    tokens.push(t);
+                }
+            }
+            (_, c) => token.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if let Some(q) = quote {
+        return Err(anyhow::anyhow!("Missing closing quote {}", q));
+    }
+    if let Some(t) = token {
+        tokens.push(t);
+    }
+    Ok(tokens)
+}
+
+#[derive(Clone, Copy)]
+pub enum Kind {
+    Word, // The name itself, e.g. "clear" of "fread clear"
+    Str,
+    Int,
+    Float,
+    Choice(&'static [&'static str]),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Count {
+    One,
+    Optional, // Only at the end of a form
+    Rest,     // Zero or more, at the end of a form
+}
+
+#[derive(Clone, Copy)]
+pub struct Arg {
+    pub name: &'static str,
+    pub kind: Kind,
+    pub count: Count,
+}
+
+impl Arg {
+    pub const fn word(name: &'static str) -> Self {
+        Arg {
+            name,
+            kind: Kind::Word,
+            count: Count::One,
+        }
+    }
+
+    pub const fn one(name: &'static str, kind: Kind) -> Self {
+        Arg {
+            name,
+            kind,
+            count: Count::One,
+        }
+    }
+
+    pub const fn optional(name: &'static str, kind: Kind) -> Self {
+        Arg {
+            name,
+            kind,
+            count: Count::Optional,
+        }
+    }
+
+    pub const fn rest(name: &'static str, kind: Kind) -> Self {
+        Arg {
+            name,
+            kind,
+            count: Count::Rest,
+        }
+    }
+
+    fn check(&self, value: &str) -> Result<(), String> {
+        let ok = match self.kind {
+            Kind::Word => value == self.name,
+            Kind::Str => true,
+            Kind::Int => value.parse::<i64>().is_ok(),
+            Kind::Float => value.parse::<f32>().is_ok(),
+            Kind::Choice(choices) => choices.contains(&value),
+        };
+        match (ok, self.kind) {
+            (true, _) => Ok(()),
+            (false, Kind::Word) => Err(format!("Unexpected argument '{}'", value)),
+            (false, _) => Err(format!(
+                "Invalid {} '{}': expected {}",
+                self.name,
+                value,
+                self.type_name()
+            )),
+        }
+    }
+
+    fn type_name(&self) -> String {
+        match self.kind {
+            Kind::Word => format!("'{}'", self.name),
+            Kind::Str => "a string".to_string(),
+            Kind::Int => "an integer".to_string(),
+            Kind::Float => "a number".to_string(),
+            Kind::Choice(choices) => format!("one of {}", choices.join("|")),
+        }
+    }
+
+    fn usage(&self) -> String {
+        match (self.kind, self.count) {
+            (Kind::Word, _) => self.name.to_string(),
+            (_, Count::One) => format!("<{}>", self.name),
+            (_, Count::Optional) => format!("[{}]", self.name),
+            (_, Count::Rest) => format!("[{}...]", self.name),
+        }
+    }
+}
+
+pub struct Spec {
+    pub about: &'static [&'static str], // The first line is the summary
+    pub forms: &'static [&'static [Arg]],
+}
+
+impl Spec {
+    // Ok if the arguments match any of the forms.
+    // Otherwise the error of the form that matched the most arguments.
+    pub fn check(&self, args: &[&str]) -> anyhow::Result<()> {
+        let mut error: Option<(usize, String)> = None;
+        for form in self.forms.iter() {
+            match check_form(form, args) {
+                Ok(()) => return Ok(()),
+                Err((position, message)) => match error {
+                    Some((p, _)) if p >= position => {}
+                    _ => error = Some((position, message)),
+                },
+            }
+        }
+        match error {
+            Some((_, message)) => Err(anyhow::anyhow!(message)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn summary(&self) -> &'static str {
+        self.about.first().copied().unwrap_or("")
+    }
+
+    pub fn print_usage(&self, name: &str) {
+        for (i, form) in self.forms.iter().enumerate() {
+            let mut line = format!("{} {}", if i == 0 { "Usage:" } else { "      " }, name);
+            for arg in form.iter() {
+                line.push(' ');
+                line.push_str(&arg.usage());
+            }
+            uprintln!("{}", line);
+        }
+    }
+
+    pub fn print_help(&self, name: &str) {
+        for line in self.about.iter() {
+            uprintln!("{}", line);
+        }
+        self.print_usage(name);
+
+        let mut printed: Vec<&str> = Vec::new();
+        for arg in self.forms.iter().flat_map(|form| form.iter()) {
+            if matches!(arg.kind, Kind::Word) || printed.contains(&arg.name) {
+                continue;
+            }
+            printed.push(arg.name);
+            uprintln!("  {:<16} {}", arg.name, arg.type_name());
+        }
+    }
+}
+
+// The error has the position of the argument
+fn check_form(form: &[Arg], args: &[&str]) -> Result<(), (usize, String)> {
+    let mut i = 0;
+    for arg in form.iter() {
+        match arg.count {
+            Count::One => {
+                let value = args
+                    .get(i)
+                    .ok_or_else(|| (i, format!("Missing argument <{}>", arg.name)))?;
+                arg.check(value).map_err(|e| (i, e))?;
+                i += 1;
+            }
+            Count::Optional => {
+                if let Some(value) = args.get(i) {
+                    arg.check(value).map_err(|e| (i, e))?;
+                    i += 1;
+                }
+            }
+            Count::Rest => {
+                while let Some(value) = args.get(i) {
+                    arg.check(value).map_err(|e| (i, e))?;
+                    i += 1;
+                }
+            }
+        }
+    }
+    match args.get(i) {
+        Some(value) => Err((i, format!("Unexpected argument '{}'", value))),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,210 @@
+/* Control configuration */
+pub struct CmdConfig {}
+
+use super::args::{Arg, Kind::*, Spec};
+use super::reply;
+use super::ConsoleCommand;
+
+use serde_json::Value;
+use std::sync::Mutex;
+
+use crate::config_file;
+use crate::control_thread::{self, ControlThreadConfig};
+use crate::log_thread;
+use crate::OperationContext;
+
+// The configuration edited by `config set` and the file on the flash it was read from.
+// It is written back by `config save`. The file can also be replaced by `ft` or
+// `mmctl sync`, so it is read again by every `config` command.
+struct Working {
+    flash: Value,
+    edited: Value,
+}
+
+static WORKING: Mutex<Option<Working>> = Mutex::new(None);
+
+fn read_flash() -> anyhow::Result<Value> {
+    config_file::read(control_thread::CONFIG_PATH, mm_config::migrate::ctrl)
+}
+
+// Element of `value` at a path like "search_ctrl_cfg.v_pid.p". Arrays are indexed by numbers.
+fn lookup<'a>(value: &'a mut Value, path: &str) -> anyhow::Result<&'a mut Value> {
+    let mut current = value;
+    for key in path.split('.') {
+        current = match current {
+            Value::Object(map) => map.get_mut(key),
+            Value::Array(array) => key.parse::<usize>().ok().and_then(|i| array.get_mut(i)),
+            _ => None,
+        }
+        .ok_or_else(|| anyhow::anyhow!("No such key '{}' in '{}'", key, path))?;
+    }
+    Ok(current)
+}
+
+// Leaves of `old` and `new` that differ, as (path, old, new)
+fn diff(
+    path: &str,
+    old: Option<&Value>,
+    new: Option<&Value>,
+    changes: &mut Vec<(String, Value, Value)>,
+) {
+    let join = |key: &str| match path {
+        "" => key.to_string(),
+        _ => format!("{}.{}", path, key),
+    };
+    match (old, new) {
+        (Some(Value::Object(o)), Some(Value::Object(n))) => {
+            let mut keys: Vec<&String> = o.keys().chain(n.keys()).collect();
+            keys.sort();
+            keys.dedup();
+            for key in keys {
+                diff(&join(key), o.get(key), n.get(key), changes);
+            }
+        }
+        (Some(Value::Array(o)), Some(Value::Array(n))) => {
+            for i in 0..o.len().max(n.len()) {
+                diff(&join(&i.to_string()), o.get(i), n.get(i), changes);
+            }
+        }
+        _ if old != new => changes.push((
+            path.to_string(),
+            old.cloned().unwrap_or(Value::Null),
+            new.cloned().unwrap_or(Value::Null),
+        )),
+        _ => {}
+    }
+}
+
+fn save(working: &Value) -> anyhow::Result<()> {
+    // Written in the order of the struct
+    let config: ControlThreadConfig = serde_json::from_value(working.clone())?;
+    config_file::write(control_thread::CONFIG_PATH, &config_file::to_json(&config)?)
+}
+
+impl ConsoleCommand for CmdConfig {
+    fn execute(&self, args: &[&str], ctx: &OperationContext) -> anyhow::Result<()> {
+        let mut working = WORKING.lock().unwrap();
+        let flash = read_flash()?;
+        match working.as_ref() {
+            Some(w) if w.flash == flash => {}
+            w => {
+                if w.map_or(false, |w| w.edited != w.flash) {
+                    uprintln!("The file has changed on the flash, the edits are discarded");
+                }
+                *working = Some(Working {
+                    flash: flash.clone(),
+                    edited: flash,
+                });
+            }
+        }
+        let Working {
+            flash,
+            edited: config,
+        } = working.as_mut().unwrap();
+
+        match args {
+            [] => {
+                uprintln!("{}", serde_json::to_string_pretty(config)?);
+                reply::set_data(config.clone());
+            }
+            ["get", path] => {
+                let value = lookup(config, path)?;
+                uprintln!("{}", value);
+                reply::set_data(value.clone());
+            }
+            ["set", path, value] => {
+                // Anything that is not JSON is taken as a string
+                let value = serde_json::from_str(value).unwrap_or(Value::String(value.to_string()));
+                let mut candidate = config.clone();
+                *lookup(&mut candidate, path)? = value.clone();
+                if let Err(e) = serde_json::from_value::<ControlThreadConfig>(candidate.clone()) {
+                    return Err(anyhow::anyhow!(
+                        "Invalid value {} for {}: {}",
+                        value,
+                        path,
+                        e
+                    ));
+                }
+                *config = candidate;
+                uprintln!("{} = {}", path, value);
+            }
+            ["save"] => {
+                save(config)?;
+                // Read back, so the working copy is what the control thread will see
+                *flash = read_flash()?;
+                *config = flash.clone();
+                uprintln!("Saved {}", control_thread::CONFIG_PATH);
+            }
+            ["apply"] => {
+                // Checked here too, for the problems in the message of the error.
+                // The profile picked at boot is layered over the copy.
+                let new = match config_file::profile() {
+                    Some(name) => ControlThreadConfig::with_profile(config, &name)?,
+                    None => serde_json::from_value(config.clone())?,
+                };
+                ctx.command_tx
+                    .send(control_thread::Command::ReloadConfig(Box::new(new.clone())));
+                match ctx.wait_response() {
+                    control_thread::Response::ConfigReloaded(true) => {
+                        log_thread::set_ctrl_cfg(&new);
+                        uprintln!("Applied to the control thread")
+                    }
+                    resp => return Err(anyhow::anyhow!("Not applied: {:?}", resp)),
+                }
+            }
+            ["diff"] => {
+                let mut changes = Vec::new();
+                diff("", Some(flash), Some(config), &mut changes);
+                for (path, old, new) in changes.iter() {
+                    uprintln!("{}: {} -> {}", path, old, new);
+                }
+                if changes.is_empty() {
+                    uprintln!("No changes");
+                }
+                let changes: Vec<Value> = changes
+                    .into_iter()
+                    .map(|(path, flash, working)| {
+                        serde_json::json!({ "path": path, "flash": flash, "working": working })
+                    })
+                    .collect();
+                reply::set_data(serde_json::json!(changes));
+            }
+            _ => return Err(anyhow::anyhow!("Invalid argument")),
+        }
+        Ok(())
+    }
+
+    fn spec(&self) -> &Spec {
+        const SPEC: Spec = Spec {
+            about: &[
+                "Show or edit the control configuration (ctrl_cfg.json)",
+                "Paths are keys separated by dots, e.g. search_ctrl_cfg.v_pid.p.",
+                "Elements of tables are indexed by numbers, e.g. ws_cfg.ls_correction_table.1.0.",
+                "'set' edits a copy in memory and is checked against the configuration struct",
+                "and its invariants, e.g. sorted correction tables.",
+                "'save' writes the copy to the flash, 'diff' compares it with the flash.",
+                "When the file on the flash is replaced, e.g. by ft, the copy is read again.",
+                "'apply' hands the copy to the control thread, which uses it from the next motion,",
+                "with the profile picked at boot (profiles.<n>.overrides) layered over it.",
+                "Otherwise the control thread reads the file at boot.",
+            ],
+            forms: &[
+                &[],
+                &[Arg::word("get"), Arg::one("path", Str)],
+                &[
+                    Arg::word("set"),
+                    Arg::one("path", Str),
+                    Arg::one("value", Str),
+                ],
+                &[Arg::word("save")],
+                &[Arg::word("apply")],
+                &[Arg::word("diff")],
+            ],
+        };
+        &SPEC
+    }
+
+    fn name(&self) -> &str {
+        "config"
+    }
+}
@@ -0,0 +1,305 @@
+// Line editor of the console.
+// Keys: backspace, delete, left/right, Ctrl-A/Ctrl-E (home/end), up/down (history),
+// tab (completion of command names and /sf paths), escape (cancel the line).
+// The history is saved in /sf/.history.
+
+use crate::uart::{receive, send};
+use esp_idf_hal::delay::FreeRtos;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+
+const HISTORY_PATH: &str = "/sf/.history";
+const HISTORY_LEN: usize = 50;
+const MAX_LINE: usize = 256;
+const ESCAPE_TIMEOUT: u32 = 20; // Wait for the rest of an escape sequence [ms]
+
+pub enum ReadLine {
+    Line(String),
+    Escape,
+}
+
+enum Key {
+    Char(u8),
+    Enter,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Home,
+    End,
+    Up,
+    Down,
+    Tab,
+    Escape,
+    Ignore,
+}
+
+pub struct Editor {
+    history: Vec<String>, // Oldest first
+    last_cr: bool,        // Skip '\n' of "\r\n"
+    echo: bool,           // Off in JSON mode, without history and completion
+}
+
+impl Editor {
+    pub fn new() -> Self {
+        let history = match File::open(HISTORY_PATH) {
+            Ok(file) => BufReader::new(file)
+                .lines()
+                .filter_map(|l| l.ok())
+                .filter(|l| !l.is_empty())
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        Editor {
+            history,
+            last_cr: false,
+            echo: true,
+        }
+    }
+
+    pub fn set_echo(&mut self, echo: bool) {
+        self.echo = echo;
+    }
+
+    // `names` are the candidates for the completion of the first word
+    pub fn read_line(&mut self, prompt: &str, names: &[&str]) -> anyhow::Result<ReadLine> {
+        let mut line: Vec<u8> = Vec::new();
+        let mut cursor = 0;
+        let mut history_index = self.history.len(); // history.len() is the line being edited
+        let mut editing = Vec::new(); // The line being edited while browsing the history
+
+        loop {
+            let key = self.read_key()?;
+            match key {
+                Key::Char(c) => {
+                    if line.len() < MAX_LINE {
+                        line.insert(cursor, c);
+                        cursor += 1;
+                    }
+                }
+                Key::Enter => {
+                    let line = String::from_utf8_lossy(&line).to_string();
+                    if self.echo {
+                        write(b"\r\n");
+                        self.add_history(&line);
+                    }
+                    return Ok(ReadLine::Line(line));
+                }
+                Key::Escape => return Ok(ReadLine::Escape),
+                Key::Backspace => {
+                    if cursor > 0 {
+                        cursor -= 1;
+                        line.remove(cursor);
+                    }
+                }
+                Key::Delete => {
+                    if cursor < line.len() {
+                        line.remove(cursor);
+                    }
+                }
+                Key::Left => cursor = cursor.saturating_sub(1),
+                Key::Right => cursor = (cursor + 1).min(line.len()),
+                Key::Home => cursor = 0,
+                Key::End => cursor = line.len(),
+                Key::Up | Key::Down => {
+                    let index = match key {
+                        Key::Up if history_index > 0 => history_index - 1,
+                        Key::Down if history_index < self.history.len() => history_index + 1,
+                        _ => continue,
+                    };
+                    if history_index == self.history.len() {
+                        editing = line.clone();
+                    }
+                    history_index = index;
+                    line = match self.history.get(index) {
+                        Some(entry) => entry.as_bytes().to_vec(),
+                        None => editing.clone(),
+                    };
+                    cursor = line.len();
+                }
+                Key::Tab if self.echo => {
+                    let (insert, candidates) = complete(&line[..cursor], names);
+                    if !insert.is_empty() {
+                        for (i, c) in insert.bytes().enumerate() {
+                            line.insert(cursor + i, c);
+                        }
+                        cursor += insert.len();
+                    } else if candidates.len() > 1 {
+                        write(b"\r\n");
+                        write(candidates.join("  ").as_bytes());
+                        write(b"\r\n");
+                    }
+                }
+                Key::Tab | Key::Ignore => continue,
+            }
+            if self.echo {
+                redraw(prompt, &line, cursor);
+            }
+        }
+    }
+
+    fn add_history(&mut self, line: &str) {
+        if line.trim().is_empty() || self.history.last().map(|l| l.as_str()) == Some(line) {
+            return;
+        }
+        self.history.push(line.to_string());
+        if self.history.len() > HISTORY_LEN {
+            self.history.remove(0);
+        }
+        if let Err(e) = self.save_history() {
+            log::warn!("Failed to save the history: {:?}", e);
+        }
+    }
+
+    fn save_history(&self) -> anyhow::Result<()> {
+        let mut file = File::create(HISTORY_PATH)?;
+        for line in self.history.iter() {
+            writeln!(file, "{}", line)?;
+        }
+        Ok(())
+    }
+
+    fn read_key(&mut self) -> anyhow::Result<Key> {
+        let c = read_byte(None)?.unwrap();
+        let last_cr = self.last_cr;
+        self.last_cr = c == b'\r';
+        Ok(match c {
+            b'\n' if last_cr => Key::Ignore,
+            b'\r' | b'\n' => Key::Enter,
+            0x08 | 0x7f => Key::Backspace,
+            0x01 => Key::Home,
+            0x05 => Key::End,
+            b'\t' => Key::Tab,
+            0x1b => read_escape()?,
+            0x20..=0x7e => Key::Char(c),
+            _ => Key::Ignore,
+        })
+    }
+}
+
+// Wait for a byte, until `timeout` [ms] if given
+fn read_byte(timeout: Option<u32>) -> anyhow::Result<Option<u8>> {
+    let mut buffer = [0u8; 1];
+    let mut time = 0;
+    loop {
+        if receive(&mut buffer)? == 1 {
+            return Ok(Some(buffer[0]));
+        }
+        if matches!(timeout, Some(t) if time >= t) {
+            return Ok(None);
+        }
+        FreeRtos::delay_ms(1);
+        time += 1;
+    }
+}
+
+// ESC alone cancels the line. ESC [ x and ESC O x are the keys of a terminal.
+fn read_escape() -> anyhow::Result<Key> {
+    match read_byte(Some(ESCAPE_TIMEOUT))? {
+        Some(b'[') | Some(b'O') => {}
+        _ => return Ok(Key::Escape),
+    }
+    let mut param = None;
+    loop {
+        let c = match read_byte(Some(ESCAPE_TIMEOUT))? {
+            Some(c) => c,
+            None => return Ok(Key::Ignore),
+        };
+        return Ok(match c {
+            b'0'..=b'9' => {
+                param = Some(c);
+                continue;
+            }
+            b'A' => Key::Up,
+            b'B' => Key::Down,
+            b'C' => Key::Right,
+            b'D' => Key::Left,
+            b'H' => Key::Home,
+            b'F' => Key::End,
+            b'~' => match param {
+                Some(b'1') | Some(b'7') => Key::Home,
+                Some(b'4') | Some(b'8') => Key::End,
+                Some(b'3') => Key::Delete,
+                _ => Key::Ignore,
+            },
+            _ => Key::Ignore,
+        });
+    }
+}
+
+fn write(bytes: &[u8]) {
+    let _ = send(bytes);
+}
+
+fn redraw(prompt: &str, line: &[u8], cursor: usize) {
+    write(b"\r");
+    write(prompt.as_bytes());
+    write(line);
+    write(b"\x1b[K"); // Clear to the end of the line
+    if cursor < line.len() {
+        write(format!("\x1b[{}D", line.len() - cursor).as_bytes());
+    }
+}
+
+// Returns the text to insert at the cursor and the candidates.
+fn complete(before: &[u8], names: &[&str]) -> (String, Vec<String>) {
+    let before = String::from_utf8_lossy(before);
+    let start = before.rfind(' ').map_or(0, |i| i + 1);
+    let word = &before[start..];
+
+    let candidates: Vec<String> = if start == 0 {
+        names
+            .iter()
+            .filter(|n| n.starts_with(word))
+            .map(|n| format!("{} ", n))
+            .collect()
+    } else if word.starts_with('/') {
+        complete_path(word)
+    } else {
+        Vec::new()
+    };
+
+    let common = match candidates.first() {
+        Some(first) => candidates.iter().fold(first.as_str(), |common, c| {
+            let len = common
+                .bytes()
+                .zip(c.bytes())
+                .take_while(|(a, b)| a == b)
+                .count();
+            &common[..len]
+        }),
+        None => "",
+    };
+    let insert = common.get(word.len()..).unwrap_or("").to_string();
+    (insert, candidates)
+}
+
+// Paths under /sf. Directories end with '/' and files with ' '.
+fn complete_path(word: &str) -> Vec<String> {
+    let base = format!("{}/", crate::spiflash::BASE_PATH);
+    if !word.starts_with(&base) {
+        return if base.starts_with(word) {
+            vec![base]
+        } else {
+            Vec::new()
+        };
+    }
+    let (dir, prefix) = word.split_at(word.rfind('/').unwrap() + 1);
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut candidates: Vec<String> = entries
+        .filter_map(|e| e.ok())
+        .filter_map(|e| {
+            let name = e.file_name().to_string_lossy().to_string();
+            if !name.starts_with(prefix) {
+                return None;
+            }
+            let is_dir = e.file_type().map_or(false, |t| t.is_dir());
+            Some(format!("{}{}{}", dir, name, if is_dir { "/" } else { " " }))
+        })
+        .collect();
+    candidates.sort();
+    candidates
+}
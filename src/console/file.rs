@@ -1,75 +1,89 @@
 /* File transfer command */
 pub struct CmdFt {}
 
-use super::blocking_uart_read;
+use super::args::{Arg, Kind::*, Spec};
+use super::reply;
 use super::ConsoleCommand;
 
 use esp_idf_hal::delay::FreeRtos;
-use std::io::Write;
-use std::io::{BufRead, BufReader, Read};
+use mm_log::transfer::{self, Link};
+use std::io::{BufRead, BufReader};
 
 use crate::uart;
 use crate::OperationContext;
 
-impl ConsoleCommand for CmdFt {
-    fn execute(&self, args: &[&str], mut _ctx: &OperationContext) -> anyhow::Result<()> {
-        const CHUNK_SIZE: usize = 256;
-        if args.len() != 2 {
-            return Err(anyhow::anyhow!("Invalid argument"));
-        }
+// The console UART for mm_log::transfer
+struct UartLink {}
 
-        let filename = args[0];
-        let filesize = match args[1].parse::<u32>() {
-            Ok(v) => v,
-            Err(e) => return Err(anyhow::anyhow!("Invalid filesize: {}", e)),
-        };
-
-        if filesize == 0
-        /* todo: check max filesize */
-        {
-            return Err(anyhow::anyhow!("Invalid filesize {}", filesize));
+impl Link for UartLink {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<()> {
+        let mut sent = 0;
+        while sent < data.len() {
+            sent += uart::send(&data[sent..]).map_err(io_error)?;
         }
+        Ok(())
+    }
 
-        // Create a file
-        // If the file already exists, remove it
-        if std::path::Path::new(filename).exists() {
-            std::fs::remove_file(filename)?;
+    fn read(&mut self, data: &mut [u8], timeout: u32) -> std::io::Result<usize> {
+        for _ in 0..timeout.max(1) {
+            let size = uart::receive(data).map_err(io_error)?;
+            if size != 0 {
+                return Ok(size);
+            }
+            FreeRtos::delay_ms(1);
         }
-        let mut file = std::fs::File::create(filename)?;
-
-        uprintln!("Start");
+        Ok(0)
+    }
+}
 
-        let mut buffer: [u8; CHUNK_SIZE] = [0; CHUNK_SIZE];
-        let num_loops = filesize / CHUNK_SIZE as u32;
-        let last_chunk_size = filesize % CHUNK_SIZE as u32;
+fn io_error(e: esp_idf_sys::EspError) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::Other, e.to_string())
+}
 
-        for _ in 0..num_loops {
-            blocking_uart_read(&mut buffer, 10000)?;
-            let checksum =
-                crc16::State::<crc16::XMODEM>::calculate(&buffer[..last_chunk_size as usize]);
-            file.write_all(&buffer)?;
-            uprintln!("{:04X}", checksum);
-        }
+impl ConsoleCommand for CmdFt {
+    fn execute(&self, args: &[&str], mut _ctx: &OperationContext) -> anyhow::Result<()> {
+        let filename = args[0];
+        let temporary = format!("{}.tmp", filename);
+        let mut link = UartLink {};
+
+        // Receive into a temporary file, so a failed transfer keeps the old file
+        let mut file = match std::fs::File::create(&temporary) {
+            Ok(f) => f,
+            Err(e) => {
+                transfer::abort(&mut link, &e.to_string())?;
+                return Err(e.into());
+            }
+        };
+        let result = transfer::receive(&mut link, &mut file);
+        drop(file);
+        let size = match result {
+            Ok(size) => size,
+            Err(e) => {
+                let _ = std::fs::remove_file(&temporary);
+                return Err(e.into());
+            }
+        };
 
-        // Receive the last chunk
-        if last_chunk_size != 0 {
-            blocking_uart_read(&mut buffer[..last_chunk_size as usize], 10000)?;
-            let checksum =
-                crc16::State::<crc16::XMODEM>::calculate(&buffer[..last_chunk_size as usize]);
-            file.write_all(&buffer[..last_chunk_size as usize])?;
-            uprintln!("{:04X}", checksum);
+        if std::path::Path::new(filename).exists() {
+            std::fs::remove_file(filename)?;
         }
-
+        std::fs::rename(&temporary, filename)?;
+        uprintln!("Received {} bytes", size);
+        reply::set_data(serde_json::json!({ "file": filename, "size": size }));
         Ok(())
     }
 
-    fn hint(&self) {
-        uprintln!("File transfer");
-        uprintln!("Usage: ft {{filename}} {{filesize}}");
-        uprintln!("  After issuing the command, the PC sends the data of the file in binary.");
-        uprintln!("  The mouse sends back a CRC16-CCITT every 256Byte (chunk).");
-        uprintln!("  The file size will not be a multiple of 256Byte in most cases.");
-        uprintln!("  So the last chunk will be less than 256Byte.");
+    fn spec(&self) -> &Spec {
+        const SPEC: Spec = Spec {
+            about: &[
+                "Receive a file from the PC",
+                "After issuing the command, the PC sends the file in frames of mm_log::transfer.",
+                "Each frame has a CRC and is sent again until the mouse acknowledges it,",
+                "and the whole file is checked with a CRC at the end.",
+            ],
+            forms: &[&[Arg::one("filename", Str)]],
+        };
+        &SPEC
     }
 
     fn name(&self) -> &str {
@@ -82,57 +96,31 @@ pub struct CmdDl {}
 
 impl ConsoleCommand for CmdDl {
     fn execute(&self, args: &[&str], mut _ctx: &OperationContext) -> anyhow::Result<()> {
-        const CHUNK_SIZE: usize = 256;
-        if args.len() != 1 {
-            return Err(anyhow::anyhow!("Invalid argument"));
-        }
-
         let filename = args[0];
+        let mut link = UartLink {};
 
-        if !std::path::Path::new(filename).exists() {
-            return Err(anyhow::anyhow!("File not found"));
-        }
-
-        FreeRtos::delay_ms(500);
-
-        // Send file size
-        let metadata = std::fs::metadata(filename)?;
-        let filesize = metadata.len();
-        uprintln!("{}", filesize);
-
-        let mut buf: [u8; 10] = [0; 10];
-        loop {
-            match uart::receive(&mut buf) {
-                Ok(size) => {
-                    if size == 0 {
-                        FreeRtos::delay_ms(100);
-                        continue;
-                    }
-                    break;
-                }
-                Err(e) => {
-                    uprintln!("Error: {}", e);
-                }
-            }
-        }
-        // Open as binary.
-        let mut file = std::fs::File::open(filename)?;
-        let mut buffer = [0u8; CHUNK_SIZE];
-
-        while let Ok(size) = file.read(&mut buffer) {
-            if size == 0 {
-                break;
+        let mut file = match std::fs::File::open(filename) {
+            Ok(f) => f,
+            Err(e) => {
+                transfer::abort(&mut link, &format!("{}: {}", filename, e))?;
+                return Err(e.into());
             }
-
-            uart::send(&buffer[..size])?;
-        }
-
+        };
+        let size = file.metadata()?.len() as u32;
+        transfer::send(&mut link, &mut file, size)?;
+        reply::set_data(serde_json::json!({ "file": filename, "size": size }));
         Ok(())
     }
 
-    fn hint(&self) {
-        uprintln!("Download file");
-        uprintln!("Usage: dl {{filename}}");
+    fn spec(&self) -> &Spec {
+        const SPEC: Spec = Spec {
+            about: &[
+                "Send a file to the PC",
+                "The file is sent in frames of mm_log::transfer, see 'ft'.",
+            ],
+            forms: &[&[Arg::one("filename", Str)]],
+        };
+        &SPEC
     }
 
     fn name(&self) -> &str {
@@ -164,9 +152,12 @@ impl ConsoleCommand for CmdShow {
         Ok(())
     }
 
-    fn hint(&self) {
-        uprintln!("Show file contents");
-        uprintln!("Usage: show {{filename}}");
+    fn spec(&self) -> &Spec {
+        const SPEC: Spec = Spec {
+            about: &["Show file contents"],
+            forms: &[&[Arg::one("filename", Str)]],
+        };
+        &SPEC
     }
 
     fn name(&self) -> &str {
@@ -183,6 +174,7 @@ impl ConsoleCommand for CmdLs {
             return Err(anyhow::anyhow!("Invalid argument"));
         }
 
+        let mut files = Vec::new();
         match std::fs::read_dir(args[0]) {
             Ok(entries) => {
                 for entry in entries {
@@ -191,6 +183,7 @@ impl ConsoleCommand for CmdLs {
                             let path = e.path();
                             if path.is_file() {
                                 uprintln!("{}", path.display());
+                                files.push(path.display().to_string());
                             }
                         }
                         Err(e) => return Err(anyhow::anyhow!("Error reading entry: {}", e)),
@@ -199,12 +192,16 @@ impl ConsoleCommand for CmdLs {
             }
             Err(e) => return Err(anyhow::anyhow!("Error reading directory: {}", e)),
         }
+        reply::set_data(serde_json::json!(files));
         Ok(())
     }
 
-    fn hint(&self) {
-        uprintln!("List files");
-        uprintln!("Usage: ls {{path}}");
+    fn spec(&self) -> &Spec {
+        const SPEC: Spec = Spec {
+            about: &["List files"],
+            forms: &[&[Arg::one("path", Str)]],
+        };
+        &SPEC
     }
 
     fn name(&self) -> &str {
@@ -228,9 +225,12 @@ impl ConsoleCommand for CmdRm {
         Ok(())
     }
 
-    fn hint(&self) {
-        uprintln!("Remove file");
-        uprintln!("Usage: rm {{filename}}");
+    fn spec(&self) -> &Spec {
+        const SPEC: Spec = Spec {
+            about: &["Remove file"],
+            forms: &[&[Arg::one("filename", Str)]],
+        };
+        &SPEC
     }
 
     fn name(&self) -> &str {
@@ -254,9 +254,12 @@ impl ConsoleCommand for CmdMv {
         Ok(())
     }
 
-    fn hint(&self) {
-        uprintln!("Move file");
-        uprintln!("Usage: mv {{src}} {{dst}}");
+    fn spec(&self) -> &Spec {
+        const SPEC: Spec = Spec {
+            about: &["Move file"],
+            forms: &[&[Arg::one("src", Str), Arg::one("dst", Str)]],
+        };
+        &SPEC
     }
 
     fn name(&self) -> &str {
@@ -287,12 +290,120 @@ impl ConsoleCommand for CmdLog {
         show.execute(&[&filename], &mut _ctx)
     }
 
-    fn hint(&self) {
-        uprintln!("Show log file");
-        uprintln!("Usage: log [log number]");
+    fn spec(&self) -> &Spec {
+        const SPEC: Spec = Spec {
+            about: &["Show log file"],
+            forms: &[&[Arg::optional("number", Int)]],
+        };
+        &SPEC
     }
 
     fn name(&self) -> &str {
         "log"
     }
 }
+
+/* List or delete saved run logs */
+pub struct CmdRuns {}
+
+impl ConsoleCommand for CmdRuns {
+    fn execute(&self, args: &[&str], mut _ctx: &OperationContext) -> anyhow::Result<()> {
+        use crate::log_thread::{run_event_path, run_log_path, RunMetadata, RUN_LOG_NUM};
+
+        match args {
+            [] => {
+                let mut runs = Vec::new();
+                for n in 0..RUN_LOG_NUM {
+                    let path = run_log_path(n);
+                    let mut file = match std::fs::File::open(&path) {
+                        Ok(f) => f,
+                        Err(_) => continue,
+                    };
+                    let size = file.metadata()?.len();
+                    // A run cut by a reset can end in the header, the others are still listed
+                    let header = match mm_log::record::Header::read(&mut file) {
+                        Ok(h) => h,
+                        Err(e) => {
+                            uprintln!("{}: unreadable header ({})", path, e);
+                            runs.push(serde_json::json!({ "path": path, "size": size }));
+                            continue;
+                        }
+                    };
+                    match serde_json::from_str::<RunMetadata>(&header.metadata) {
+                        Ok(m) => {
+                            uprintln!(
+                                "{}: boot {}, {}, {} records ({} dropped), {} events, cfg {:04X}, {} bytes",
+                                path,
+                                m.boot_count,
+                                m.mode,
+                                m.records,
+                                m.dropped,
+                                m.events,
+                                m.ctrl_cfg_crc,
+                                size
+                            );
+                            if let Some(profile) = m.profile {
+                                uprintln!("  profile {}", profile);
+                            }
+                            if let Some(perf) = m.perf {
+                                uprintln!(
+                                    "  cycle avg {}[us], max {}[us], {} overruns",
+                                    perf.cycle.avg,
+                                    perf.cycle.max,
+                                    perf.overruns
+                                );
+                            }
+                            if let Some(trigger) = m.trigger {
+                                uprintln!(
+                                    "  triggered by {:?} after {} records",
+                                    trigger,
+                                    m.pre_trigger
+                                );
+                            }
+                        }
+                        Err(_) => uprintln!("{}: no metadata, {} bytes", path, size),
+                    }
+                    let metadata = serde_json::from_str::<serde_json::Value>(&header.metadata).ok();
+                    runs.push(serde_json::json!({
+                        "path": path,
+                        "size": size,
+                        "metadata": metadata,
+                    }));
+                }
+                reply::set_data(serde_json::json!(runs));
+            }
+            ["rm", "all"] => {
+                for n in 0..RUN_LOG_NUM {
+                    let _ = std::fs::remove_file(run_log_path(n));
+                    let _ = std::fs::remove_file(run_event_path(n));
+                }
+            }
+            ["rm", number] => {
+                let n = match number.parse::<usize>() {
+                    Ok(v) if v < RUN_LOG_NUM => v,
+                    _ => return Err(anyhow::anyhow!("Invalid run number: {}", number)),
+                };
+                std::fs::remove_file(run_log_path(n))?;
+                let _ = std::fs::remove_file(run_event_path(n));
+            }
+            _ => return Err(anyhow::anyhow!("Invalid argument")),
+        }
+        Ok(())
+    }
+
+    fn spec(&self) -> &Spec {
+        const SPEC: Spec = Spec {
+            about: &["List or delete saved run logs (run00 is the latest)"],
+            forms: &[
+                &[],
+                &[Arg::word("rm"), Arg::one("number", Int)],
+                &[Arg::word("rm"), Arg::word("all")],
+            ],
+        };
+        &SPEC
+    }
+
+    fn name(&self) -> &str {
+        "runs"
+    }
+}
@@ -1,42 +1,21 @@
 use crate::control_thread;
 use crate::led::LedColor::*;
 use crate::ods;
-use crate::uart::{self, read_line, receive};
+use crate::uart::{read_line, receive};
 use crate::OperationContext;
+use args::{Arg, Kind::*, Spec};
+use editor::{Editor, ReadLine};
 use esp_idf_hal::delay::FreeRtos;
-use std::fs::File;
-use std::io::prelude::*;
 
+mod args;
+mod config;
+mod editor;
 mod file;
-
-fn blocking_uart_read(buffer: &mut [u8], timeout_ms: u32) -> anyhow::Result<()> {
-    let size = buffer.len();
-    let mut i = 0;
-    let mut time_count = 0;
-    while i < size {
-        if time_count > timeout_ms {
-            return Err(anyhow::anyhow!("Receive timeout"));
-        }
-        time_count += 1;
-        FreeRtos::delay_ms(1);
-        match receive(&mut buffer[i..]) {
-            Ok(size) => {
-                if size == 0 {
-                    continue;
-                }
-                i += size;
-            }
-            Err(e) => {
-                uprintln!("Error: {}", e);
-                continue;
-            }
-        }
-    }
-    Ok(())
-}
+mod reply;
 
 pub struct Console {
     commands: Vec<Box<dyn ConsoleCommand>>,
+    json: bool, // Mode of the replies, see reply.rs
 }
 
 impl Console {
@@ -47,13 +26,19 @@ impl Console {
             Box::new(CmdOdo {}),
             Box::new(CmdGoffset {}),
             Box::new(CmdReset {}),
-            Box::new(CmdConfig {}),
+            Box::new(config::CmdConfig {}),
             Box::new(CmdBatt {}),
             Box::new(CmdFprint {}),
             Box::new(CmdFread {}),
+            Box::new(CmdFlevel {}),
             Box::new(CmdPanic {}),
+            Box::new(CmdCrash {}),
             Box::new(CmdMot {}),
             Box::new(CmdVac {}),
+            Box::new(CmdTelem {}),
+            Box::new(CmdTrigger {}),
+            Box::new(CmdAlloc {}),
+            Box::new(CmdPerf {}),
             Box::new(file::CmdFt {}),
             Box::new(file::CmdDl {}),
             Box::new(file::CmdShow {}),
@@ -61,121 +46,194 @@ impl Console {
             Box::new(file::CmdRm {}),
             Box::new(file::CmdMv {}),
             Box::new(file::CmdLog {}),
+            Box::new(file::CmdRuns {}),
         ];
-        Console { commands }
+        Console {
+            commands,
+            json: false,
+        }
     }
 
-    fn list(&self, args: &[&str]) {
+    fn list(&self, args: &[&str]) -> anyhow::Result<()> {
         if args.len() != 0 {
-            uprintln!("Invalid argument");
-            return;
+            return Err(anyhow::anyhow!("Invalid argument"));
         }
 
         for cmd in self.commands.iter() {
             uprintln!("{}", cmd.name());
         }
+        let names: Vec<&str> = self.commands.iter().map(|cmd| cmd.name()).collect();
+        reply::set_data(serde_json::json!(names));
+        Ok(())
+    }
+
+    // Summaries of all commands, or the arguments of a command
+    fn help(&self, args: &[&str]) -> anyhow::Result<()> {
+        match args {
+            [] => {
+                for cmd in self.commands.iter() {
+                    uprintln!("{:<10}{}", cmd.name(), cmd.spec().summary());
+                }
+                uprintln!("{:<10}{}", "list", "List the commands.");
+                uprintln!("{:<10}{}", "help", "Show this help, or 'help <command>'.");
+                uprintln!(
+                    "{:<10}{}",
+                    "mode",
+                    "Show or set the mode, 'mode [text|json]'."
+                );
+                uprintln!("{:<10}{}", "exit", "Exit the console.");
+            }
+            [name] => match self.commands.iter().find(|cmd| cmd.name() == *name) {
+                Some(cmd) => cmd.spec().print_help(cmd.name()),
+                None => return Err(anyhow::anyhow!("Command not found: '{}'", name)),
+            },
+            _ => return Err(anyhow::anyhow!("Invalid argument")),
+        }
+        Ok(())
+    }
+
+    // In JSON mode the line is not echoed and each command is answered with a JSON object
+    fn mode(&mut self, args: &[&str], editor: &mut Editor) -> anyhow::Result<()> {
+        match args {
+            [] => {}
+            ["text"] => self.json = false,
+            ["json"] => self.json = true,
+            _ => return Err(anyhow::anyhow!("Invalid argument")),
+        }
+        editor.set_echo(!self.json);
+        let mode = if self.json { "json" } else { "text" };
+        uprintln!("Mode: {}", mode);
+        reply::set_data(serde_json::json!({ "mode": mode }));
+        Ok(())
     }
 
-    pub fn run(&mut self, mut ctx: &OperationContext) -> anyhow::Result<()> {
+    pub fn run(&mut self, ctx: &OperationContext) -> anyhow::Result<()> {
         log::info!("Console started.");
         uprintln!("Welcome to ExtraICE console!");
 
+        let mut editor = Editor::new();
+        editor.set_echo(!self.json);
+        let mut names: Vec<String> = self
+            .commands
+            .iter()
+            .map(|cmd| cmd.name().to_string())
+            .collect();
+        names.extend(["list", "help", "mode", "exit"].map(String::from));
+        let names: Vec<&str> = names.iter().map(|n| n.as_str()).collect();
+
         loop {
             ctx.led_tx.send((Green, Some("10")))?;
-            let mut buf = [0u8; 256];
-            let mut args = [""; 16];
-            let mut arg_num = 0;
-            let mut len = 0;
 
-            let batt_phy = ctx.ods.lock().unwrap().wall_sensor.batt_phy;
+            let prompt = if self.json {
+                reply::PROMPT.to_string()
+            } else {
+                let batt_phy = ctx.ods.lock().unwrap().wall_sensor.batt_phy;
+                format!("{:1.2}[V] > ", batt_phy)
+            };
 
-            uprint!("{:1.2}[V] > ", batt_phy);
+            uprint!("{}", prompt);
 
             // receive command
-            match read_line(&mut buf, true) {
-                Ok(result) => {
-                    if result == uart::ReadLineResult::Escape {
-                        FreeRtos::delay_ms(10);
-                        println!("");
-                        continue;
-                    }
+            let line = match editor.read_line(&prompt, &names) {
+                Ok(ReadLine::Line(line)) => line,
+                Ok(ReadLine::Escape) => {
+                    FreeRtos::delay_ms(10);
+                    println!("");
+                    continue;
                 }
                 Err(e) => {
                     uprintln!("{}", e);
                     continue;
                 }
-            }
-
-            while buf[len] != 0 {
-                len += 1;
-            }
-
-            if len == 0 {
-                continue;
-            }
+            };
 
             // parse command
-            log::info!("Command: {}", std::str::from_utf8(&buf).unwrap());
-
-            let mut i = 0;
-            let mut arg_start = 0;
-            while i < len {
-                if buf[i] == ' ' as u8 {
-                    args[arg_num] = core::str::from_utf8(&buf[arg_start..i]).unwrap();
-                    arg_num += 1;
-                    arg_start = i + 1;
+            log::info!("Command: {}", line);
+
+            let json = self.json; // The reply of 'mode' is in the mode before the change
+            let tokens = match args::tokenize(&line) {
+                Ok(tokens) => tokens,
+                Err(e) => {
+                    if json {
+                        reply::finish("", &Err(e));
+                    } else {
+                        uprintln!("Error: {}", e);
+                    }
+                    continue;
                 }
-                i += 1;
+            };
+            let args: Vec<&str> = tokens.iter().map(|t| t.as_str()).collect();
+            if args.is_empty() {
+                continue;
             }
-            args[arg_num] = core::str::from_utf8(&buf[arg_start..i]).unwrap();
-            arg_num += 1;
 
-            if arg_num == 0 {
-                continue;
+            let cmd = self.commands.iter().position(|cmd| cmd.name() == args[0]);
+            if json {
+                // Commands streaming to the UART are not captured
+                reply::begin(!cmd.map_or(false, |i| self.commands[i].streams()));
             }
 
-            let mut found = false;
-            for cmd in self.commands.iter_mut() {
-                if cmd.name() == args[0] {
-                    ctx.led_tx.send((Red, Some("1")))?;
-                    match cmd.execute(&args[1..arg_num], &mut ctx) {
-                        Ok(_) => {}
-                        Err(e) => {
-                            uprintln!("Error: {}", e);
-                            log::error!("Command Error: {}", e);
-                            cmd.hint();
+            let result = match cmd {
+                Some(i) => {
+                    let cmd = &self.commands[i];
+                    match cmd.spec().check(&args[1..]) {
+                        Ok(()) => {
+                            ctx.led_tx.send((Red, Some("1")))?;
+                            let result = cmd.execute(&args[1..], ctx);
+                            ctx.led_tx.send((Red, Some("0")))?;
+                            if let Err(e) = &result {
+                                log::error!("Command Error: {}", e);
+                            }
+                            result
                         }
+                        Err(e) => Err(e),
                     }
-                    ctx.led_tx.send((Red, Some("0")))?;
-                    found = true;
-                    break;
                 }
-            }
-
-            if !found {
-                match args[0] {
-                    "list" => {
-                        self.list(&args[1..arg_num]);
-                    }
+                None => match args[0] {
+                    "list" => self.list(&args[1..]),
+                    "help" => self.help(&args[1..]),
+                    "mode" => self.mode(&args[1..], &mut editor),
                     "exit" => {
-                        uprintln!("Exit console.");
                         log::info!("Exit console.");
+                        if json {
+                            reply::finish("exit", &Ok(()));
+                        } else {
+                            uprintln!("Exit console.");
+                        }
                         return Ok(());
                     }
                     _ => {
-                        uprintln!("Command not found: '{}'", args[0]);
                         log::info!("Command not found: '{}'", args[0]);
+                        Err(anyhow::anyhow!("Command not found: '{}'", args[0]))
                     }
+                },
+            };
+
+            if let Err(e) = &result {
+                if !json {
+                    uprintln!("Error: {}", e);
+                }
+                if let Some(i) = cmd {
+                    self.commands[i].spec().print_usage(self.commands[i].name());
                 }
             }
+            if json {
+                reply::finish(args[0], &result);
+            }
         }
     }
 }
 
 pub trait ConsoleCommand {
     fn execute(&self, args: &[&str], ctx: &OperationContext) -> anyhow::Result<()>;
-    fn hint(&self);
+    fn spec(&self) -> &Spec;
     fn name(&self) -> &str;
+
+    // Prints while running (streams), so the output is not captured in JSON mode.
+    // The frames of the file transfers bypass the capture, see uart::send.
+    fn streams(&self) -> bool {
+        false
+    }
 }
 
 /* echo command */
@@ -200,9 +258,15 @@ impl ConsoleCommand for CmdEcho {
         Ok(())
     }
 
-    fn hint(&self) {
-        uprintln!("Echo input string.");
-        uprintln!("Usage: echo [args...]");
+    fn spec(&self) -> &Spec {
+        const SPEC: Spec = Spec {
+            about: &[
+                "Echo input string.",
+                "If no argument is specified, read a line and echo it.",
+            ],
+            forms: &[&[Arg::rest("args", Str)]],
+        };
+        &SPEC
     }
 
     fn name(&self) -> &str {
@@ -283,14 +347,21 @@ impl ConsoleCommand for CmdSen {
         Ok(())
     }
 
-    fn hint(&self) {
-        uprintln!("Show all sensor's values.");
-        uprintln!("Usage: sen");
+    fn spec(&self) -> &Spec {
+        const SPEC: Spec = Spec {
+            about: &["Show all sensor's values."],
+            forms: &[&[]],
+        };
+        &SPEC
     }
 
     fn name(&self) -> &str {
         "sen"
     }
+
+    fn streams(&self) -> bool {
+        true
+    }
 }
 
 struct CmdOdo {}
@@ -311,7 +382,7 @@ impl ConsoleCommand for CmdOdo {
         loop {
             let (micromouse, gyro) = {
                 let ods = ctx.ods.lock().unwrap();
-                (ods.micromouse.clone(), ods.imu.gyro_x_phy)
+                (ods.micromouse, ods.imu.gyro_x_phy)
             };
             uprintln!(
                 "x: {}[m], y: {}[m], theta: {}[rad], gyro: {}[rad/s], v_r: {}[m/s], v_l: {}[m/s]",
@@ -346,14 +417,21 @@ impl ConsoleCommand for CmdOdo {
         Ok(())
     }
 
-    fn hint(&self) {
-        uprintln!("Show the micromouse X, Y, Theta.");
-        uprintln!("Usage: odo");
+    fn spec(&self) -> &Spec {
+        const SPEC: Spec = Spec {
+            about: &["Show the micromouse X, Y, Theta."],
+            forms: &[&[]],
+        };
+        &SPEC
     }
 
     fn name(&self) -> &str {
         "odo"
     }
+
+    fn streams(&self) -> bool {
+        true
+    }
 }
 
 /* goffset cmd */
@@ -371,6 +449,7 @@ impl ConsoleCommand for CmdGoffset {
         match resp {
             control_thread::Response::CalibrationDone(offset) => {
                 uprintln!("Gyro offset: {}", offset);
+                reply::set_data(serde_json::json!({ "offset": offset }));
             }
             #[allow(unreachable_patterns)]
             _ => {
@@ -381,9 +460,12 @@ impl ConsoleCommand for CmdGoffset {
         Ok(())
     }
 
-    fn hint(&self) {
-        uprintln!("Measure the offset of the gyro.");
-        uprintln!("Usage: goffset");
+    fn spec(&self) -> &Spec {
+        const SPEC: Spec = Spec {
+            about: &["Measure the offset of the gyro."],
+            forms: &[&[]],
+        };
+        &SPEC
     }
 
     fn name(&self) -> &str {
@@ -405,9 +487,12 @@ impl ConsoleCommand for CmdReset {
         Ok(())
     }
 
-    fn hint(&self) {
-        uprintln!("Reset the robot.");
-        uprintln!("Usage: reset");
+    fn spec(&self) -> &Spec {
+        const SPEC: Spec = Spec {
+            about: &["Reset the robot."],
+            forms: &[&[]],
+        };
+        &SPEC
     }
 
     fn name(&self) -> &str {
@@ -415,31 +500,6 @@ impl ConsoleCommand for CmdReset {
     }
 }
 
-struct CmdConfig {}
-
-impl ConsoleCommand for CmdConfig {
-    fn execute(&self, args: &[&str], mut _ctx: &OperationContext) -> anyhow::Result<()> {
-        if args.len() == 0 {
-            let mut f = File::open("/sf/ctrl_cfg.json")?;
-            let mut contents = String::new();
-            f.read_to_string(&mut contents)?;
-            let result = serde_json::from_str(&contents)?;
-            uprintln!("{:?}", result);
-            return Ok(());
-        }
-        return Err(anyhow::anyhow!("Invalid argument"));
-    }
-
-    fn hint(&self) {
-        uprintln!("Show current configurations.");
-        uprintln!("Usage: config");
-    }
-
-    fn name(&self) -> &str {
-        "config"
-    }
-}
-
 struct CmdBatt {}
 
 impl ConsoleCommand for CmdBatt {
@@ -473,14 +533,21 @@ impl ConsoleCommand for CmdBatt {
         return Ok(());
     }
 
-    fn hint(&self) {
-        uprintln!("Show the battery voltage.");
-        uprintln!("Usage: batt");
+    fn spec(&self) -> &Spec {
+        const SPEC: Spec = Spec {
+            about: &["Show the battery voltage."],
+            forms: &[&[]],
+        };
+        &SPEC
     }
 
     fn name(&self) -> &str {
         "batt"
     }
+
+    fn streams(&self) -> bool {
+        true
+    }
 }
 
 // Write strings to FRAM using fprintln! macro.
@@ -497,9 +564,12 @@ impl ConsoleCommand for CmdFprint {
         return Ok(());
     }
 
-    fn hint(&self) {
-        uprintln!("Write strings to FRAM using fprintln! macro.");
-        uprintln!("Usage: fprint [string]");
+    fn spec(&self) -> &Spec {
+        const SPEC: Spec = Spec {
+            about: &["Write strings to FRAM using fprintln! macro."],
+            forms: &[&[Arg::one("string", Str)]],
+        };
+        &SPEC
     }
 
     fn name(&self) -> &str {
@@ -507,37 +577,43 @@ impl ConsoleCommand for CmdFprint {
     }
 }
 
-// Read strings from FRAM, until the read data is null.
+// Show the log entries in FRAM, oldest first.
 struct CmdFread {}
 
 impl ConsoleCommand for CmdFread {
     fn execute(&self, args: &[&str], mut _ctx: &OperationContext) -> anyhow::Result<()> {
-        if args.len() != 0 {
-            return Err(anyhow::anyhow!("Invalid argument"));
-        }
-
-        let mut buffer: [u8; 32] = [0; 32];
-        let mut adrs = 0;
-        let mut flag = true;
-
-        while flag {
-            crate::fram_logger::read_fram(adrs, &mut buffer)?;
-            adrs += buffer.len() as u16;
-            for b in buffer {
-                if b == 0 {
-                    flag = false;
-                    break;
+        match args {
+            [] => {
+                let entries = crate::fram_logger::entries()?;
+                for entry in entries.iter() {
+                    uprintln!("{}", crate::fram_logger::format_entry(entry));
                 }
-                uprint!("{}", b as char);
+                let data: Vec<serde_json::Value> = entries
+                    .iter()
+                    .map(|e| {
+                        serde_json::json!({
+                            "seq": e.seq,
+                            "time": e.time,
+                            "level": e.level,
+                            "text": e.text,
+                        })
+                    })
+                    .collect();
+                reply::set_data(serde_json::json!(data));
             }
+            ["clear"] => crate::fram_logger::clear()?,
+            _ => return Err(anyhow::anyhow!("Invalid argument")),
         }
 
         return Ok(());
     }
 
-    fn hint(&self) {
-        uprintln!("Read strings from FRAM");
-        uprintln!("Usage: fread");
+    fn spec(&self) -> &Spec {
+        const SPEC: Spec = Spec {
+            about: &["Show the log entries in FRAM, oldest first, or delete them."],
+            forms: &[&[], &[Arg::word("clear")]],
+        };
+        &SPEC
     }
 
     fn name(&self) -> &str {
@@ -545,6 +621,43 @@ impl ConsoleCommand for CmdFread {
     }
 }
 
+// Show or set the level of the log written to FRAM.
+struct CmdFlevel {}
+
+impl ConsoleCommand for CmdFlevel {
+    fn execute(&self, args: &[&str], mut _ctx: &OperationContext) -> anyhow::Result<()> {
+        match args {
+            [] => {}
+            [level] => {
+                let level = level
+                    .parse::<log::LevelFilter>()
+                    .map_err(|_| anyhow::anyhow!("Unknown level '{}'", level))?;
+                crate::fram_logger::set_level(level);
+            }
+            _ => return Err(anyhow::anyhow!("Invalid argument")),
+        }
+        let level = crate::fram_logger::level();
+        uprintln!("FRAM log level: {}", level);
+        reply::set_data(serde_json::json!({ "level": level.to_string() }));
+        Ok(())
+    }
+
+    fn spec(&self) -> &Spec {
+        const SPEC: Spec = Spec {
+            about: &["Show or set the level of the log written to FRAM."],
+            forms: &[&[Arg::optional(
+                "level",
+                Choice(&["off", "error", "warn", "info", "debug", "trace"]),
+            )]],
+        };
+        &SPEC
+    }
+
+    fn name(&self) -> &str {
+        "flevel"
+    }
+}
+
 // Intentionally panic.
 struct CmdPanic {}
 
@@ -560,9 +673,12 @@ impl ConsoleCommand for CmdPanic {
         Ok(())
     }
 
-    fn hint(&self) {
-        uprintln!("Intentionally panic.");
-        uprintln!("Usage: panic");
+    fn spec(&self) -> &Spec {
+        const SPEC: Spec = Spec {
+            about: &["Intentionally panic."],
+            forms: &[&[]],
+        };
+        &SPEC
     }
 
     fn name(&self) -> &str {
@@ -570,6 +686,130 @@ impl ConsoleCommand for CmdPanic {
     }
 }
 
+// Show the crash dump saved in FRAM by the panic handler or on a fault.
+struct CmdCrash {}
+
+impl ConsoleCommand for CmdCrash {
+    fn execute(&self, args: &[&str], _ctx: &OperationContext) -> anyhow::Result<()> {
+        use crate::crash_dump;
+
+        match args {
+            [] => {}
+            ["clear"] => return crash_dump::clear(),
+            _ => return Err(anyhow::anyhow!("Invalid argument")),
+        }
+
+        let dump = match crash_dump::load()? {
+            Some(dump) => dump,
+            None => {
+                uprintln!("No crash dump");
+                return Ok(());
+            }
+        };
+        uprintln!(
+            "{:?} at boot {}, {:.3}[s]",
+            dump.reason,
+            dump.boot_count,
+            dump.time as f32 / 1000.0
+        );
+        let command = control_thread::COMMAND_NAMES
+            .get(dump.command as usize)
+            .unwrap_or(&"none");
+        uprintln!("Command: {}, last request: {}", command, dump.req_id);
+        uprintln!("Message: {}", dump.message);
+        reply::set_data(serde_json::json!({
+            "reason": format!("{:?}", dump.reason),
+            "boot_count": dump.boot_count,
+            "time": dump.time,
+            "command": command,
+            "req_id": dump.req_id,
+            "message": dump.message,
+        }));
+
+        let s = match dump.snapshot {
+            Some(s) => s,
+            None => {
+                uprintln!("The ODS was locked, no snapshot");
+                return Ok(());
+            }
+        };
+        let m = &s.micromouse;
+        uprintln!(
+            "Pose: x {:.4}, y {:.4}, theta {:.3}, time {}",
+            m.x,
+            m.y,
+            m.theta,
+            m.time
+        );
+        uprintln!(
+            "Velocity: v {:.3} (target {:.3}), omega {:.3} (target {:.3}), v_l {:.3}, v_r {:.3}",
+            m.v,
+            m.target_v,
+            m.omega,
+            m.target_omega,
+            m.v_l,
+            m.v_r
+        );
+        uprintln!(
+            "Duty: l {:.2}, r {:.2}, target_theta {:.3}",
+            m.duty_l,
+            m.duty_r,
+            m.target_theta
+        );
+        uprintln!(
+            "Sensors: ls {}, lf {}, rf {}, rs {}, walls {}{}{}{}, wall_error {}",
+            m.ls,
+            m.lf,
+            m.rf,
+            m.rs,
+            m.ls_wall.to_bool() as u8,
+            m.lf_wall.to_bool() as u8,
+            m.rf_wall.to_bool() as u8,
+            m.rs_wall.to_bool() as u8,
+            m.wall_error
+        );
+        uprintln!(
+            "Encoder: l {} ({:+}), r {} ({:+}), gyro_x_raw {}",
+            s.encoder.l,
+            s.encoder.l_diff,
+            s.encoder.r,
+            s.encoder.r_diff,
+            s.gyro_x_raw
+        );
+        uprintln!(
+            "Battery: {:.3}[V] (raw {}), v_batt {:.3}[V]",
+            s.batt_phy,
+            s.batt_raw,
+            m.v_batt
+        );
+
+        uprintln!("Events ({}):", s.events.len());
+        let header = crate::log_thread::event_header();
+        for e in s.events.iter() {
+            uprintln!(
+                "  {:>8} {:<15} {:<16} {}",
+                e.time,
+                e.kind.name(),
+                header.code_name(e),
+                e.value
+            );
+        }
+        Ok(())
+    }
+
+    fn spec(&self) -> &Spec {
+        const SPEC: Spec = Spec {
+            about: &["Show the crash dump saved by the panic handler or on a fault."],
+            forms: &[&[], &[Arg::word("clear")]],
+        };
+        &SPEC
+    }
+
+    fn name(&self) -> &str {
+        "crash"
+    }
+}
+
 // Motor test (set percentage of duty)
 struct CmdMot {}
 
@@ -598,14 +838,20 @@ impl ConsoleCommand for CmdMot {
         let act_duty_r = crate::motor::get_r();
 
         uprintln!("Left: {}%, Right: {}%", act_duty_l, act_duty_r);
+        reply::set_data(serde_json::json!({ "left": act_duty_l, "right": act_duty_r }));
 
         Ok(())
     }
 
-    fn hint(&self) {
-        uprintln!("Set motor duty.");
-        uprintln!("If no argument is specified, stop the motor.");
-        uprintln!("Usage: mot [left] [right]");
+    fn spec(&self) -> &Spec {
+        const SPEC: Spec = Spec {
+            about: &[
+                "Set motor duty [%].",
+                "If no argument is specified, stop the motor.",
+            ],
+            forms: &[&[], &[Arg::one("left", Int), Arg::one("right", Int)]],
+        };
+        &SPEC
     }
 
     fn name(&self) -> &str {
@@ -640,13 +886,228 @@ impl ConsoleCommand for CmdVac {
         Ok(())
     }
 
-    fn hint(&self) {
-        uprintln!("Set vacuum fan voltage.");
-        uprintln!("If no argument is specified, stop the fan.");
-        uprintln!("Usage: vac [duty 0 - 4.2]");
+    fn spec(&self) -> &Spec {
+        const SPEC: Spec = Spec {
+            about: &[
+                "Set vacuum fan voltage (0 - 4.2[V]).",
+                "If no argument is specified, stop the fan.",
+            ],
+            forms: &[&[Arg::optional("voltage", Float)]],
+        };
+        &SPEC
     }
 
     fn name(&self) -> &str {
         "vac"
     }
 }
+
+// Start/stop streaming telemetry packets
+struct CmdTelem {}
+
+impl ConsoleCommand for CmdTelem {
+    fn execute(&self, args: &[&str], ctx: &OperationContext) -> anyhow::Result<()> {
+        let interval = if args.len() == 0 {
+            0
+        } else {
+            args[0].parse::<u16>()?
+        };
+
+        if interval == 0 {
+            ctx.telemetry_tx.send(crate::telemetry::Command::Stop)?;
+            return Ok(());
+        }
+
+        let names: Vec<String> = args[1..].iter().map(|s| s.to_string()).collect();
+        let channels = crate::log_thread::channel_mask(&names)?;
+        ctx.telemetry_tx
+            .send(crate::telemetry::Command::Start(interval, channels))?;
+
+        Ok(())
+    }
+
+    fn spec(&self) -> &Spec {
+        const SPEC: Spec = Spec {
+            about: &[
+                "Stream log channels as binary packets. Receive them with 'mmlog telem'.",
+                "If no argument is specified, stop streaming. The interval is in [ms].",
+            ],
+            forms: &[
+                &[],
+                &[Arg::one("interval", Int), Arg::rest("channels", Str)],
+            ],
+        };
+        &SPEC
+    }
+
+    fn name(&self) -> &str {
+        "telem"
+    }
+}
+
+// Pre-trigger capture of the run log
+struct CmdTrigger {}
+
+impl ConsoleCommand for CmdTrigger {
+    fn execute(&self, args: &[&str], ctx: &OperationContext) -> anyhow::Result<()> {
+        use crate::log_thread::{TriggerConfig, TriggerReason, ALL_CHANNELS};
+        use control_thread::Command;
+
+        match args {
+            [] => {
+                ctx.command_tx
+                    .send(Command::LogTrigger(TriggerReason::Manual));
+            }
+            ["arm", interval, pre, post, rest @ ..] if rest.len() <= 1 => {
+                let config = TriggerConfig {
+                    pre: pre.parse()?,
+                    post: post.parse()?,
+                    wall_error_limit: match rest.first() {
+                        Some(limit) => Some(limit.parse()?),
+                        None => None,
+                    },
+                };
+                ctx.command_tx.send(Command::StartLogTrigger(
+                    interval.parse()?,
+                    ALL_CHANNELS,
+                    config,
+                ));
+                ctx.wait_response(); // Wait for CommandRequest
+            }
+            ["stop"] => {
+                ctx.command_tx.send(Command::StopLog);
+                ctx.wait_response(); // Wait for CommandRequest
+            }
+            _ => return Err(anyhow::anyhow!("Invalid argument")),
+        }
+
+        Ok(())
+    }
+
+    fn spec(&self) -> &Spec {
+        const SPEC: Spec = Spec {
+            about: &[
+                "Keep the latest records and save them to a run log when the trigger fires.",
+                "If no argument is specified, fire the trigger.",
+            ],
+            forms: &[
+                &[],
+                &[
+                    Arg::word("arm"),
+                    Arg::one("interval", Int),
+                    Arg::one("pre", Int),
+                    Arg::one("post", Int),
+                    Arg::optional("wall_error_limit", Int),
+                ],
+                &[Arg::word("stop")],
+            ],
+        };
+        &SPEC
+    }
+
+    fn name(&self) -> &str {
+        "trigger"
+    }
+}
+
+// Heap allocations in the control cycle (debug builds only)
+struct CmdAlloc {}
+
+impl ConsoleCommand for CmdAlloc {
+    fn execute(&self, args: &[&str], _ctx: &OperationContext) -> anyhow::Result<()> {
+        use crate::alloc_counter;
+
+        if !alloc_counter::ENABLED {
+            return Err(anyhow::anyhow!(
+                "The allocation counter is only in debug builds"
+            ));
+        }
+        match args {
+            [] => {
+                let stats = alloc_counter::stats();
+                uprintln!(
+                    "{} allocations in {} control cycles, last at {}[ms]",
+                    stats.allocations,
+                    stats.flagged_cycles,
+                    stats.last_flagged
+                );
+                reply::set_data(serde_json::json!({
+                    "allocations": stats.allocations,
+                    "flagged_cycles": stats.flagged_cycles,
+                    "last_flagged": stats.last_flagged,
+                }));
+            }
+            ["reset"] => alloc_counter::reset(),
+            _ => return Err(anyhow::anyhow!("Invalid argument")),
+        }
+        Ok(())
+    }
+
+    fn spec(&self) -> &Spec {
+        const SPEC: Spec = Spec {
+            about: &["Show heap allocations in the control cycle."],
+            forms: &[&[], &[Arg::word("reset")]],
+        };
+        &SPEC
+    }
+
+    fn name(&self) -> &str {
+        "alloc"
+    }
+}
+
+// Timing of the control cycle
+struct CmdPerf {}
+
+impl ConsoleCommand for CmdPerf {
+    fn execute(&self, args: &[&str], _ctx: &OperationContext) -> anyhow::Result<()> {
+        use crate::perf;
+
+        match args {
+            [] => {
+                let stats = perf::stats();
+                uprintln!("{:<12}{:>8}{:>8}{:>8}", "[us]", "min", "avg", "max");
+                for (name, phase) in [
+                    ("measure", stats.measure),
+                    ("update", stats.update),
+                    ("controller", stats.controller),
+                    ("log", stats.log),
+                    ("cycle", stats.cycle),
+                ] {
+                    uprintln!(
+                        "{:<12}{:>8}{:>8}{:>8}",
+                        name,
+                        phase.min,
+                        phase.avg,
+                        phase.max
+                    );
+                }
+                uprintln!(
+                    "{} overruns (> {}[us]) in {} cycles",
+                    stats.overruns,
+                    perf::CYCLE_US,
+                    stats.cycles
+                );
+                reply::set_data(serde_json::to_value(stats)?);
+            }
+            ["reset"] => perf::reset(),
+            _ => return Err(anyhow::anyhow!("Invalid argument")),
+        }
+        Ok(())
+    }
+
+    fn spec(&self) -> &Spec {
+        const SPEC: Spec = Spec {
+            about: &[
+                "Show the execution time of each phase of the control cycle.",
+                "The statistics are reset when a log starts.",
+            ],
+            forms: &[&[], &[Arg::word("reset")]],
+        };
+        &SPEC
+    }
+
+    fn name(&self) -> &str {
+        "perf"
+    }
+}
@@ -0,0 +1,53 @@
+// Replies of the console in JSON mode ("mode json").
+//
+// Each command is answered by a single line
+//   {"status":"ok","command":"ls","error":null,"output":["..."],"data":...}
+// followed by PROMPT. "output" is the text the command printed and "data" is the
+// structured result set by the command with `set_data`, or null.
+//
+// Not every line before the reply is JSON. The commands that stream (see
+// ConsoleCommand::streams, e.g. sen and odo) print their text as it comes, and the
+// packets started by telem keep arriving between the replies. Clients skip the lines
+// that do not start with '{' and take the last one that does, as mmctl does.
+
+use serde::Serialize;
+use std::sync::Mutex;
+
+pub const PROMPT: &str = "@> ";
+
+static DATA: Mutex<Option<serde_json::Value>> = Mutex::new(None);
+
+#[derive(Serialize)]
+struct Reply<'a> {
+    status: &'a str,
+    command: &'a str,
+    error: Option<String>,
+    output: Vec<&'a str>,
+    data: Option<serde_json::Value>,
+}
+
+// Called by the commands in both modes. Only JSON mode uses the data.
+pub fn set_data(data: serde_json::Value) {
+    *DATA.lock().unwrap() = Some(data);
+}
+
+// Start a command. The output is captured unless the command streams to the UART.
+pub fn begin(capture: bool) {
+    DATA.lock().unwrap().take();
+    if capture {
+        crate::uart::start_capture();
+    }
+}
+
+pub fn finish(command: &str, result: &anyhow::Result<()>) {
+    let output = crate::uart::end_capture();
+    let reply = Reply {
+        status: if result.is_ok() { "ok" } else { "error" },
+        command,
+        error: result.as_ref().err().map(|e| e.to_string()),
+        output: output.lines().collect(),
+        data: DATA.lock().unwrap().take(),
+    };
+    // Serializing strings and a Value does not fail
+    uprintln!("{}", serde_json::to_string(&reply).unwrap_or_default());
+}
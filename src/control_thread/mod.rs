@@ -1,107 +1,60 @@
 mod motor_control;
+use crate::alloc_counter;
+use crate::config_file;
+use crate::crash_dump;
 use crate::encoder;
 use crate::imu;
 use crate::led::{self, LedColor::*};
 use crate::log_thread;
-use crate::log_thread::LOG_LEN;
 use crate::misc;
 use crate::misc::correct_value;
 use crate::mm_const;
 use crate::ods;
 use crate::ods::MicromouseState;
+use crate::perf;
 use crate::pid;
 use crate::spin_mpsc::{self, SpinReceiver, SpinSender};
 use crate::timer_interrupt::{sync_ms, wait_us};
 use crate::wall_sensor;
+use mm_log::event::EventKind;
 use mm_maze::maze::Wall;
 use motor_control::reset_controller;
+use motor_control::reset_pids;
 use motor_control::turn_back;
 use motor_control::turn_left;
 use motor_control::turn_right;
 use serde::{Deserialize, Serialize};
-use std::fs::File;
-use std::io::prelude::*;
-use std::sync::mpsc::Sender;
 use std::sync::{Arc, Mutex};
 
-#[derive(Debug, Serialize, Deserialize, Default)]
-struct WsConfig {
-    led_rise_time: u32,
+pub const CONFIG_PATH: &str = "/sf/ctrl_cfg.json";
 
-    rs_reference: u16,
-    ls_reference: u16,
-
-    rs_threshold: u16,
-    rf_threshold: u16,
-    lf_threshold: u16,
-    ls_threshold: u16,
-
-    wall_edge_enable: bool,
-    wall_edge_position: f32,
-
-    ls_correction_table: Vec<(u16, f32)>,
-    rs_correction_table: Vec<(u16, f32)>,
-}
-
-#[derive(Debug, Serialize, Deserialize, Default)]
-struct GyroConfig {
-    correction_table: Vec<(i16, f32)>,
-}
-
-#[derive(Debug, Serialize, Deserialize, Default)]
-struct MechanicalParameter {
-    wheel_diameter: f32,
-    gear_ratio: f32,
-}
-
-#[derive(Debug, Serialize, Deserialize, Default)]
-struct BatteryConfig {
-    correction_table: Vec<(i16, f32)>,
-}
-
-#[derive(Debug, Serialize, Deserialize, Default)]
-struct SpeedConfig {
-    velocity: f32,
-    acceleration: f32,
-    deceleration: f32,
-}
-
-#[derive(Debug, Serialize, Deserialize, Default)]
-struct ControlThreadConfig {
-    ws_cfg: WsConfig,
-    gyro_cfg: GyroConfig,
-    mech_param: MechanicalParameter,
-    battery_cfg: BatteryConfig,
-
-    search_ctrl_cfg: SearchControlConfig,
-
-    judge_position: f32,
-}
-
-#[derive(Debug, Serialize, Deserialize, Default)]
-struct SearchControlConfig {
-    vel_fwd: f32,
-    theta_pid: pid::PidParameter,
-    omega_pid: pid::PidParameter,
-    v_pid: pid::PidParameter,
-    pos_pid: pid::PidParameter,
-    wall_pid: pid::PidParameter,
-}
+pub use mm_config::ctrl::ControlThreadConfig;
 
 struct LogInfo {
     interval: u8,
+    channels: u64,
+    record_size: usize,
     counter: u8,
-    is_full: bool,
     on_logging: bool,
+    capture: Option<Capture>, // Some in the capture mode
+}
+
+// State of the pre-trigger capture
+struct Capture {
+    config: log_thread::TriggerConfig,
+    trigger: Option<log_thread::TriggerReason>,
+    post_count: u16, // Records after the trigger
 }
 
 impl LogInfo {
     fn new() -> Self {
         Self {
             interval: 0,
+            channels: log_thread::ALL_CHANNELS,
+            record_size: log_thread::record_size(log_thread::ALL_CHANNELS),
             counter: 0,
-            is_full: false,
             on_logging: false,
+            capture: None,
         }
     }
 }
@@ -131,13 +84,14 @@ struct ControlContext {
     ods: Arc<Mutex<ods::Ods>>,
 
     #[allow(unused)]
-    log_tx: Sender<log_thread::LogCommand>,
+    log_tx: SpinSender<log_thread::LogCommand>,
     log_info: LogInfo,
 
     response_tx: SpinSender<Response>,
     command_rx: SpinReceiver<Command>,
 
     config: ControlThreadConfig,
+    config_valid: bool, // The motors are not armed with an invalid configuration
 
     ws_ena: bool,
     ws_step: WsStep,
@@ -166,10 +120,11 @@ struct ControlContext {
 impl ControlContext {
     fn new(
         ods: Arc<Mutex<ods::Ods>>,
-        log_tx: Sender<log_thread::LogCommand>,
+        log_tx: SpinSender<log_thread::LogCommand>,
         response_tx: SpinSender<Response>,
         command_rx: SpinReceiver<Command>,
         config: ControlThreadConfig,
+        config_valid: bool,
     ) -> Self {
         Self {
             ods,
@@ -178,6 +133,7 @@ impl ControlContext {
             response_tx,
             command_rx,
             config,
+            config_valid,
             ws_ena: false,
             ws_step: WsStep::Side,
             ls_ref: 0,
@@ -195,43 +151,200 @@ impl ControlContext {
             turn_back_direction: TurnBackDirection::Left,
         }
     }
-    pub fn start_log(&mut self, interval: u8) {
+    pub fn start_log(&mut self, interval: u8, channels: u64) {
+        // A capture in progress is finished as by StopLog
+        self.save_capture();
+        self.wait_saved();
+        self.log_info.counter = 0;
+        self.log_info.interval = interval;
+        self.log_info.channels = channels;
+        self.log_info.record_size = log_thread::record_size(channels);
+        self.log_info.on_logging = true;
+        self.log_info.capture = None;
+        perf::reset();
+        {
+            let mut ods = self.ods.lock().unwrap();
+            ods.log.filling.clear();
+            ods.log.dropped = 0;
+        }
+        self.log_tx
+            .send(log_thread::LogCommand::Start(interval, channels));
+    }
+
+    // Keep the latest records in a ring buffer and save them when a trigger fires
+    pub fn start_capture(
+        &mut self,
+        interval: u8,
+        channels: u64,
+        config: log_thread::TriggerConfig,
+    ) {
+        // The ring of the previous capture may not have been taken by the log thread yet
+        self.save_capture();
+        self.wait_saved();
+        let record_size = log_thread::record_size(channels);
+        let max = log_thread::LOG_RING_SIZE / record_size;
+        let post = (config.post as usize).min(max);
+        let pre = (config.pre as usize).min(max - post);
+        if pre != config.pre as usize || post != config.post as usize {
+            log::warn!("Capture is limited to pre {} post {}", pre, post);
+        }
+
         self.log_info.counter = 0;
-        self.log_info.is_full = false;
         self.log_info.interval = interval;
+        self.log_info.channels = channels;
+        self.log_info.record_size = record_size;
         self.log_info.on_logging = true;
-        self.ods.lock().unwrap().log.clear();
+        self.log_info.capture = Some(Capture {
+            config: log_thread::TriggerConfig {
+                pre: pre as u16,
+                post: post as u16,
+                ..config
+            },
+            trigger: None,
+            post_count: 0,
+        });
+        perf::reset();
+        // Records are pushed once the log thread has put the ring in ODS
+        self.log_tx.send(log_thread::LogCommand::AllocRing(
+            (pre + post) as u32,
+            channels,
+        ));
+    }
+
+    // Fire the trigger of the capture mode. Ignored if it has already fired.
+    pub fn trigger(&mut self, reason: log_thread::TriggerReason) {
+        let capture = match self.log_info.capture.as_mut() {
+            Some(c) if c.trigger.is_none() => c,
+            _ => return,
+        };
+        capture.trigger = Some(reason);
+        capture.post_count = 0;
+        let post = capture.config.post;
+        self.event(EventKind::Trigger, reason as u8, 0.0);
+        if post == 0 {
+            self.save_capture();
+        }
+    }
+
+    fn save_capture(&mut self) {
+        let capture = match self.log_info.capture.take() {
+            Some(c) => c,
+            None => return,
+        };
+        self.log_info.on_logging = false;
+        let reason = match capture.trigger {
+            Some(r) => r,
+            None => {
+                // Nothing happened. Discard the records.
+                self.ods.lock().unwrap().log.saving = true;
+                self.log_tx.send(log_thread::LogCommand::DiscardCapture);
+                return;
+            }
+        };
+        let len = {
+            let mut ods = self.ods.lock().unwrap();
+            ods.log.saving = true;
+            ods.log.ring.as_ref().map_or(0, |ring| ring.len())
+        };
+        let pre = len.saturating_sub(capture.post_count as usize);
+        self.log_tx.send(log_thread::LogCommand::SaveCapture(
+            self.log_info.interval,
+            self.log_info.channels,
+            reason,
+            pre as u32,
+        ));
+    }
+
+    fn log_capture(&mut self) {
+        let wall_error = {
+            let mut ods = self.ods.lock().unwrap();
+            let mut log = std::mem::take(&mut ods.log);
+            if let Some(ring) = log.ring.as_mut() {
+                ring.push(&ods, self.log_info.channels);
+            }
+            ods.log = log;
+            ods.micromouse.wall_error
+        };
+
+        let capture = match self.log_info.capture.as_mut() {
+            Some(c) => c,
+            None => return,
+        };
+        if capture.trigger.is_some() {
+            capture.post_count += 1;
+            if capture.post_count >= capture.config.post {
+                self.save_capture();
+            }
+        } else if let Some(limit) = capture.config.wall_error_limit {
+            if wall_error.unsigned_abs() > limit {
+                self.trigger(log_thread::TriggerReason::WallError);
+            }
+        }
     }
 
     pub fn log(&mut self) {
-        if self.log_info.is_full || !self.log_info.on_logging {
+        if !self.log_info.on_logging {
             return;
         }
+        let _timer = perf::Timer::start(perf::Phase::Log);
         self.log_info.counter += 1;
         if self.log_info.counter >= self.log_info.interval {
-            let mut ods = self.ods.lock().unwrap();
             self.log_info.counter = 0;
-            let micromouse = ods.micromouse.clone();
-            ods.log.push(micromouse);
-            if ods.log.len() >= LOG_LEN {
-                self.log_info.is_full = true;
+            if self.log_info.capture.is_some() {
+                self.log_capture();
+                return;
+            }
+            let mut ods = self.ods.lock().unwrap();
+            if ods.log.filling.len() + self.log_info.record_size > log_thread::LOG_CHUNK_SIZE {
+                // Hand the chunk over to the log thread and continue with the other one
+                if ods.log.swap() {
+                    self.log_tx.send(log_thread::LogCommand::Flush);
+                } else {
+                    // The log thread has not finished writing the other chunk yet
+                    ods.log.dropped += 1;
+                    return;
+                }
             }
+            // Take the buffer out so that the rest of ODS can be read while appending.
+            // This does not allocate.
+            let mut log = std::mem::take(&mut ods.log);
+            log_thread::encode(&ods, self.log_info.channels, &mut log.filling);
+            ods.log = log;
         }
     }
 
-    pub fn log_msg(&mut self, msg: String) {
-        let mut ods = self.ods.lock().unwrap();
-        let (min, sec, ms, us) = crate::timer_interrupt::get_time();
-        let msg = format!("[{:02}:{:02}:{:03}:{:03}] {}", min, sec, ms, us, msg);
-        ods.log_msg.push(msg);
-        while ods.log_msg.len() >= log_thread::LOG_MSG_LEN {
-            ods.log_msg.remove(0);
-        }
+    pub fn event(&mut self, kind: EventKind, code: u8, value: f32) {
+        self.ods.lock().unwrap().event(kind, code, value);
     }
 
     pub fn stop_log(&mut self) {
+        if !self.log_info.on_logging {
+            return;
+        }
+        if self.log_info.capture.is_some() {
+            // Save what has been recorded after the trigger, or discard if it has not fired
+            self.save_capture();
+            return;
+        }
         self.log_info.on_logging = false;
-        let _ = self.log_tx.send(log_thread::LogCommand::Save);
+        self.ods.lock().unwrap().log.saving = true;
+        self.log_tx.send(log_thread::LogCommand::Save);
+    }
+
+    // Wait until the log thread has saved the previous run. Otherwise the next run would clear
+    // the records which have not been written yet. Sensors are measured while waiting.
+    fn wait_saved(&mut self) {
+        let mut failed = false;
+        while self.ods.lock().unwrap().log.saving {
+            // Failures are left to the next motion, which measures with `?`. Logged once.
+            if let Err(e) = measure(self) {
+                if !failed {
+                    log::warn!("Measurement failed while waiting for the log: {:?}", e);
+                    failed = true;
+                }
+            }
+            sync_ms();
+        }
     }
 
     pub fn set_ws_enable(&mut self, ena: bool) {
@@ -247,17 +360,22 @@ impl ControlContext {
     }
 
     pub fn request_command(&mut self) {
-        self.log_msg(format!("Req cmd({})", self.req_id));
+        self.event(EventKind::Request, 0, self.req_id as f32);
         self.response_tx.send(Response::CommandRequest(self.req_id));
+        crash_dump::set_req_id(self.req_id);
         self.req_id += 1;
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
 pub enum Command {
     GyroCalibration,
-    StartLog(u8), // The argument is the interval of logging
+    StartLog(u8),              // The argument is the interval of logging
+    StartLogChannels(u8, u64), // The arguments are the interval of logging and the channel mask
     StopLog,
+    // Start the capture mode. The arguments are the interval of logging, the channel mask and the trigger settings.
+    StartLogTrigger(u8, u64, log_thread::TriggerConfig),
+    LogTrigger(log_thread::TriggerReason), // Fire the trigger of the capture mode
     SetActivateWallSensor(bool),
     ResetController,
     SStart(f32),
@@ -268,6 +386,8 @@ pub enum Command {
     SReturn,
     SPivot(f32), // The arguments are the angle
     Test,
+    // Replace the configuration read at boot. Applied between motions, answered by ConfigReloaded.
+    ReloadConfig(Box<ControlThreadConfig>),
 }
 
 impl Default for Command {
@@ -276,13 +396,74 @@ impl Default for Command {
     }
 }
 
+// Names of the commands in the event log, indexed by Command::code()
+pub const COMMAND_NAMES: [&str; 17] = [
+    "GyroCalibration",
+    "StartLog",
+    "StartLogChannels",
+    "StopLog",
+    "StartLogTrigger",
+    "LogTrigger",
+    "SetActivateWallSensor",
+    "ResetController",
+    "SStart",
+    "SForward",
+    "SStop",
+    "SRight",
+    "SLeft",
+    "SReturn",
+    "SPivot",
+    "Test",
+    "ReloadConfig",
+];
+
+impl Command {
+    pub fn code(&self) -> u8 {
+        match self {
+            Command::GyroCalibration => 0,
+            Command::StartLog(_) => 1,
+            Command::StartLogChannels(..) => 2,
+            Command::StopLog => 3,
+            Command::StartLogTrigger(..) => 4,
+            Command::LogTrigger(_) => 5,
+            Command::SetActivateWallSensor(_) => 6,
+            Command::ResetController => 7,
+            Command::SStart(_) => 8,
+            Command::SForward => 9,
+            Command::SStop => 10,
+            Command::SRight => 11,
+            Command::SLeft => 12,
+            Command::SReturn => 13,
+            Command::SPivot(_) => 14,
+            Command::Test => 15,
+            Command::ReloadConfig(_) => 16,
+        }
+    }
+
+    // The argument recorded in the event log
+    pub fn value(&self) -> f32 {
+        match self {
+            Command::StartLog(interval)
+            | Command::StartLogChannels(interval, _)
+            | Command::StartLogTrigger(interval, ..) => *interval as f32,
+            Command::LogTrigger(reason) => *reason as u8 as f32,
+            Command::SetActivateWallSensor(ena) => *ena as u8 as f32,
+            Command::SStart(distance) => *distance,
+            Command::SPivot(angle) => *angle,
+            _ => 0.0,
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum Response {
     CalibrationDone(f32),
     CommandRequest(u16),
+    ConfigReloaded(bool), // False if the configuration was refused
 }
 
 fn measure(ctx: &mut ControlContext) -> anyhow::Result<()> {
+    let _timer = perf::Timer::start(perf::Phase::Measure);
     let batt = ctx.batt_ave.update(wall_sensor::read_batt()?.into()) as u16;
     let batt_phy = correct_value(
         &ctx.config.battery_cfg.correction_table.as_slice(),
@@ -420,6 +601,7 @@ fn reset_micromouse_state(ctx: &mut ControlContext) {
 }
 
 fn update(ctx: &mut ControlContext) -> MicromouseState {
+    let _timer = perf::Timer::start(perf::Phase::Update);
     let mut ods = ctx.ods.lock().unwrap();
     let current_time = crate::timer_interrupt::get_ms();
     let delta_step = if ctx.previous_time == 0 {
@@ -473,15 +655,23 @@ fn update(ctx: &mut ControlContext) -> MicromouseState {
     ods.micromouse.delta_step = delta_step;
     ods.micromouse.wall_error = 0;
 
-    ods.micromouse.clone()
+    ods.micromouse
 }
 
 fn set_motor_duty(ctx: &ControlContext, duty_l: f32, duty_r: f32) {
     crate::motor::set_l(duty_l);
     crate::motor::set_r(duty_r);
+    // The controller runs between update() and here
+    perf::record_from_mark(perf::Phase::Controller);
     let mut ods = ctx.ods.lock().unwrap();
     ods.micromouse.duty_l = duty_l;
     ods.micromouse.duty_r = duty_r;
+
+    // The duty is set once per control cycle after all controllers are updated.
+    ods.pid.v = ctx.v_pid.get_terms();
+    ods.pid.omega = ctx.omega_pid.get_terms();
+    ods.pid.theta = ctx.theta_pid.get_terms();
+    ods.pid.wall = ctx.wall_pid.get_terms();
 }
 
 fn gyro_calibration(ctx: &mut ControlContext) {
@@ -503,7 +693,7 @@ fn gyro_calibration(ctx: &mut ControlContext) {
 
 pub fn init(
     ods: &Arc<Mutex<ods::Ods>>,
-    log_tx: Sender<log_thread::LogCommand>,
+    log_tx: SpinSender<log_thread::LogCommand>,
 ) -> anyhow::Result<(
     SpinSender<Command>,
     SpinReceiver<Response>,
@@ -514,16 +704,17 @@ pub fn init(
     let (tx, rx_for_ope): (SpinSender<Response>, SpinReceiver<Response>) = spin_mpsc::channel();
     let mut config_success = Ok(());
 
-    fn read() -> anyhow::Result<ControlThreadConfig> {
-        let mut f = File::open("/sf/ctrl_cfg.json")?;
-        let mut contents = String::new();
-        f.read_to_string(&mut contents)?;
-        let result = serde_json::from_str(&contents)?;
-        return Ok(result);
-    }
-
-    let config = match read() {
-        Ok(c) => c,
+    // An invalid file is still used with its defaults for the console, but the motors are not armed
+    let config = match config_file::read(CONFIG_PATH, mm_config::migrate::ctrl) {
+        Ok(value) => {
+            let (config, problems) = ControlThreadConfig::load(&value);
+            if !problems.is_empty() {
+                let problems = mm_config::Problems(problems);
+                println!("❌Invalid config:\n{}", problems);
+                config_success = Err(problems.into());
+            }
+            config
+        }
         Err(e) => {
             println!("❌Failed to read config: {:?}", e);
             config_success = Err(e);
@@ -532,8 +723,10 @@ pub fn init(
     };
 
     println!("{:?}", config);
+    log_thread::set_ctrl_cfg(&config);
 
-    let mut ctx = ControlContext::new(ods.clone(), log_tx, tx, rx, config);
+    let config_valid = config_success.is_ok();
+    let mut ctx = ControlContext::new(ods.clone(), log_tx, tx, rx, config, config_valid);
 
     // Spawn the control thread
     esp_idf_hal::task::thread::ThreadSpawnConfiguration {
@@ -547,81 +740,112 @@ pub fn init(
 
     std::thread::Builder::new().spawn(move || -> anyhow::Result<()> {
         wall_sensor::off()?;
+        alloc_counter::register_control_thread();
         loop {
             match ctx.command_rx.try_recv() {
-                Some(cmd) => match cmd {
-                    Command::GyroCalibration => {
-                        gyro_calibration(&mut ctx);
-                    }
-                    Command::StartLog(interval) => {
-                        ctx.log_msg("StartLog".to_string());
-                        ctx.start_log(interval);
-                        ctx.log_msg("StartLog done".to_string());
-                        ctx.request_command();
-                    }
-                    Command::StopLog => {
-                        ctx.log_msg("StopLog".to_string());
-                        ctx.stop_log();
-                        ctx.log_msg("StopLog done".to_string());
-                        ctx.request_command();
-                    }
-                    Command::SetActivateWallSensor(ena) => {
-                        ctx.set_ws_enable(ena);
-                    }
-                    Command::ResetController => {
-                        ctx.log_msg("ResetController".to_string());
-                        reset_controller(&mut ctx);
-                        ctx.log_msg("ResetController done".to_string());
-                        ctx.request_command();
-                    }
-                    Command::SStart(distance) => {
-                        ctx.log_msg(format!("SStart({})", distance));
-                        ctx.set_ws_enable(true);
-                        motor_control::start(&mut ctx, distance).unwrap();
-                        ctx.log_msg("SStart done".to_string());
-                    }
-                    Command::SForward => {
-                        ctx.log_msg("SForward".to_string());
-                        motor_control::forward(&mut ctx, mm_const::BLOCK_LENGTH).unwrap();
-                        ctx.log_msg("SForward done".to_string());
-                    }
-                    Command::SStop => {
-                        ctx.log_msg("SStop".to_string());
-                        motor_control::stop(&mut ctx, mm_const::BLOCK_LENGTH / 2.0, true).unwrap();
-                        ctx.log_msg("SStop done".to_string());
-                    }
-                    Command::SRight => {
-                        ctx.log_msg("SRight".to_string());
-                        turn_right(&mut ctx).unwrap();
-                        ctx.log_msg("SRight done".to_string());
-                    }
-                    Command::SLeft => {
-                        ctx.log_msg("SLeft".to_string());
-                        turn_left(&mut ctx).unwrap();
-                        ctx.log_msg("SLeft done".to_string());
-                    }
-                    Command::SReturn => {
-                        ctx.log_msg("SReturn".to_string());
-                        turn_back(&mut ctx).unwrap();
-                        ctx.log_msg("SReturn done".to_string());
-                    }
-                    Command::SPivot(angle) => {
-                        ctx.log_msg(format!("SPivot({})", angle));
-                        motor_control::pivot(&mut ctx, angle, angle / std::f32::consts::PI / 2.0)
+                Some(cmd) => {
+                    let (code, value) = (cmd.code(), cmd.value());
+                    ctx.event(EventKind::CommandReceived, code, value);
+                    crash_dump::set_command(code);
+                    match cmd {
+                        Command::GyroCalibration => {
+                            gyro_calibration(&mut ctx);
+                        }
+                        Command::StartLog(interval) => {
+                            ctx.start_log(interval, log_thread::ALL_CHANNELS);
+                            ctx.request_command();
+                        }
+                        Command::StartLogChannels(interval, channels) => {
+                            ctx.start_log(interval, channels);
+                            ctx.request_command();
+                        }
+                        Command::StopLog => {
+                            ctx.stop_log();
+                            ctx.request_command();
+                        }
+                        Command::StartLogTrigger(interval, channels, config) => {
+                            ctx.start_capture(interval, channels, config);
+                            ctx.request_command();
+                        }
+                        Command::LogTrigger(reason) => {
+                            if reason == log_thread::TriggerReason::Fault {
+                                ctx.event(EventKind::Fault, 0, 0.0);
+                                // The write to FRAM blocks, so the log thread does it
+                                ctx.log_tx.send(log_thread::LogCommand::CrashDump(
+                                    crash_dump::Moment::now(),
+                                ));
+                            }
+                            ctx.trigger(reason);
+                        }
+                        Command::SetActivateWallSensor(ena) => {
+                            ctx.set_ws_enable(ena);
+                        }
+                        Command::ResetController => {
+                            reset_controller(&mut ctx);
+                            ctx.request_command();
+                        }
+                        Command::SStart(distance) => {
+                            ctx.set_ws_enable(true);
+                            motor_control::start(&mut ctx, distance).unwrap();
+                        }
+                        Command::SForward => {
+                            motor_control::forward(&mut ctx, mm_const::BLOCK_LENGTH).unwrap();
+                        }
+                        Command::SStop => {
+                            motor_control::stop(&mut ctx, mm_const::BLOCK_LENGTH / 2.0, true)
+                                .unwrap();
+                        }
+                        Command::SRight => {
+                            turn_right(&mut ctx).unwrap();
+                        }
+                        Command::SLeft => {
+                            turn_left(&mut ctx).unwrap();
+                        }
+                        Command::SReturn => {
+                            turn_back(&mut ctx).unwrap();
+                        }
+                        Command::SPivot(angle) => {
+                            motor_control::pivot(
+                                &mut ctx,
+                                angle,
+                                angle / std::f32::consts::PI / 2.0,
+                            )
                             .unwrap();
-                        ctx.log_msg("SPivot done".to_string());
-                        ctx.request_command();
-                    }
-                    Command::Test => {
-                        ctx.log_msg("Test".to_string());
-                        motor_control::test(&mut ctx).unwrap();
-                        ctx.log_msg("Test done".to_string());
+                            ctx.request_command();
+                        }
+                        Command::Test => {
+                            motor_control::test(&mut ctx).unwrap();
+                        }
+                        Command::ReloadConfig(config) => {
+                            // Checking the config allocates. It is sent only between motions.
+                            let reloaded = alloc_counter::allow(|| {
+                                let problems = config.validate();
+                                if !problems.is_empty() {
+                                    log::warn!(
+                                        "Config refused:\n{}",
+                                        mm_config::Problems(problems)
+                                    );
+                                    return false;
+                                }
+                                ctx.config = *config;
+                                ctx.config_valid = true;
+                                reset_pids(&mut ctx);
+                                true
+                            });
+                            ctx.response_tx.send(Response::ConfigReloaded(reloaded));
+                        }
                     }
-                },
+                    ctx.event(EventKind::CommandDone, code, value);
+                    crash_dump::set_command(crash_dump::NO_COMMAND);
+                }
                 None => {}
             }
             measure(&mut ctx)?;
             update(&mut ctx);
+            // Between motions only the capture mode records, to keep the records before a trigger
+            if ctx.log_info.capture.is_some() {
+                ctx.log();
+            }
             sync_ms()
         }
     })?;
@@ -8,6 +8,7 @@ use crate::motor;
 use crate::ods::MicromouseState;
 use crate::pid;
 use crate::timer_interrupt::{self, sync_ms};
+use mm_log::event::EventKind;
 
 use super::TurnBackDirection;
 
@@ -195,6 +196,8 @@ fn go(
         {
             let mut ods = ctx.ods.lock().unwrap();
             ods.micromouse.target_v = target_v;
+            ods.micromouse.target_omega = 0.0;
+            ods.micromouse.target_theta = std::f32::consts::PI / 2.0;
         }
         control_thread::measure(ctx)?;
         let micromouse = control_thread::update(ctx);
@@ -207,12 +210,14 @@ fn go(
             enable_wall_edge_l = false;
             current_position = ctx.config.ws_cfg.wall_edge_position;
             led::on(Blue)?;
+            ctx.event(EventKind::WallEdge, 1, current_position);
         }
         if (enable_wall_edge_l) && (micromouse.y > 0.035) && (micromouse.ls < ctx.ls_ref / 3) {
             enable_wall_edge_r = false;
             enable_wall_edge_l = false;
             current_position = ctx.config.ws_cfg.wall_edge_position;
             led::on(Blue)?;
+            ctx.event(EventKind::WallEdge, 0, current_position);
         }
 
         let fb_v = ctx.v_pid.update(target_v - micromouse.v);
@@ -243,16 +248,23 @@ fn go(
         };
 
         // Update MicromouseState
-        {
+        let position_reset = {
             let mut ods = ctx.ods.lock().unwrap();
             ods.micromouse.y = current_position;
             ods.micromouse.wall_error = ws_error.unwrap_or(0);
 
             if ctx.position_reset_count > 500 {
+                let x = ods.micromouse.x;
                 ods.micromouse.x = mm_const::BLOCK_LENGTH / 2.0;
                 ods.micromouse.theta = std::f32::consts::PI / 2.0;
                 ctx.position_reset_count = 0;
+                Some(x)
+            } else {
+                None
             }
+        };
+        if let Some(x) = position_reset {
+            ctx.event(EventKind::PositionReset, 0, x);
         }
 
         // Angle feedback
@@ -325,12 +337,18 @@ pub(super) fn start(ctx: &mut ControlContext, distance: f32) -> anyhow::Result<(
     Ok(())
 }
 
-pub(super) fn reset_controller(ctx: &mut ControlContext) {
+// Build the PIDs from the configuration. Also used when the configuration is reloaded,
+// without the wall references and the motors of reset_controller.
+pub(super) fn reset_pids(ctx: &mut ControlContext) {
     ctx.theta_pid = pid::Pid::new(&ctx.config.search_ctrl_cfg.theta_pid);
     ctx.omega_pid = pid::Pid::new(&ctx.config.search_ctrl_cfg.omega_pid);
     ctx.v_pid = pid::Pid::new(&ctx.config.search_ctrl_cfg.v_pid);
     ctx.pos_pid = pid::Pid::new(&ctx.config.search_ctrl_cfg.pos_pid);
     ctx.wall_pid = pid::Pid::new(&ctx.config.search_ctrl_cfg.wall_pid);
+}
+
+pub(super) fn reset_controller(ctx: &mut ControlContext) {
+    reset_pids(ctx);
 
     ctx.ws_ena = true;
     let mut ls: i32 = 0;
@@ -348,12 +366,17 @@ pub(super) fn reset_controller(ctx: &mut ControlContext) {
     ctx.ls_ref = (ls / 100) as u16;
     ctx.rs_ref = (rs / 100) as u16;
 
-    ctx.log_msg(format!("ls_ref: {}, rs_ref: {}", ctx.ls_ref, ctx.rs_ref));
+    ctx.event(EventKind::WallReference, 0, ctx.ls_ref as f32);
+    ctx.event(EventKind::WallReference, 1, ctx.rs_ref as f32);
 
     control_thread::reset_micromouse_state(ctx);
     motor::set_l(0.0);
     motor::set_r(0.0);
-    motor::enable(true);
+    if ctx.config_valid {
+        motor::enable(true);
+    } else {
+        log::error!("The motors are not armed with an invalid config");
+    }
     timer_interrupt::sync_ms();
 }
 
@@ -442,6 +465,13 @@ pub(super) fn pivot(ctx: &mut ControlContext, angle: f32, duration: f32) -> anyh
             target_theta = original_angle + target_omega * time;
         };
 
+        {
+            let mut ods = ctx.ods.lock().unwrap();
+            ods.micromouse.target_v = 0.0;
+            ods.micromouse.target_omega = target_omega;
+            ods.micromouse.target_theta = target_theta;
+        }
+
         let fb_theta = ctx.theta_pid.update(target_theta - micromouse.theta);
         let fb_omega = ctx.omega_pid.update(target_omega - micromouse.omega);
 
@@ -476,6 +506,13 @@ pub(super) fn nop(ctx: &mut ControlContext, duration: f32) -> anyhow::Result<()>
     let target_theta = std::f32::consts::PI / 2.0;
     let target_omega = 0.0;
 
+    {
+        let mut ods = ctx.ods.lock().unwrap();
+        ods.micromouse.target_v = 0.0;
+        ods.micromouse.target_omega = target_omega;
+        ods.micromouse.target_theta = target_theta;
+    }
+
     while time < total_duration {
         control_thread::measure(ctx)?;
         let micromouse = control_thread::update(ctx);
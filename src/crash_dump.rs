@@ -0,0 +1,384 @@
+// Binary crash record in FRAM.
+// It is written by the panic handler and on a fault, and the `crash` command shows it after reboot.
+//
+// Layout (all values are little endian):
+//   magic    "MMCD"
+//   version  u8
+//   length   u16, of the body
+//   body     see CrashDump::encode
+//   crc      u16, CRC16/XMODEM of the body
+
+use crate::fram_logger;
+use crate::ods::{self, MicromouseState};
+use crate::timer_interrupt;
+use esp_idf_hal::delay::FreeRtos;
+use mm_log::event::{self, Event};
+use mm_log::ring::Storage;
+use mm_maze::maze::Wall;
+use std::sync::atomic::{AtomicU16, AtomicU32, AtomicU8, Ordering::Relaxed};
+use std::sync::{Arc, Mutex, TryLockError};
+
+const ADDRESS: u32 = fram_logger::DUMP_ADDRESS;
+const MAGIC: [u8; 4] = *b"MMCD";
+const VERSION: u8 = 1;
+const HEADER_SIZE: usize = 7;
+const MAX_SIZE: usize = 1024; // Of the body
+const MAX_EVENTS: usize = 32;
+const MAX_MESSAGE: usize = 200; // [bytes]
+const LOCK_TIMEOUT: u32 = 20; // [ms]
+const _: () = assert!((HEADER_SIZE + MAX_SIZE + 2) as u32 <= fram_logger::DUMP_SIZE);
+
+pub const NO_COMMAND: u8 = 0xff;
+
+static mut ODS: Option<Arc<Mutex<ods::Ods>>> = None;
+static BOOT_COUNT: AtomicU32 = AtomicU32::new(0);
+static COMMAND: AtomicU8 = AtomicU8::new(NO_COMMAND); // Code of the command in progress
+static REQ_ID: AtomicU16 = AtomicU16::new(0); // The last request sent by the control thread
+
+// When the crash happened. The dump of a fault is written later by the log thread.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Moment {
+    pub time: u32, // [ms]
+    pub command: u8,
+    pub req_id: u16,
+}
+
+impl Moment {
+    pub fn now() -> Self {
+        Moment {
+            time: timer_interrupt::get_ms(),
+            command: COMMAND.load(Relaxed),
+            req_id: REQ_ID.load(Relaxed),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Reason {
+    Panic,
+    Fault,
+}
+
+// ODS values at the crash
+pub struct Snapshot {
+    pub micromouse: MicromouseState,
+    pub encoder: ods::OdsEncoder,
+    pub gyro_x_raw: i16,
+    pub batt_raw: u16,
+    pub batt_phy: f32,
+    pub events: Vec<Event>, // The latest events, oldest first
+}
+
+pub struct CrashDump {
+    pub reason: Reason,
+    pub boot_count: u32,
+    pub time: u32, // [ms]
+    pub command: u8,
+    pub req_id: u16,
+    pub message: String,
+    pub snapshot: Option<Snapshot>, // None if the ODS could not be locked
+}
+
+pub fn init(ods: &Arc<Mutex<ods::Ods>>, boot_count: u32) {
+    unsafe {
+        ODS = Some(ods.clone());
+    }
+    BOOT_COUNT.store(boot_count, Relaxed);
+}
+
+// Called by the control thread when a command starts and ends
+pub fn set_command(code: u8) {
+    COMMAND.store(code, Relaxed);
+}
+
+pub fn set_req_id(req_id: u16) {
+    REQ_ID.store(req_id, Relaxed);
+}
+
+// The crashed thread may hold the lock of the ODS, so give up after LOCK_TIMEOUT.
+fn snapshot() -> Option<Snapshot> {
+    let ods = unsafe { ODS.as_ref()? };
+    for _ in 0..LOCK_TIMEOUT {
+        let ods = match ods.try_lock() {
+            Ok(ods) => ods,
+            Err(TryLockError::Poisoned(e)) => e.into_inner(),
+            Err(TryLockError::WouldBlock) => {
+                FreeRtos::delay_ms(1);
+                continue;
+            }
+        };
+        let skip = ods.events.len().saturating_sub(MAX_EVENTS);
+        return Some(Snapshot {
+            micromouse: ods.micromouse,
+            encoder: ods.encoder,
+            gyro_x_raw: ods.imu.gyro_x_raw,
+            batt_raw: ods.wall_sensor.batt_raw,
+            batt_phy: ods.wall_sensor.batt_phy,
+            events: ods.events.iter().skip(skip).copied().collect(),
+        });
+    }
+    None
+}
+
+pub fn save(reason: Reason, message: &str) -> anyhow::Result<()> {
+    save_at(reason, Moment::now(), message)
+}
+
+pub fn save_at(reason: Reason, moment: Moment, message: &str) -> anyhow::Result<()> {
+    let dump = CrashDump {
+        reason,
+        boot_count: BOOT_COUNT.load(Relaxed),
+        time: moment.time,
+        command: moment.command,
+        req_id: moment.req_id,
+        message: message.to_string(),
+        snapshot: snapshot(),
+    };
+    let body = dump.encode();
+
+    let mut data = Vec::with_capacity(HEADER_SIZE + body.len() + 2);
+    data.extend_from_slice(&MAGIC);
+    data.push(VERSION);
+    data.extend_from_slice(&(body.len() as u16).to_le_bytes());
+    data.extend_from_slice(&body);
+    data.extend_from_slice(&crc16::State::<crc16::XMODEM>::calculate(&body).to_le_bytes());
+    fram_logger::with_storage(|fram| fram.write(ADDRESS, &data))
+}
+
+// The last dump, None if there is none
+pub fn load() -> anyhow::Result<Option<CrashDump>> {
+    let mut header = [0; HEADER_SIZE];
+    fram_logger::with_storage(|fram| fram.read(ADDRESS, &mut header))?;
+    if header[..4] != MAGIC {
+        return Ok(None);
+    }
+    if header[4] != VERSION {
+        return Err(anyhow::anyhow!("Unknown crash dump version {}", header[4]));
+    }
+    let len = u16::from_le_bytes([header[5], header[6]]) as usize;
+    if len > MAX_SIZE {
+        return Err(anyhow::anyhow!("Broken crash dump (length {})", len));
+    }
+
+    let mut data = vec![0; len + 2];
+    fram_logger::with_storage(|fram| fram.read(ADDRESS + HEADER_SIZE as u32, &mut data))?;
+    let crc = u16::from_le_bytes([data[len], data[len + 1]]);
+    if crc != crc16::State::<crc16::XMODEM>::calculate(&data[..len]) {
+        return Err(anyhow::anyhow!("Broken crash dump (CRC error)"));
+    }
+    Ok(Some(CrashDump::decode(&data[..len])?))
+}
+
+pub fn clear() -> anyhow::Result<()> {
+    fram_logger::with_storage(|fram| fram.write(ADDRESS, &[0; 4]))
+}
+
+impl CrashDump {
+    // reason u8, boot_count u32, time u32, command u8, req_id u16,
+    // message length u8, message, snapshot flag u8, snapshot (see below)
+    fn encode(&self) -> Vec<u8> {
+        let mut b = Vec::with_capacity(MAX_SIZE);
+        b.push(self.reason as u8);
+        b.extend_from_slice(&self.boot_count.to_le_bytes());
+        b.extend_from_slice(&self.time.to_le_bytes());
+        b.push(self.command);
+        b.extend_from_slice(&self.req_id.to_le_bytes());
+
+        let mut len = self.message.len().min(MAX_MESSAGE);
+        while !self.message.is_char_boundary(len) {
+            len -= 1;
+        }
+        b.push(len as u8);
+        b.extend_from_slice(&self.message.as_bytes()[..len]);
+
+        match &self.snapshot {
+            Some(s) => {
+                b.push(1);
+                s.encode(&mut b);
+            }
+            None => b.push(0),
+        }
+        b
+    }
+
+    fn decode(data: &[u8]) -> anyhow::Result<Self> {
+        let mut r = Reader { data, pos: 0 };
+        let reason = match r.u8()? {
+            0 => Reason::Panic,
+            _ => Reason::Fault,
+        };
+        let boot_count = r.u32()?;
+        let time = r.u32()?;
+        let command = r.u8()?;
+        let req_id = r.u16()?;
+        let len = r.u8()? as usize;
+        let message = String::from_utf8_lossy(r.bytes(len)?).to_string();
+        let snapshot = match r.u8()? {
+            0 => None,
+            _ => Some(Snapshot::decode(&mut r)?),
+        };
+        Ok(CrashDump {
+            reason,
+            boot_count,
+            time,
+            command,
+            req_id,
+            message,
+            snapshot,
+        })
+    }
+}
+
+impl Snapshot {
+    // MicromouseState: time u32, x, y, theta, omega, v_batt, v, target_v, target_omega,
+    //   target_theta, v_l, v_r, duty_l, duty_r, delta_step f32, ls, lf, rf, rs u16,
+    //   walls u8 (bit 0: ls, 1: lf, 2: rf, 3: rs), wall_error i16
+    // Raw values: encoder l, r u16, l_diff, r_diff i16, gyro_x_raw i16, batt_raw u16, batt_phy f32
+    // Events: count u8, events (see mm_log::event)
+    fn encode(&self, b: &mut Vec<u8>) {
+        let m = &self.micromouse;
+        b.extend_from_slice(&m.time.to_le_bytes());
+        for v in [
+            m.x,
+            m.y,
+            m.theta,
+            m.omega,
+            m.v_batt,
+            m.v,
+            m.target_v,
+            m.target_omega,
+            m.target_theta,
+            m.v_l,
+            m.v_r,
+            m.duty_l,
+            m.duty_r,
+            m.delta_step,
+        ] {
+            b.extend_from_slice(&v.to_le_bytes());
+        }
+        for v in [m.ls, m.lf, m.rf, m.rs] {
+            b.extend_from_slice(&v.to_le_bytes());
+        }
+        let walls = [m.ls_wall, m.lf_wall, m.rf_wall, m.rs_wall]
+            .iter()
+            .enumerate()
+            .fold(0, |acc, (i, w)| acc | (w.to_bool() as u8) << i);
+        b.push(walls);
+        b.extend_from_slice(&m.wall_error.to_le_bytes());
+
+        let e = &self.encoder;
+        b.extend_from_slice(&e.l.to_le_bytes());
+        b.extend_from_slice(&e.r.to_le_bytes());
+        b.extend_from_slice(&e.l_diff.to_le_bytes());
+        b.extend_from_slice(&e.r_diff.to_le_bytes());
+        b.extend_from_slice(&self.gyro_x_raw.to_le_bytes());
+        b.extend_from_slice(&self.batt_raw.to_le_bytes());
+        b.extend_from_slice(&self.batt_phy.to_le_bytes());
+
+        b.push(self.events.len() as u8);
+        for e in self.events.iter() {
+            // Writing to a Vec does not fail
+            let _ = e.write(b);
+        }
+    }
+
+    fn decode(r: &mut Reader) -> anyhow::Result<Self> {
+        let time = r.u32()?;
+        let mut f = [0.0; 14];
+        for v in f.iter_mut() {
+            *v = r.f32()?;
+        }
+        let [x, y, theta, omega, v_batt, v, target_v, target_omega, target_theta, v_l, v_r, duty_l, duty_r, delta_step] =
+            f;
+        let (ls, lf, rf, rs) = (r.u16()?, r.u16()?, r.u16()?, r.u16()?);
+        let walls = r.u8()?;
+        let wall = |i: u8| Wall::from_bool(walls & (1 << i) != 0);
+        let micromouse = MicromouseState {
+            time,
+            x,
+            y,
+            theta,
+            omega,
+            v_batt,
+            v,
+            target_v,
+            target_omega,
+            target_theta,
+            v_l,
+            v_r,
+            duty_l,
+            duty_r,
+            ls,
+            lf,
+            rf,
+            rs,
+            ls_wall: wall(0),
+            lf_wall: wall(1),
+            rf_wall: wall(2),
+            rs_wall: wall(3),
+            delta_step,
+            wall_error: r.u16()? as i16,
+        };
+
+        let encoder = ods::OdsEncoder {
+            l: r.u16()?,
+            r: r.u16()?,
+            l_diff: r.u16()? as i16,
+            r_diff: r.u16()? as i16,
+            ..Default::default()
+        };
+        let gyro_x_raw = r.u16()? as i16;
+        let batt_raw = r.u16()?;
+        let batt_phy = r.f32()?;
+
+        let count = r.u8()? as usize;
+        let mut events = Vec::with_capacity(count);
+        for _ in 0..count {
+            let mut bytes = r.bytes(event::EVENT_SIZE)?;
+            if let Some(e) = Event::read(&mut bytes)? {
+                events.push(e);
+            }
+        }
+
+        Ok(Snapshot {
+            micromouse,
+            encoder,
+            gyro_x_raw,
+            batt_raw,
+            batt_phy,
+            events,
+        })
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> anyhow::Result<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + len)
+            .ok_or_else(|| anyhow::anyhow!("Broken crash dump (too short)"))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> anyhow::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> anyhow::Result<u16> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into()?))
+    }
+
+    fn u32(&mut self) -> anyhow::Result<u32> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into()?))
+    }
+
+    fn f32(&mut self) -> anyhow::Result<f32> {
+        Ok(f32::from_le_bytes(self.bytes(4)?.try_into()?))
+    }
+}
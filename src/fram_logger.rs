@@ -1,4 +1,4 @@
-use crate::timer_interrupt::get_time;
+use crate::timer_interrupt::get_ms;
 use anyhow::Ok;
 use esp_idf_hal::delay::BLOCK;
 use esp_idf_hal::gpio::AnyIOPin;
@@ -7,7 +7,9 @@ use esp_idf_hal::peripheral::Peripheral;
 use esp_idf_hal::prelude::*;
 use esp_idf_hal::units::Hertz;
 
+use mm_log::ring::{Entry, Ring, Storage};
 use std::io::Write as _;
+use std::sync::{Mutex, TryLockError};
 
 const I2C_ADDRESS: u8 = 0x50;
 
@@ -37,7 +39,7 @@ fn write_fram(adrs: u16, data: &[u8]) -> anyhow::Result<()> {
     Ok(())
 }
 
-pub fn read_fram(adrs: u16, data: &mut [u8]) -> anyhow::Result<()> {
+fn read_fram(adrs: u16, data: &mut [u8]) -> anyhow::Result<()> {
     let buffer: [u8; 2] = [(adrs >> 8) as u8, adrs as u8];
     unsafe {
         // Write the address and then read data.
@@ -58,87 +60,165 @@ pub fn init(peripherals: &mut Peripherals) -> anyhow::Result<()> {
         )?;
         I2C = Some(i2c);
     };
+    *RING.lock().unwrap() = Some(Ring::open(Fram, RING_ADDRESS, RING_SIZE)?);
     Ok(())
 }
 
-// Macros, like println! and print!
-use core::fmt::{self, Write};
+// Size of the FRAM. The firmware has used the first 8 KB since its first version,
+// so a part of at least 8 KB is assumed. Addresses beyond the part wrap around to 0.
+pub const FRAM_SIZE: u32 = 0x2000;
 
-static mut CURSOR: u16 = 0; // Write position
+// Log entries are kept in a ring buffer in FRAM, which survives reboots.
+// The crash dump (see crash_dump.rs) is after the ring at the end of FRAM.
+const RING_ADDRESS: u32 = 0x0000;
+const RING_SIZE: u32 = 0x1800;
+pub const DUMP_ADDRESS: u32 = RING_ADDRESS + RING_SIZE;
+pub const DUMP_SIZE: u32 = 0x0800;
+const _: () = assert!(RING_ADDRESS + RING_SIZE <= FRAM_SIZE);
+const _: () = assert!(DUMP_ADDRESS + DUMP_SIZE <= FRAM_SIZE);
+const _: () = assert!(RING_SIZE + DUMP_SIZE <= FRAM_SIZE);
 
-pub fn fram_print(args: fmt::Arguments) {
-    let mut writer = FramWriter {};
-    writer.write_fmt(args).unwrap();
+// Level of the entries written by fprint!, the others are log::Level
+pub const PRINT: u8 = 0;
+
+pub struct Fram;
+
+impl Storage for Fram {
+    fn read(&mut self, address: u32, data: &mut [u8]) -> std::io::Result<()> {
+        read_fram(address as u16, data).map_err(io_error)
+    }
+
+    fn write(&mut self, address: u32, data: &[u8]) -> std::io::Result<()> {
+        write_fram(address as u16, data).map_err(io_error)
+    }
 }
 
-#[macro_export]
-macro_rules! fprint {
-    ($($arg:tt)*) => ($crate::fram_print(format_args!($($arg)*)));
+fn io_error(e: anyhow::Error) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::Other, e.to_string())
 }
 
-#[macro_export]
-macro_rules! fprintln {
-    ($fmt:expr) => (fprint!(concat!($fmt, "\n")));
-    ($fmt:expr, $($arg:tt)*) => (fprint!(concat!($fmt, "\n"), $($arg)*));
+static RING: Mutex<Option<Ring<Fram>>> = Mutex::new(None);
+
+// The panic handler may run while the ring is locked by the panicking thread,
+// so it gives up after a while instead of waiting forever.
+fn with_ring<R>(f: impl FnOnce(&mut Ring<Fram>) -> R) -> Option<R> {
+    let mut ring = if std::thread::panicking() {
+        let mut locked = None;
+        for _ in 0..10 {
+            match RING.try_lock() {
+                Result::Ok(ring) => locked = Some(ring),
+                Err(TryLockError::Poisoned(e)) => locked = Some(e.into_inner()),
+                Err(TryLockError::WouldBlock) => {
+                    esp_idf_hal::delay::FreeRtos::delay_ms(1);
+                    continue;
+                }
+            }
+            break;
+        }
+        locked?
+    } else {
+        RING.lock().unwrap_or_else(|e| e.into_inner())
+    };
+    ring.as_mut().map(f)
 }
 
-struct FramWriter;
+// Access to FRAM outside the ring, e.g. the crash dump.
+// The lock of the ring is held, so that it does not interleave with log entries.
+pub fn with_storage<R>(f: impl FnOnce(&mut Fram) -> std::io::Result<R>) -> anyhow::Result<R> {
+    let result = with_ring(|_| f(&mut Fram)).ok_or_else(not_initialized)??;
+    Ok(result)
+}
 
-fn write(s: &str) -> fmt::Result {
-    write_fram(unsafe { CURSOR }, s.as_bytes()).unwrap();
+fn push(level: u8, text: &str) {
+    with_ring(|ring| ring.push(get_ms(), level, text.trim_end_matches('\n')));
+}
 
-    // Advance the cursor
-    unsafe {
-        CURSOR += s.len() as u16;
-        while CURSOR > 0x2000 {
-            CURSOR -= 0x2000;
-        }
-    }
+// All entries, oldest first
+pub fn entries() -> anyhow::Result<Vec<Entry>> {
+    let entries = with_ring(|ring| ring.entries()).ok_or_else(not_initialized)??;
+    Ok(entries)
+}
 
-    // Write terminal character
-    write_fram(unsafe { CURSOR }, b"\0").unwrap();
+pub fn clear() -> anyhow::Result<()> {
+    with_ring(|ring| ring.clear()).ok_or_else(not_initialized)??;
+    Ok(())
+}
 
-    core::result::Result::Ok(())
+fn not_initialized() -> anyhow::Error {
+    anyhow::anyhow!("FRAM is not initialized")
 }
 
-impl Write for FramWriter {
-    fn write_str(&mut self, s: &str) -> Result<(), std::fmt::Error> {
-        write(&s)
-    }
+pub fn format_entry(entry: &Entry) -> String {
+    let level = match entry.level {
+        1 => "ERROR",
+        2 => "WARN",
+        3 => "INFO",
+        4 => "DEBUG",
+        5 => "TRACE",
+        _ => "",
+    };
+    format!(
+        "{:>6} [{:02}:{:02}:{:03}] {:<5} {}",
+        entry.seq,
+        entry.time / 60000,
+        entry.time / 1000 % 60,
+        entry.time % 1000,
+        level,
+        entry.text
+    )
+}
+
+// Macros, like println! and print!
+// Each call is an entry of the ring.
+use core::fmt;
+
+pub fn fram_print(args: fmt::Arguments) {
+    push(PRINT, &args.to_string());
+}
+
+#[macro_export]
+macro_rules! fprint {
+    ($($arg:tt)*) => ($crate::fram_print(format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! fprintln {
+    ($fmt:expr) => (fprint!(concat!($fmt, "\n")));
+    ($fmt:expr, $($arg:tt)*) => (fprint!(concat!($fmt, "\n"), $($arg)*));
 }
 
-use log::{Level, Metadata, Record};
+use log::{LevelFilter, Metadata, Record};
 pub struct FramLogger;
 
 impl log::Log for FramLogger {
     fn enabled(&self, metadata: &Metadata) -> bool {
-        metadata.level() <= Level::Info
+        metadata.level() <= log::max_level()
     }
 
     fn log(&self, record: &Record) {
         if self.enabled(record.metadata()) {
-            let (min, sec, ms, us) = get_time();
-            fprintln!(
-                "[{:02}:{:02}:{:03}:{:03}] {} - {}",
-                min,
-                sec,
-                ms,
-                us,
-                record.level(),
-                record.args()
-            );
+            push(record.level() as u8, &record.args().to_string());
         }
     }
 
     fn flush(&self) {}
 }
 
-pub fn set_log(log_level: log::LevelFilter) {
+pub fn set_log(log_level: LevelFilter) {
     log::set_boxed_logger(Box::new(FramLogger))
         .map(|()| log::set_max_level(log_level))
         .unwrap();
 }
 
+// The level can be changed at runtime by the `flevel` command
+pub fn set_level(log_level: LevelFilter) {
+    log::set_max_level(log_level);
+}
+
+pub fn level() -> LevelFilter {
+    log::max_level()
+}
+
 // panic handler with FRAM
 use std::panic::{self, PanicInfo};
 
@@ -153,6 +233,7 @@ fn fram_panic_handler(info: &PanicInfo) {
         fprintln!("Panic occurred but can't get location information...");
     }
     fprintln!("{}", info);
+    let _ = crate::crash_dump::save(crate::crash_dump::Reason::Panic, &info.to_string());
 
     loop {
         esp_idf_hal::delay::FreeRtos::delay_ms(1000);
@@ -163,10 +244,30 @@ pub fn set_panic_handler() {
     panic::set_hook(Box::new(fram_panic_handler));
 }
 
+// Copy the entries added since the last copy to /sf/log00.txt
 pub fn move_fram_to_flash() {
-    let mut buffer: [u8; 32] = [0; 32];
-    let mut adrs = 0;
-    let mut flag = true;
+    let copied = with_ring(|ring| -> std::io::Result<_> {
+        let mark = ring.mark();
+        let next = ring.seq_range().1;
+        let entries: Vec<Entry> = ring
+            .entries()?
+            .into_iter()
+            .filter(|e| e.seq.wrapping_sub(mark) < next.wrapping_sub(mark))
+            .collect();
+        Result::Ok((entries, next))
+    });
+    let (entries, next) = match copied {
+        Some(Result::Ok(copied)) => copied,
+        Some(Err(e)) => {
+            println!("Failed to read FRAM: {:?}", e);
+            return;
+        }
+        None => return,
+    };
+    if entries.is_empty() {
+        println!("No new entries in FRAM.");
+        return;
+    }
 
     let _ = std::fs::remove_file("/sf/log05.txt");
     for n in (0..5).rev() {
@@ -178,23 +279,18 @@ pub fn move_fram_to_flash() {
 
     let mut file = std::fs::OpenOptions::new()
         .write(true)
+        .create(true)
         .append(true)
         .open("/sf/log00.txt")
         .unwrap();
 
-    while flag {
-        let mut size = 0;
-        read_fram(adrs, &mut buffer).unwrap();
-        adrs += buffer.len() as u16;
-        for b in buffer {
-            size += 1;
-            if b == 0 {
-                flag = false;
-                break;
-            }
-        }
-        file.write_all(&buffer[..size]).unwrap();
+    for entry in entries.iter() {
+        writeln!(file, "{}", format_entry(entry)).unwrap();
     }
+    with_ring(|ring| ring.set_mark(next));
 
-    println!("Data in FRAM has been copied to log00.txt.");
+    println!(
+        "{} entries in FRAM have been copied to log00.txt.",
+        entries.len()
+    );
 }
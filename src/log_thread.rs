@@ -1,67 +1,643 @@
+use crate::config_file;
+use crate::control_thread::{ControlThreadConfig, COMMAND_NAMES};
+use crate::crash_dump;
 use crate::led::LedColor::Red;
 use crate::led_thread::Command;
-use crate::ods;
+use crate::ods::{self, Ods};
+use crate::perf;
+use crate::spin_mpsc::{self, SpinSender};
+use mm_log::event::{self, Event, EventKind};
+use mm_log::record::{self, Field, FieldKind, FieldKind::*, Header};
+use serde::{Deserialize, Serialize};
 use std::fs::File;
-use std::io::Write;
-use std::sync::mpsc::{self, Sender};
+use std::io::{Seek, SeekFrom, Write};
+use std::sync::mpsc::Sender;
 use std::sync::{Arc, Mutex};
 
-pub const LOG_SIZE_IN_BYTE: usize = 200_000;
-pub const LOG_LEN: usize = LOG_SIZE_IN_BYTE / std::mem::size_of::<crate::ods::MicromouseState>();
-pub const LOG_MSG_LEN: usize = 100;
+// The run log is streamed to flash in chunks of this size.
+// Two chunks are allocated: one is filled by the control thread while the other is written.
+pub const LOG_CHUNK_SIZE: usize = 32_768;
+// Number of the latest events kept in ODS
+pub const EVENT_LOG_LEN: usize = 2048;
+
+// Upper limit of the ring buffer for pre-trigger capture
+pub const LOG_RING_SIZE: usize = 96_000;
+
+// Space reserved in the header for the metadata, which is only known when the run ends.
+const METADATA_SIZE: usize = 4096;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum LogCommand {
-    Save,
+    Start(u8, u64), // The arguments are the interval of logging and the channel mask
+    Flush,          // A chunk is full
+    Save,           // Write the rest and close the run log
+    // Save the ring buffer of the capture mode. The arguments are the interval, the channel mask,
+    // the reason of the trigger and the number of records before the trigger.
+    SaveCapture(u8, u64, TriggerReason, u32),
+    // Allocate the ring buffer of the capture mode. The arguments are the number of records
+    // and the channel mask.
+    AllocRing(u32, u64),
+    DiscardCapture,                // The capture mode stopped without a trigger
+    SetMode(&'static str),         // Operation mode recorded in the metadata
+    CrashDump(crash_dump::Moment), // Write the crash dump of a fault to FRAM
+}
+
+// Double buffer between the control thread and the log thread
+pub struct LogBuffer {
+    pub filling: Vec<u8>,       // Appended by the control thread
+    pub full: Option<Vec<u8>>,  // Waiting to be written by the log thread
+    pub spare: Option<Vec<u8>>, // Empty chunk. None while the other chunk is in use.
+    pub dropped: u32,           // Samples lost because no chunk was available
+    pub ring: Option<RingLog>,  // Used instead of the chunks in the capture mode
+    pub saving: bool,           // The log thread is still handling a Save or a capture
+}
+
+impl LogBuffer {
+    pub fn new() -> Self {
+        Self {
+            filling: Vec::with_capacity(LOG_CHUNK_SIZE),
+            full: None,
+            spare: Some(Vec::with_capacity(LOG_CHUNK_SIZE)),
+            dropped: 0,
+            ring: None,
+            saving: false,
+        }
+    }
+
+    // Hand the filled chunk over to the log thread.
+    // Returns false if the other chunk is still being written.
+    pub fn swap(&mut self) -> bool {
+        match self.spare.take() {
+            Some(spare) => {
+                self.full = Some(std::mem::replace(&mut self.filling, spare));
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+// Used only to take the buffer out of ODS temporarily. Does not allocate.
+impl Default for LogBuffer {
+    fn default() -> Self {
+        Self {
+            filling: Vec::new(),
+            full: None,
+            spare: None,
+            dropped: 0,
+            ring: None,
+            saving: false,
+        }
+    }
+}
+
+// Pre-trigger capture settings
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct TriggerConfig {
+    pub pre: u16,  // Number of records kept before the trigger
+    pub post: u16, // Number of records recorded after the trigger
+    #[serde(default)]
+    pub wall_error_limit: Option<u16>, // Fire when |wall_error| exceeds this value
+}
+
+// Names of TriggerReason in the event log
+pub const TRIGGER_NAMES: [&str; 4] = ["Manual", "Fault", "Abort", "WallError"];
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum TriggerReason {
+    Manual,
+    Fault,
+    Abort,
+    WallError,
+}
+
+// Keeps the latest records. The oldest one is overwritten when it is full.
+pub struct RingLog {
+    data: Vec<u8>,
+    record: Vec<u8>,
+    record_size: usize,
+    capacity: usize, // in records
+    head: usize,     // Index of the next record to be written
+    len: usize,
+}
+
+impl RingLog {
+    pub fn new(capacity: usize, record_size: usize) -> Self {
+        let capacity = capacity.max(1);
+        Self {
+            data: vec![0; capacity * record_size],
+            record: Vec::with_capacity(record_size),
+            record_size,
+            capacity,
+            head: 0,
+            len: 0,
+        }
+    }
+
+    pub fn push(&mut self, ods: &Ods, channels: u64) {
+        self.record.clear();
+        encode(ods, channels, &mut self.record);
+        let offset = self.head * self.record_size;
+        self.data[offset..offset + self.record_size].copy_from_slice(&self.record);
+        self.head = (self.head + 1) % self.capacity;
+        self.len = (self.len + 1).min(self.capacity);
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    // Records from the oldest one. The second slice is empty unless it has wrapped around.
+    fn as_slices(&self) -> (&[u8], &[u8]) {
+        if self.len < self.capacity {
+            (&self.data[..self.len * self.record_size], &[])
+        } else {
+            let (first, second) = self.data.split_at(self.head * self.record_size);
+            (second, first)
+        }
+    }
+}
+
+type Getter = fn(&Ods) -> f32;
+
+// Channels which can be recorded in the run log: (name, kind, scale, getter)
+// Bit n of a channel mask selects CHANNELS[n].
+// "time" is not a channel. It is always the first field of a record.
+const CHANNELS: [(&str, FieldKind, f32, Getter); 44] = [
+    ("x", I16, 0.0001, |o| o.micromouse.x),
+    ("y", I16, 0.0001, |o| o.micromouse.y),
+    ("theta", I16, 0.001, |o| o.micromouse.theta),
+    ("omega", I16, 0.001, |o| o.micromouse.omega),
+    ("v_batt", U16, 0.001, |o| o.micromouse.v_batt),
+    ("v", I16, 0.001, |o| o.micromouse.v),
+    ("target_v", I16, 0.001, |o| o.micromouse.target_v),
+    ("v_l", I16, 0.001, |o| o.micromouse.v_l),
+    ("v_r", I16, 0.001, |o| o.micromouse.v_r),
+    ("duty_l", I16, 0.01, |o| o.micromouse.duty_l),
+    ("duty_r", I16, 0.01, |o| o.micromouse.duty_r),
+    ("ls", U16, 1.0, |o| o.micromouse.ls as f32),
+    ("lf", U16, 1.0, |o| o.micromouse.lf as f32),
+    ("rf", U16, 1.0, |o| o.micromouse.rf as f32),
+    ("rs", U16, 1.0, |o| o.micromouse.rs as f32),
+    // bit0: ls, bit1: lf, bit2: rf, bit3: rs
+    ("walls", U8, 1.0, |o| {
+        let s = &o.micromouse;
+        (s.ls_wall.to_bool() as u8
+            | (s.lf_wall.to_bool() as u8) << 1
+            | (s.rf_wall.to_bool() as u8) << 2
+            | (s.rs_wall.to_bool() as u8) << 3) as f32
+    }),
+    ("delta_step", U8, 1.0, |o| o.micromouse.delta_step),
+    ("wall_error", I16, 1.0, |o| o.micromouse.wall_error as f32),
+    ("gyro_x_raw", I16, 1.0, |o| o.imu.gyro_x_raw as f32),
+    ("enc_l_diff", I16, 1.0, |o| o.encoder.l_diff as f32),
+    ("enc_r_diff", I16, 1.0, |o| o.encoder.r_diff as f32),
+    ("batt_raw", U16, 1.0, |o| o.wall_sensor.batt_raw as f32),
+    ("target_omega", I16, 0.001, |o| o.micromouse.target_omega),
+    ("target_theta", I16, 0.001, |o| o.micromouse.target_theta),
+    ("v_pid_error", I16, 0.001, |o| o.pid.v.error),
+    ("v_pid_p", I16, 0.001, |o| o.pid.v.p),
+    ("v_pid_i", I16, 0.001, |o| o.pid.v.i),
+    ("v_pid_d", I16, 0.001, |o| o.pid.v.d),
+    ("v_pid_out", I16, 0.001, |o| o.pid.v.output),
+    ("omega_pid_error", I16, 0.001, |o| o.pid.omega.error),
+    ("omega_pid_p", I16, 0.001, |o| o.pid.omega.p),
+    ("omega_pid_i", I16, 0.001, |o| o.pid.omega.i),
+    ("omega_pid_d", I16, 0.001, |o| o.pid.omega.d),
+    ("omega_pid_out", I16, 0.001, |o| o.pid.omega.output),
+    ("theta_pid_error", I16, 0.001, |o| o.pid.theta.error),
+    ("theta_pid_p", I16, 0.001, |o| o.pid.theta.p),
+    ("theta_pid_i", I16, 0.001, |o| o.pid.theta.i),
+    ("theta_pid_d", I16, 0.001, |o| o.pid.theta.d),
+    ("theta_pid_out", I16, 0.001, |o| o.pid.theta.output),
+    ("wall_pid_error", I16, 0.001, |o| o.pid.wall.error),
+    ("wall_pid_p", I16, 0.001, |o| o.pid.wall.p),
+    ("wall_pid_i", I16, 0.001, |o| o.pid.wall.i),
+    ("wall_pid_d", I16, 0.001, |o| o.pid.wall.d),
+    ("wall_pid_out", I16, 0.001, |o| o.pid.wall.output),
+];
+
+// Names which select several channels at once
+const GROUPS: [(&str, &[&str]); 11] = [
+    ("pose", &["x", "y", "theta"]),
+    ("velocities", &["v", "omega", "v_l", "v_r"]),
+    ("duties", &["duty_l", "duty_r"]),
+    ("sensors", &["ls", "lf", "rf", "rs", "walls"]),
+    (
+        "raw",
+        &["gyro_x_raw", "enc_l_diff", "enc_r_diff", "batt_raw"],
+    ),
+    ("targets", &["target_v", "target_omega", "target_theta"]),
+    (
+        "v_pid",
+        &["v_pid_error", "v_pid_p", "v_pid_i", "v_pid_d", "v_pid_out"],
+    ),
+    (
+        "omega_pid",
+        &[
+            "omega_pid_error",
+            "omega_pid_p",
+            "omega_pid_i",
+            "omega_pid_d",
+            "omega_pid_out",
+        ],
+    ),
+    (
+        "theta_pid",
+        &[
+            "theta_pid_error",
+            "theta_pid_p",
+            "theta_pid_i",
+            "theta_pid_d",
+            "theta_pid_out",
+        ],
+    ),
+    (
+        "wall_pid",
+        &[
+            "wall_pid_error",
+            "wall_pid_p",
+            "wall_pid_i",
+            "wall_pid_d",
+            "wall_pid_out",
+        ],
+    ),
+    (
+        "pid_out",
+        &[
+            "v_pid_out",
+            "omega_pid_out",
+            "theta_pid_out",
+            "wall_pid_out",
+        ],
+    ),
+];
+
+pub const ALL_CHANNELS: u64 = (1 << CHANNELS.len()) - 1;
+
+// Convert channel and group names to a channel mask.
+// An empty list selects all channels.
+pub fn channel_mask(names: &[String]) -> anyhow::Result<u64> {
+    if names.is_empty() {
+        return Ok(ALL_CHANNELS);
+    }
+    let mut mask = 0;
+    for name in names.iter() {
+        match GROUPS.iter().find(|(group, _)| *group == name.as_str()) {
+            Some((_, members)) => {
+                for member in members.iter() {
+                    mask |= channel_bit(member)?;
+                }
+            }
+            None => mask |= channel_bit(name)?,
+        }
+    }
+    Ok(mask)
+}
+
+fn channel_bit(name: &str) -> anyhow::Result<u64> {
+    match CHANNELS.iter().position(|(channel, ..)| *channel == name) {
+        Some(i) => Ok(1 << i),
+        None => Err(anyhow::anyhow!("Unknown log channel '{}'", name)),
+    }
+}
+
+// Size of a record in bytes, including the time stamp
+pub fn record_size(channels: u64) -> usize {
+    CHANNELS
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| channels & (1 << i) != 0)
+        .map(|(_, (_, kind, ..))| kind.size())
+        .sum::<usize>()
+        + U32.size()
+}
+
+// Append a packed record of the selected channels to `buf`.
+pub fn encode(ods: &Ods, channels: u64, buf: &mut Vec<u8>) {
+    record::encode_raw(buf, U32, ods.micromouse.time as i64);
+    for (i, (_, kind, scale, getter)) in CHANNELS.iter().enumerate() {
+        if channels & (1 << i) != 0 {
+            record::encode(buf, *kind, *scale, getter(ods));
+        }
+    }
+}
+
+pub fn header(interval: u16, channels: u64) -> Header {
+    let mut fields = vec![Field::new("time", U32, 1.0)];
+    for (i, (name, kind, scale, _)) in CHANNELS.iter().enumerate() {
+        if channels & (1 << i) != 0 {
+            fields.push(Field::new(name, *kind, *scale));
+        }
+    }
+    Header::new(interval, fields)
+}
+
+// Saved runs are kept as /sf/runNN.bin and /sf/runNN.evt, run00 being the latest.
+pub const RUN_LOG_NUM: usize = 6;
+
+pub fn run_log_path(n: usize) -> String {
+    format!("/sf/run{:02}.bin", n)
+}
+
+pub fn run_event_path(n: usize) -> String {
+    format!("/sf/run{:02}.evt", n)
+}
+
+// Names of the codes of each event kind, see mm_log::event
+pub fn event_header() -> event::Header {
+    let mut names: Vec<&[&str]> = vec![&[]; event::KINDS.len()];
+    names[EventKind::CommandReceived as usize] = &COMMAND_NAMES;
+    names[EventKind::CommandDone as usize] = &COMMAND_NAMES;
+    names[EventKind::WallEdge as usize] = &["left", "right"];
+    names[EventKind::WallReference as usize] = &["left", "right"];
+    names[EventKind::Trigger as usize] = &TRIGGER_NAMES;
+    event::Header::new(&names)
+}
+
+// Stored as JSON in the header of a run log
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RunMetadata {
+    pub boot_count: u32,
+    pub time: u32, // Time since boot when the log was saved [ms]
+    pub mode: String,
+    pub records: u32,
+    #[serde(default)]
+    pub dropped: u32,
+    #[serde(default)]
+    pub trigger: Option<TriggerReason>,
+    #[serde(default)]
+    pub pre_trigger: u32, // Number of records before the trigger
+    #[serde(default)]
+    pub perf: Option<perf::PerfStats>, // Timing of the control cycle during the recording
+    #[serde(default, alias = "messages")]
+    pub events: u32,
+    pub ctrl_cfg_crc: u16, // Of the configuration the control thread ran with
+    pub ctrl_cfg: serde_json::Value,
+    #[serde(default)]
+    pub profile: Option<String>, // Of ctrl_cfg.json picked at boot
+}
+
+// The configuration the control thread runs with as JSON, for the metadata of the runs.
+// Set whenever the control thread takes a configuration, which can differ from the file.
+static CTRL_CFG: Mutex<String> = Mutex::new(String::new());
+
+pub fn set_ctrl_cfg(config: &ControlThreadConfig) {
+    *CTRL_CFG.lock().unwrap() = serde_json::to_string(config).unwrap_or_default();
+}
+
+// Shift runNN to runNN+1 and drop the oldest one
+fn rotate_run_logs() {
+    let _ = std::fs::remove_file(run_log_path(RUN_LOG_NUM - 1));
+    let _ = std::fs::remove_file(run_event_path(RUN_LOG_NUM - 1));
+    for n in (0..RUN_LOG_NUM - 1).rev() {
+        let _ = std::fs::rename(run_log_path(n), run_log_path(n + 1));
+        let _ = std::fs::rename(run_event_path(n), run_event_path(n + 1));
+    }
+}
+
+// The run log being written
+struct Recording {
+    file: File,
+    metadata_offset: u64,
+    interval: u8,
+    channels: u64,
+    bytes: usize,
+    start_time: Option<u32>, // Time of the first record
+    trigger: Option<(TriggerReason, u32)>,
+    ctrl_cfg: String, // Of the control thread at the start, see set_ctrl_cfg
+    profile: Option<String>,
+}
+
+impl Recording {
+    fn start(interval: u8, channels: u64) -> anyhow::Result<Self> {
+        rotate_run_logs();
+
+        let mut header = header(interval as u16, channels);
+        header.metadata = " ".repeat(METADATA_SIZE);
+        let mut bytes = Vec::new();
+        header.write(&mut bytes)?;
+
+        let mut file = File::create(run_log_path(0))?;
+        file.write_all(&bytes)?;
+        log::info!("Recording to {}", run_log_path(0));
+
+        Ok(Recording {
+            file,
+            metadata_offset: (bytes.len() - METADATA_SIZE) as u64,
+            interval,
+            channels,
+            bytes: 0,
+            start_time: None,
+            trigger: None,
+            ctrl_cfg: CTRL_CFG.lock().unwrap().clone(),
+            profile: config_file::profile(),
+        })
+    }
+
+    fn append(&mut self, chunk: &[u8]) -> anyhow::Result<()> {
+        if self.start_time.is_none() && chunk.len() >= 4 {
+            // "time" is the first field of a record
+            self.start_time = Some(u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]));
+        }
+        self.file.write_all(chunk)?;
+        self.bytes += chunk.len();
+        Ok(())
+    }
+
+    // Fill in the metadata and close the file
+    fn finish(
+        mut self,
+        ods: &Mutex<ods::Ods>,
+        dropped: u32,
+        boot_count: u32,
+        mode: &str,
+    ) -> anyhow::Result<()> {
+        let records = self.bytes / record_size(self.channels);
+        log::info!(
+            "Saving log data... ({} records, {} dropped, interval {})",
+            records,
+            dropped,
+            self.interval
+        );
+
+        // Events during the recording
+        let start_time = self.start_time.unwrap_or(u32::MAX);
+        let events: Vec<Event> = ods
+            .lock()
+            .unwrap()
+            .events
+            .iter()
+            .filter(|e| e.time >= start_time)
+            .copied()
+            .collect();
+
+        let ctrl_cfg = &self.ctrl_cfg;
+        let mut metadata = RunMetadata {
+            boot_count,
+            time: crate::timer_interrupt::get_ms(),
+            mode: mode.to_string(),
+            records: records as u32,
+            dropped,
+            trigger: self.trigger.map(|(reason, _)| reason),
+            pre_trigger: self.trigger.map_or(0, |(_, pre)| pre),
+            perf: Some(perf::stats()),
+            events: events.len() as u32,
+            ctrl_cfg_crc: crc16::State::<crc16::XMODEM>::calculate(ctrl_cfg.as_bytes()),
+            ctrl_cfg: serde_json::from_str(ctrl_cfg).unwrap_or(serde_json::Value::Null),
+            profile: self.profile.take(),
+        };
+        let mut json = serde_json::to_string(&metadata)?;
+        if json.len() > METADATA_SIZE {
+            // Keep only the CRC of the config
+            metadata.ctrl_cfg = serde_json::Value::Null;
+            json = serde_json::to_string(&metadata)?;
+        }
+        // JSON allows trailing white spaces, so the rest of the reserved space is left as is.
+        self.file.seek(SeekFrom::Start(self.metadata_offset))?;
+        self.file.write_all(json.as_bytes())?;
+        self.file.flush()?;
+
+        let mut file = std::io::BufWriter::new(File::create(run_event_path(0))?);
+        event_header().write(&mut file)?;
+        for e in events.iter() {
+            e.write(&mut file)?;
+        }
+        file.flush()?;
+
+        log::info!("Saved as {}", run_log_path(0));
+        Ok(())
+    }
 }
 
 pub fn init(
     ods: &Arc<Mutex<ods::Ods>>,
     led_tx: Sender<Command>,
-) -> anyhow::Result<Sender<LogCommand>> {
+    boot_count: u32,
+) -> anyhow::Result<SpinSender<LogCommand>> {
     let ods = ods.clone();
 
     // Spawn the log thread
     esp_idf_hal::task::thread::ThreadSpawnConfiguration {
         name: None,
-        stack_size: 2048,
+        stack_size: 4096,
         priority: 15,
         inherit: false, // don't inherit this configuration across threads
         pin_to_core: Some(esp_idf_hal::cpu::Core::Core0),
     }
     .set()?;
 
-    /* mpsc */
-    let (tx, rx) = mpsc::channel::<LogCommand>();
+    // The control thread sends to this queue, so it is preallocated (see spin_mpsc)
+    let (tx, rx) = spin_mpsc::channel::<LogCommand>();
 
     std::thread::Builder::new().spawn(move || -> anyhow::Result<()> {
+        let mut mode = "";
+        let mut recording: Option<Recording> = None;
         loop {
-            // Wait for the save command
-            log::info!("Waiting for the save command...");
-            let command = rx.recv().unwrap();
-            if command == LogCommand::Save {
-                // Write log data as CSV file
-                let ods = &mut ods.lock().unwrap();
-                log::info!("Saving log data... ({} records)", ods.log.len());
-
-                led_tx.send((Red, Some("1"))).unwrap();
-                let mut wtr = csv::Writer::from_writer(File::create("/sf/log.csv")?);
-                for log_data in ods.log.iter() {
-                    wtr.serialize(log_data)?;
+            let command = rx.recv();
+            match command {
+                LogCommand::SetMode(m) => {
+                    mode = m;
                 }
-                wtr.flush()?;
-
-                ods.log.clear(); // clear() does not release heap memory.
-                log::info!("Saved");
-
-                let mut file = File::create("/sf/log_msg.txt")?;
-                for msg in ods.log_msg.iter() {
-                    writeln!(file, "{}", msg)?;
+                LogCommand::Start(interval, channels) => {
+                    recording = match Recording::start(interval, channels) {
+                        Ok(r) => Some(r),
+                        Err(e) => {
+                            log::error!("Failed to start the log: {:?}", e);
+                            None
+                        }
+                    };
+                }
+                LogCommand::Flush => {
+                    // Write the chunk without holding the lock of ODS
+                    let chunk = ods.lock().unwrap().log.full.take();
+                    if let Some(mut chunk) = chunk {
+                        if let Some(r) = recording.as_mut() {
+                            if let Err(e) = r.append(&chunk) {
+                                log::error!("Failed to write the log: {:?}", e);
+                            }
+                        }
+                        chunk.clear(); // clear() does not release heap memory.
+                        ods.lock().unwrap().log.spare = Some(chunk);
+                    }
+                }
+                LogCommand::Save => {
+                    led_tx.send((Red, Some("1"))).unwrap();
+                    // The control thread does not log until `saving` is cleared (see
+                    // ControlContext::start_log), so the spare chunk is put in the place of the
+                    // one being written and nothing is allocated.
+                    let (full, filling, dropped) = {
+                        let mut ods = ods.lock().unwrap();
+                        let filling = match ods.log.spare.take() {
+                            Some(spare) => std::mem::replace(&mut ods.log.filling, spare),
+                            None => std::mem::take(&mut ods.log.filling),
+                        };
+                        (ods.log.full.take(), filling, ods.log.dropped)
+                    };
+                    if let Some(mut r) = recording.take() {
+                        let result = full
+                            .as_ref()
+                            .map_or(Ok(()), |chunk| r.append(chunk))
+                            .and_then(|_| r.append(&filling))
+                            .and_then(|_| r.finish(&ods, dropped, boot_count, mode));
+                        if let Err(e) = result {
+                            log::error!("Failed to save the log: {:?}", e);
+                        }
+                    } else {
+                        log::warn!("Save without Start");
+                    }
+                    let mut ods = ods.lock().unwrap();
+                    for mut chunk in full.into_iter().chain([filling]) {
+                        chunk.clear(); // clear() does not release heap memory.
+                        if ods.log.filling.capacity() == 0 {
+                            ods.log.filling = chunk;
+                        } else {
+                            ods.log.spare = Some(chunk);
+                        }
+                    }
+                    ods.log.saving = false;
+                    drop(ods);
+                    led_tx.send((Red, Some("0"))).unwrap();
+                }
+                LogCommand::SaveCapture(interval, channels, reason, pre) => {
+                    led_tx.send((Red, Some("1"))).unwrap();
+                    log::info!("Capture triggered by {:?}", reason);
+                    let ring = ods.lock().unwrap().log.ring.take();
+                    if let Some(ring) = ring {
+                        let result = Recording::start(interval, channels).and_then(|mut r| {
+                            let (first, second) = ring.as_slices();
+                            r.append(first)?;
+                            r.append(second)?;
+                            r.trigger = Some((reason, pre));
+                            r.finish(&ods, 0, boot_count, mode)
+                        });
+                        if let Err(e) = result {
+                            log::error!("Failed to save the capture: {:?}", e);
+                        }
+                    }
+                    ods.lock().unwrap().log.saving = false;
+                    led_tx.send((Red, Some("0"))).unwrap();
+                }
+                LogCommand::AllocRing(records, channels) => {
+                    // Allocated here to keep the allocation out of the control cycle
+                    let ring = RingLog::new(records as usize, record_size(channels));
+                    ods.lock().unwrap().log.ring = Some(ring);
+                }
+                LogCommand::CrashDump(moment) => {
+                    let saved =
+                        crash_dump::save_at(crash_dump::Reason::Fault, moment, "Fault reported");
+                    if let Err(e) = saved {
+                        log::warn!("Failed to save the crash dump: {:?}", e);
+                    }
+                }
+                LogCommand::DiscardCapture => {
+                    let ring = {
+                        let mut ods = ods.lock().unwrap();
+                        ods.log.saving = false;
+                        ods.log.ring.take()
+                    };
+                    drop(ring);
                 }
-
-                led_tx.send((Red, Some("0"))).unwrap();
-            } else {
-                log::warn!("Unknown command: {:?}", command);
             }
         }
     })?;
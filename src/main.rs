@@ -17,9 +17,12 @@ pub mod uart;
 pub mod fram_logger;
 use crate::fram_logger::fram_print;
 
+mod alloc_counter;
+mod config_file;
 mod console;
 mod control_thread;
-use control_thread::Command;
+mod crash_dump;
+use control_thread::{Command, ControlThreadConfig};
 mod encoder;
 pub mod imu;
 mod led;
@@ -29,14 +32,18 @@ pub mod misc;
 pub mod mm_const;
 mod motor;
 pub mod ods;
+mod perf;
 pub mod pid;
 mod spiflash;
+mod telemetry;
+mod test_run;
 pub mod timer_interrupt;
 mod ui;
 mod vac_fan;
 mod wall_sensor;
 pub use mm_maze::{adachi, maze, path_finder::PathFinder};
 pub mod spin_mpsc;
+use mm_log::event::EventKind;
 use spin_mpsc::{SpinReceiver, SpinSender};
 
 #[allow(unused_imports)]
@@ -59,18 +66,31 @@ struct SearchConfig {
     goal_x: usize,
     goal_y: usize,
     log_interval: u8,
+    // Names of log channels or channel groups. All channels are logged if empty.
+    #[serde(default)]
+    log_channels: Vec<String>,
+    // If given, only the records around a trigger (abort, wall error) are saved.
+    #[serde(default)]
+    log_trigger: Option<log_thread::TriggerConfig>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Default)]
-struct OperationThreadConfig {
-    mode: OperationMode,
-    search_config: SearchConfig,
-    test_config: TestConfig,
+struct TelemetryConfig {
+    interval: u16, // [ms], 0 disables telemetry
+    // Names of log channels or channel groups. All channels are sent if empty.
+    #[serde(default)]
+    channels: Vec<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Default)]
-struct TestConfig {
-    test_pattern: Vec<control_thread::Command>,
+struct OperationThreadConfig {
+    #[allow(unused)]
+    version: u32, // See mm_config::migrate
+    mode: OperationMode,
+    search_config: SearchConfig,
+    test_config: test_run::TestConfig,
+    #[serde(default)]
+    telemetry: TelemetryConfig,
 }
 
 pub struct OperationContext {
@@ -79,7 +99,8 @@ pub struct OperationContext {
     pub vac_tx: Sender<vac_fan::Command>,
     pub command_tx: SpinSender<Command>,
     pub response_rx: SpinReceiver<control_thread::Response>,
-    pub log_tx: Sender<log_thread::LogCommand>,
+    pub log_tx: SpinSender<log_thread::LogCommand>,
+    pub telemetry_tx: Sender<telemetry::Command>,
 }
 
 impl OperationContext {
@@ -121,8 +142,9 @@ fn main() -> anyhow::Result<()> {
         led_tx: mpsc::channel().0,
         command_tx: spin_mpsc::channel().0,
         response_rx: spin_mpsc::channel().1,
-        log_tx: mpsc::channel().0,
+        log_tx: spin_mpsc::channel().0,
         vac_tx: mpsc::channel().0,
+        telemetry_tx: mpsc::channel().0,
     };
 
     let mut peripherals = Peripherals::take().unwrap();
@@ -141,14 +163,18 @@ fn main() -> anyhow::Result<()> {
     let boot_count = boot_count();
 
     // Start log thread
-    let log_tx = log_thread::init(&mut ctx.ods, ctx.led_tx.clone())?;
+    let log_tx = log_thread::init(&mut ctx.ods, ctx.led_tx.clone(), boot_count)?;
     ctx.log_tx = log_tx.clone();
 
+    // Start telemetry thread
+    ctx.telemetry_tx = telemetry::init(&ctx.ods)?;
+
     // Initialize peripherals
     motor::init(&mut peripherals)?;
     wall_sensor::init(&mut peripherals)?;
     fram_logger::init(&mut peripherals)?;
     fram_logger::set_log(log::LevelFilter::Info);
+    crash_dump::init(&ctx.ods, boot_count);
     fram_logger::set_panic_handler();
     fram_logger::move_fram_to_flash();
     imu::init(&mut peripherals)?;
@@ -174,10 +200,8 @@ fn main() -> anyhow::Result<()> {
 
     // Read config
     fn read() -> anyhow::Result<OperationThreadConfig> {
-        let mut f = File::open("/sf/ope_cfg.json")?;
-        let mut contents = String::new();
-        f.read_to_string(&mut contents)?;
-        let result = serde_json::from_str(&contents)?;
+        let value = config_file::read("/sf/ope_cfg.json", mm_config::migrate::ope)?;
+        let result = serde_json::from_value(value)?;
         return Ok(result);
     }
 
@@ -190,8 +214,20 @@ fn main() -> anyhow::Result<()> {
         }
     };
 
+    ctx.log_tx
+        .send(log_thread::LogCommand::SetMode(match config.mode {
+            OperationMode::Search => "Search",
+            OperationMode::Test => "Test",
+        }));
+
     uprintln!("Boot count: {}", boot_count);
     log::info!("Boot count: {}", boot_count);
+    if let Ok(Some(dump)) = crash_dump::load() {
+        uprintln!(
+            "A crash dump of boot {} is in FRAM. Type 'crash' to show it.",
+            dump.boot_count
+        );
+    }
 
     if config_failure {
         ctx.led_tx.send((Red, Some("01")))?;
@@ -211,7 +247,18 @@ fn app_main(ctx: &OperationContext, config: OperationThreadConfig) -> anyhow::Re
 
     ctx.led_tx.send((Blue, Some("0")))?;
     if ui::hold_ws(&ctx, Some(500)) == ui::UserOperation::HoldR {
-        ui::wait(&ctx, ui::UserOperation::HoldL);
+        match select_profile(&ctx) {
+            Ok(true) => {}
+            Ok(false) => ui::wait(&ctx, ui::UserOperation::HoldL),
+            Err(e) => {
+                // The control thread keeps the base config. Stay in the console.
+                uprintln!("❌Failed to select a profile: {:?}", e);
+                log::error!("Failed to select a profile: {:?}", e);
+                ctx.led_tx.send((Red, Some("01")))?;
+                ctx.led_tx.send((Blue, Some("01")))?;
+                return console.run(&ctx);
+            }
+        }
         // Calibrate the gyro
         ctx.led_tx.send((Red, Some("10")))?;
         uprintln!("Start gyro calibration");
@@ -221,15 +268,66 @@ fn app_main(ctx: &OperationContext, config: OperationThreadConfig) -> anyhow::Re
         uprintln!("Gyro offset: {}", offset);
 
         ui::countdown(&ctx);
+        let telemetry = config.telemetry.interval != 0;
+        if telemetry {
+            let channels = log_thread::channel_mask(&config.telemetry.channels)?;
+            ctx.telemetry_tx.send(telemetry::Command::Start(
+                config.telemetry.interval,
+                channels,
+            ))?;
+        }
         if config.mode == OperationMode::Search {
             search_run(&ctx, config)?;
         } else {
             test_run(&ctx, config)?;
         }
+        if telemetry {
+            ctx.telemetry_tx.send(telemetry::Command::Stop)?;
+        }
+        let stats = alloc_counter::stats();
+        if stats.flagged_cycles != 0 {
+            uprintln!(
+                "⚠️{} heap allocations in {} control cycles",
+                stats.allocations,
+                stats.flagged_cycles
+            );
+            log::warn!("Allocations in the control cycle: {:?}", stats);
+        }
     }
     return console.run(&ctx);
 }
 
+// Pick a profile of ctrl_cfg.json with the hands and hand it to the control thread.
+// Returns false if the file has no profiles.
+fn select_profile(ctx: &OperationContext) -> anyhow::Result<bool> {
+    let value = config_file::read(control_thread::CONFIG_PATH, mm_config::migrate::ctrl)?;
+    let (config, _) = ControlThreadConfig::load(&value);
+    let names: Vec<String> = config.profiles.iter().map(|p| p.name.clone()).collect();
+    if names.is_empty() {
+        return Ok(false);
+    }
+
+    uprintln!("Select a profile. Hold right for the next one, left to confirm.");
+    let index = ui::select(ctx, &names);
+    let name = &names[index];
+    let config = ControlThreadConfig::with_profile(&value, name)?;
+    ctx.command_tx
+        .send(Command::ReloadConfig(Box::new(config.clone())));
+    match ctx.wait_response() {
+        control_thread::Response::ConfigReloaded(true) => {}
+        resp => return Err(anyhow::anyhow!("Profile {} is refused: {:?}", name, resp)),
+    }
+    log_thread::set_ctrl_cfg(&config);
+    config_file::set_profile(name);
+    ctx.ods
+        .lock()
+        .unwrap()
+        .event(EventKind::Profile, index as u8, 0.0);
+    uprintln!("Profile: {}", name);
+    log::info!("Profile: {}", name);
+    Ok(true)
+}
+
 fn search_run(ctx: &OperationContext, config: OperationThreadConfig) -> anyhow::Result<()> {
     ctx.led_tx.send((Red, None))?;
     ctx.led_tx.send((Blue, None))?;
@@ -251,8 +349,13 @@ fn search_run(ctx: &OperationContext, config: OperationThreadConfig) -> anyhow::
     ctx.wait_response(); // Wait for CommandRequest    ctx.command_tx
 
     if config.search_config.log_interval != 0 {
-        ctx.command_tx
-            .send(Command::StartLog(config.search_config.log_interval));
+        let channels = log_thread::channel_mask(&config.search_config.log_channels)?;
+        ctx.command_tx.send(match config.search_config.log_trigger {
+            Some(trigger) => {
+                Command::StartLogTrigger(config.search_config.log_interval, channels, trigger)
+            }
+            None => Command::StartLogChannels(config.search_config.log_interval, channels),
+        });
         ctx.wait_response(); // Wait for CommandRequest
     }
     ctx.command_tx.send(Command::SStart(mm_const::BLOCK_LENGTH));
@@ -286,6 +389,8 @@ fn search_run(ctx: &OperationContext, config: OperationThreadConfig) -> anyhow::
         let dir = solver.navigate(front, left, right, solver.get_goal());
         if let Err(e) = dir {
             log::warn!("{:?}", e);
+            ctx.command_tx
+                .send(Command::LogTrigger(log_thread::TriggerReason::Abort));
             ctx.command_tx.send(Command::SStop);
             ctx.wait_response(); // Wait for CommandRequest
             ctx.command_tx.send(Command::StopLog);
@@ -335,16 +440,5 @@ fn search_run(ctx: &OperationContext, config: OperationThreadConfig) -> anyhow::
 }
 
 fn test_run(ctx: &OperationContext, config: OperationThreadConfig) -> anyhow::Result<()> {
-    for command in config.test_config.test_pattern.iter() {
-        log::info!("Sending command: {:?}", command);
-        ctx.command_tx.send(*command);
-        let response = ctx.wait_response(); // Wait for CommandRequest
-        match response {
-            control_thread::Response::CommandRequest(_) => {}
-            _ => {
-                return Err(anyhow::anyhow!("Unexpected response: {:?}", response));
-            }
-        }
-    }
-    Ok(())
+    test_run::run(ctx, &config.test_config)
 }
@@ -52,7 +52,8 @@ fn main() {
 
 pub struct FIR<T> {
     coefficients: Vec<T>,
-    buffer: Vec<T>,
+    buffer: Vec<T>, // Ring buffer of the latest inputs
+    head: usize,    // Index of the oldest input
 }
 
 impl<T> FIR<T>
@@ -64,18 +65,23 @@ where
         FIR {
             coefficients,
             buffer,
+            head: 0,
         }
     }
 
     pub fn filter(&mut self, input: T) -> T {
-        // Adds the new input value to the beginning of the buffer and deletes the old value
-        self.buffer.remove(0);
-        self.buffer.push(input);
+        let len = self.buffer.len();
+        if len == 0 {
+            return T::default();
+        }
+        // Overwrite the oldest value with the new input
+        self.buffer[self.head] = input;
+        self.head = (self.head + 1) % len;
 
-        // FIR filter
+        // FIR filter. coefficients[0] is applied to the oldest value.
         let mut output = T::default();
-        for i in 0..self.coefficients.len() {
-            output = output + self.buffer[i] * self.coefficients[i];
+        for i in 0..len {
+            output = output + self.buffer[(self.head + i) % len] * self.coefficients[i];
         }
         output
     }
@@ -84,6 +90,7 @@ where
         for item in self.buffer.iter_mut() {
             *item = T::default();
         }
+        self.head = 0;
     }
 }
 
@@ -111,72 +118,103 @@ pub fn rad(deg: f32) -> f32 {
 pub fn deg(rad: f32) -> f32 {
     rad * 180.0 / std::f32::consts::PI
 }
+// Fixed-size ring buffer of the latest values. Does not allocate after new().
+struct Window<T> {
+    values: Vec<T>,
+    head: usize, // Index of the next value to be written
+    len: usize,
+}
+
+impl<T: Default + Copy> Window<T> {
+    fn new(size: usize) -> Self {
+        Window {
+            values: vec![T::default(); size.max(1)],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    // Push a value and return the overwritten one, if any
+    fn push(&mut self, value: T) -> Option<T> {
+        let old = if self.len == self.values.len() {
+            Some(self.values[self.head])
+        } else {
+            self.len += 1;
+            None
+        };
+        self.values[self.head] = value;
+        self.head = (self.head + 1) % self.values.len();
+        old
+    }
+
+    fn clear(&mut self) {
+        self.head = 0;
+        self.len = 0;
+    }
+}
+
 pub struct MovingAverage {
-    window_size: usize,
-    values: Vec<f32>,
+    window: Window<f32>,
+    sum: f32,
 }
 
 impl MovingAverage {
     pub fn new(window_size: usize) -> Self {
         MovingAverage {
-            window_size,
-            values: Vec::with_capacity(window_size),
+            window: Window::new(window_size),
+            sum: 0.0,
         }
     }
 
     pub fn update(&mut self, value: f32) -> f32 {
-        if self.values.len() == self.window_size {
-            self.values.remove(0);
-        }
-        self.values.push(value);
-        let mut sum = 0.0;
-        for v in &self.values {
-            sum += v;
+        if let Some(old) = self.window.push(value) {
+            self.sum -= old;
         }
-        sum / self.values.len() as f32
+        self.sum += value;
+        self.sum / self.window.len as f32
     }
 
     pub fn reset(&mut self) {
-        self.values.clear();
+        self.window.clear();
+        self.sum = 0.0;
     }
 
+    // Allocates. Do not call in the control cycle.
     pub fn set_window_size(&mut self, window_size: usize) {
-        self.window_size = window_size;
+        self.window = Window::new(window_size);
         self.reset();
     }
 }
 
 pub struct MovingAverageInt {
-    window_size: usize,
-    values: Vec<i32>,
+    window: Window<i32>,
     sum: i32,
 }
 
 impl MovingAverageInt {
     pub fn new(window_size: usize) -> Self {
         MovingAverageInt {
-            window_size,
-            values: Vec::with_capacity(window_size),
+            window: Window::new(window_size),
             sum: 0,
         }
     }
 
     pub fn update(&mut self, value: i32) -> i32 {
-        if self.values.len() == self.window_size {
-            self.sum -= self.values.remove(0);
+        if let Some(old) = self.window.push(value) {
+            self.sum -= old;
         }
-        self.values.push(value);
         self.sum += value;
-        self.sum / self.values.len() as i32
+        self.sum / self.window.len as i32
     }
 
     pub fn reset(&mut self) {
-        self.values.clear();
+        self.window.clear();
         self.sum = 0;
     }
 
+    // Allocates. Do not call in the control cycle.
     pub fn set_window_size(&mut self, window_size: usize) {
-        self.window_size = window_size;
+        self.window = Window::new(window_size);
         self.reset();
     }
 }
@@ -1,7 +1,10 @@
 use crate::log_thread;
 use crate::mm_const;
+use crate::pid::PidTerms;
+use mm_log::event::{Event, EventKind};
 use mm_maze::maze::{Maze, Wall};
 use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
 
 #[derive(Debug, Default, Clone, Copy)]
 pub struct OdsImu {
@@ -39,24 +42,35 @@ pub struct OdsWallSensor {
     pub batt_phy: f32,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+// Internals of the controllers in the last control cycle
+#[derive(Debug, Default, Clone, Copy)]
+pub struct OdsPid {
+    pub v: PidTerms,
+    pub omega: PidTerms,
+    pub theta: PidTerms,
+    pub wall: PidTerms,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
 pub struct MicromouseState {
-    pub time: u32,     // Time [ms]
-    pub x: f32,        // X coordinate [m]
-    pub y: f32,        // Y coordinate [m]
-    pub theta: f32,    // Heading [rad]
-    pub omega: f32,    // Angular velocity [rad/s]
-    pub v_batt: f32,   // Battery voltage [V]
-    pub v: f32,        // Velocity [m/s]
-    pub target_v: f32, // Target velocity [m/s]
-    pub v_l: f32,      // Left wheel velocity [m/s]
-    pub v_r: f32,      // Right wheel velocity [m/s]
-    pub duty_l: f32,   // Left wheel duty [%]
-    pub duty_r: f32,   // Left wheel duty [%]
-    pub ls: u16,       // Left side sensor value
-    pub lf: u16,       // Left front sensor value
-    pub rf: u16,       // Right front sensor value
-    pub rs: u16,       // Right side sensor value
+    pub time: u32,         // Time [ms]
+    pub x: f32,            // X coordinate [m]
+    pub y: f32,            // Y coordinate [m]
+    pub theta: f32,        // Heading [rad]
+    pub omega: f32,        // Angular velocity [rad/s]
+    pub v_batt: f32,       // Battery voltage [V]
+    pub v: f32,            // Velocity [m/s]
+    pub target_v: f32,     // Target velocity [m/s]
+    pub target_omega: f32, // Target angular velocity [rad/s]
+    pub target_theta: f32, // Target heading [rad]
+    pub v_l: f32,          // Left wheel velocity [m/s]
+    pub v_r: f32,          // Right wheel velocity [m/s]
+    pub duty_l: f32,       // Left wheel duty [%]
+    pub duty_r: f32,       // Left wheel duty [%]
+    pub ls: u16,           // Left side sensor value
+    pub lf: u16,           // Left front sensor value
+    pub rf: u16,           // Right front sensor value
+    pub rs: u16,           // Right side sensor value
     pub ls_wall: Wall,
     pub lf_wall: Wall,
     pub rf_wall: Wall,
@@ -76,6 +90,8 @@ impl Default for MicromouseState {
             v_batt: 0.0,
             v: 0.0,
             target_v: 0.0,
+            target_omega: 0.0,
+            target_theta: std::f32::consts::PI / 2.0,
             v_l: 0.0,
             v_r: 0.0,
             duty_l: 0.0,
@@ -98,9 +114,10 @@ pub struct Ods {
     pub imu: OdsImu,
     pub encoder: OdsEncoder,
     pub wall_sensor: OdsWallSensor,
+    pub pid: OdsPid,
     pub micromouse: MicromouseState,
-    pub log: Vec<MicromouseState>,
-    pub log_msg: Vec<String>,
+    pub log: log_thread::LogBuffer, // Packed records, see log_thread::encode
+    pub events: VecDeque<Event>,    // The latest events, see Ods::event
     pub maze: Maze,
 }
 
@@ -110,10 +127,24 @@ impl Ods {
             imu: OdsImu::default(),
             encoder: OdsEncoder::default(),
             wall_sensor: OdsWallSensor::default(),
+            pid: OdsPid::default(),
             micromouse: MicromouseState::default(),
-            log: Vec::with_capacity(log_thread::LOG_LEN),
-            log_msg: Vec::with_capacity(log_thread::LOG_MSG_LEN),
+            log: log_thread::LogBuffer::new(),
+            events: VecDeque::with_capacity(log_thread::EVENT_LOG_LEN),
             maze: Maze::new(mm_const::MAZE_WIDTH, mm_const::MAZE_HEIGHT),
         }
     }
+
+    // Record an event with the time of MicromouseState
+    pub fn event(&mut self, kind: EventKind, code: u8, value: f32) {
+        if self.events.len() >= log_thread::EVENT_LOG_LEN {
+            self.events.pop_front();
+        }
+        self.events.push_back(Event {
+            time: self.micromouse.time,
+            kind,
+            code,
+            value,
+        });
+    }
 }
@@ -0,0 +1,172 @@
+// Execution time of the phases of the control cycle.
+// The control thread records the phases and timer_interrupt::sync_ms() closes each cycle.
+// The statistics are shown by the `perf` command and saved in the run log header.
+
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU32, Ordering::Relaxed};
+use std::sync::Mutex;
+
+pub const CYCLE_US: u32 = 1000; // Budget of a control cycle [us]
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Phase {
+    Measure,
+    Update,
+    Controller,
+    Log,
+}
+
+const PHASE_NUM: usize = 4;
+const ZERO: AtomicU32 = AtomicU32::new(0);
+
+static CYCLE_START: AtomicU32 = AtomicU32::new(0); // 0 if no cycle has started
+static MARK: AtomicU32 = AtomicU32::new(0); // End of the last phase, the start of the controller
+static PHASE_US: [AtomicU32; PHASE_NUM] = [ZERO; PHASE_NUM]; // In the current cycle
+static PHASE_RAN: AtomicU32 = AtomicU32::new(0); // Bit mask of the phases in the current cycle
+static STATS: Mutex<PerfStats> = Mutex::new(PerfStats::new());
+
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+pub struct PhaseStats {
+    pub min: u32, // [us]
+    pub max: u32, // [us]
+    pub avg: u32, // [us]
+    #[serde(skip)]
+    total: u64,
+    #[serde(skip)]
+    count: u32,
+}
+
+impl PhaseStats {
+    const fn new() -> Self {
+        PhaseStats {
+            min: 0,
+            max: 0,
+            avg: 0,
+            total: 0,
+            count: 0,
+        }
+    }
+
+    fn add(&mut self, us: u32) {
+        if self.count == 0 || us < self.min {
+            self.min = us;
+        }
+        self.max = self.max.max(us);
+        self.total += us as u64;
+        self.count += 1;
+        self.avg = (self.total / self.count as u64) as u32;
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+pub struct PerfStats {
+    pub measure: PhaseStats,
+    pub update: PhaseStats,
+    pub controller: PhaseStats,
+    pub log: PhaseStats,
+    pub cycle: PhaseStats, // From the end of sync_ms() to the next sync_ms()
+    pub cycles: u32,
+    pub overruns: u32, // Cycles longer than CYCLE_US
+}
+
+impl PerfStats {
+    const fn new() -> Self {
+        PerfStats {
+            measure: PhaseStats::new(),
+            update: PhaseStats::new(),
+            controller: PhaseStats::new(),
+            log: PhaseStats::new(),
+            cycle: PhaseStats::new(),
+            cycles: 0,
+            overruns: 0,
+        }
+    }
+
+    fn phase(&mut self, phase: usize) -> &mut PhaseStats {
+        match phase {
+            0 => &mut self.measure,
+            1 => &mut self.update,
+            2 => &mut self.controller,
+            _ => &mut self.log,
+        }
+    }
+}
+
+fn now() -> u32 {
+    unsafe { esp_idf_sys::esp_timer_get_time() as u32 }
+}
+
+fn add(phase: Phase, us: u32) {
+    PHASE_US[phase as usize].fetch_add(us, Relaxed);
+    PHASE_RAN.fetch_or(1 << phase as u32, Relaxed);
+}
+
+// Measures a phase until it is dropped
+pub struct Timer {
+    phase: Phase,
+    start: u32,
+}
+
+impl Timer {
+    pub fn start(phase: Phase) -> Self {
+        Timer {
+            phase,
+            start: now(),
+        }
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        let end = now();
+        add(self.phase, end.wrapping_sub(self.start));
+        MARK.store(end, Relaxed);
+    }
+}
+
+// Record the time since the end of the last phase.
+// Only once per phase, so calls without a preceding phase are ignored.
+pub fn record_from_mark(phase: Phase) {
+    let mark = MARK.swap(0, Relaxed);
+    if mark != 0 {
+        add(phase, now().wrapping_sub(mark));
+    }
+}
+
+pub fn start_cycle() {
+    CYCLE_START.store(now(), Relaxed);
+}
+
+pub fn end_cycle() {
+    let start = CYCLE_START.swap(0, Relaxed);
+    let ran = PHASE_RAN.swap(0, Relaxed);
+    MARK.store(0, Relaxed);
+    let mut phase_us = [0; PHASE_NUM];
+    for (us, atomic) in phase_us.iter_mut().zip(PHASE_US.iter()) {
+        *us = atomic.swap(0, Relaxed);
+    }
+    if start == 0 {
+        return;
+    }
+    let cycle_us = now().wrapping_sub(start);
+
+    let mut stats = STATS.lock().unwrap();
+    for (i, us) in phase_us.iter().enumerate() {
+        if ran & (1 << i) != 0 {
+            stats.phase(i).add(*us);
+        }
+    }
+    stats.cycle.add(cycle_us);
+    stats.cycles += 1;
+    if cycle_us > CYCLE_US {
+        stats.overruns += 1;
+    }
+}
+
+pub fn stats() -> PerfStats {
+    *STATS.lock().unwrap()
+}
+
+pub fn reset() {
+    *STATS.lock().unwrap() = PerfStats::new();
+}
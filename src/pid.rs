@@ -1,12 +1,13 @@
-use serde::{Deserialize, Serialize};
+pub use mm_config::ctrl::PidParameter;
 
-#[derive(Debug, Serialize, Deserialize, Default, Clone, Copy)]
-pub struct PidParameter {
+// Contributions of each term in the last update
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PidTerms {
+    pub error: f32,
     pub p: f32,
     pub i: f32,
     pub d: f32,
-    pub i_limit: f32,
-    pub dead_zone: f32,
+    pub output: f32,
 }
 
 pub struct Pid {
@@ -17,6 +18,7 @@ pub struct Pid {
     integral: f32,
     i_limit: f32,
     dead_zone: f32,
+    terms: PidTerms,
 }
 
 impl Pid {
@@ -29,6 +31,7 @@ impl Pid {
             integral: 0.0,
             i_limit: 0.0,
             dead_zone: 0.0,
+            terms: PidTerms::default(),
         }
     }
     pub fn new(parameter: &PidParameter) -> Self {
@@ -40,6 +43,7 @@ impl Pid {
             integral: 0.0,
             i_limit: parameter.i_limit,
             dead_zone: parameter.dead_zone,
+            terms: PidTerms::default(),
         }
     }
 
@@ -71,13 +75,22 @@ impl Pid {
         self.last_error = error;
 
         // The output is the sum of the terms
-        p + i + d
+        let output = p + i + d;
+        self.terms = PidTerms {
+            error,
+            p,
+            i,
+            d,
+            output,
+        };
+        output
     }
 
     #[allow(dead_code)]
     pub fn reset(&mut self) {
         self.last_error = 0.0;
         self.integral = 0.0;
+        self.terms = PidTerms::default();
     }
 
     #[allow(dead_code)]
@@ -103,4 +116,8 @@ impl Pid {
     pub fn get_integral(&self) -> f32 {
         self.integral
     }
+
+    pub fn get_terms(&self) -> PidTerms {
+        self.terms
+    }
 }
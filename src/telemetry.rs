@@ -0,0 +1,98 @@
+// Stream log channels over UART as framed binary packets while the mouse runs.
+// Use `mmlog telem <port>` on the PC to receive them.
+
+use crate::log_thread;
+use crate::ods;
+use crate::uart;
+use esp_idf_hal::delay::FreeRtos;
+use mm_log::frame;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+
+// Send the header again every HEADER_PERIOD samples,
+// so that a receiver started later can decode the stream.
+const HEADER_PERIOD: u32 = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    Start(u16, u64), // The arguments are the interval [ms] and the channel mask
+    Stop,
+}
+
+pub fn init(ods: &Arc<Mutex<ods::Ods>>) -> anyhow::Result<Sender<Command>> {
+    let ods = ods.clone();
+
+    // Spawn the telemetry thread
+    esp_idf_hal::task::thread::ThreadSpawnConfiguration {
+        name: None,
+        stack_size: 3072,
+        priority: 5,
+        inherit: false, // don't inherit this configuration across threads
+        pin_to_core: Some(esp_idf_hal::cpu::Core::Core0),
+    }
+    .set()?;
+
+    let (tx, rx): (Sender<Command>, Receiver<Command>) = mpsc::channel();
+
+    std::thread::Builder::new().spawn(move || -> anyhow::Result<()> {
+        let mut setting: Option<(u16, u64)> = None;
+        let mut seq: u16 = 0;
+        let mut count = 0;
+        let mut record = Vec::new();
+        let mut packet = Vec::new();
+
+        loop {
+            // Block while stopped, otherwise just check the queue
+            let command = if setting.is_some() {
+                match rx.try_recv() {
+                    Ok(cmd) => Some(cmd),
+                    Err(TryRecvError::Empty) => None,
+                    Err(TryRecvError::Disconnected) => return Ok(()),
+                }
+            } else {
+                Some(rx.recv()?)
+            };
+
+            match command {
+                Some(Command::Start(interval, channels)) => {
+                    log::info!("Telemetry start ({}[ms], {:x})", interval, channels);
+                    setting = Some((interval.max(1), channels));
+                    count = 0;
+                }
+                Some(Command::Stop) => {
+                    log::info!("Telemetry stop");
+                    setting = None;
+                    continue;
+                }
+                None => {}
+            }
+
+            let (interval, channels) = match setting {
+                Some(s) => s,
+                None => continue,
+            };
+
+            packet.clear();
+            if count % HEADER_PERIOD == 0 {
+                let mut header = Vec::new();
+                log_thread::header(interval, channels).write(&mut header)?;
+                frame::encode_packet(frame::KIND_HEADER, seq, &header, &mut packet);
+                seq = seq.wrapping_add(1);
+            }
+            count += 1;
+
+            record.clear();
+            {
+                let ods = ods.lock().unwrap();
+                log_thread::encode(&ods, channels, &mut record);
+            }
+            frame::encode_packet(frame::KIND_SAMPLE, seq, &record, &mut packet);
+            seq = seq.wrapping_add(1);
+
+            uart::send(&packet)?;
+            FreeRtos::delay_ms(interval as u32);
+        }
+    })?;
+
+    Ok(tx)
+}
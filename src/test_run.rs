@@ -0,0 +1,212 @@
+use crate::control_thread::{Command, Response};
+use crate::log_thread;
+use crate::OperationContext;
+use esp_idf_hal::delay::FreeRtos;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+
+const RESULT_PATH: &str = "/sf/test_result.json";
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct TestConfig {
+    pub test_pattern: Vec<TestStep>,
+}
+
+// A step of the test pattern.
+// Plain commands are written as before (e.g. "SForward", {"StartLog": 2}),
+// so existing ope_cfg.json files are still valid.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum TestStep {
+    Command(Command),
+    Control(TestControl),
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub enum TestControl {
+    // Run `steps` `count` times.
+    // If `log_interval` is given, the block is wrapped with StartLog/StopLog.
+    // `log_channels` selects the channels to be logged (all channels if empty).
+    // With `log_trigger`, only the records around a trigger (fault, wall error) are saved.
+    Repeat {
+        count: u32,
+        #[serde(default)]
+        log_interval: Option<u8>,
+        #[serde(default)]
+        log_channels: Vec<String>,
+        #[serde(default)]
+        log_trigger: Option<log_thread::TriggerConfig>,
+        steps: Vec<TestStep>,
+    },
+    // Wait for the given time [ms]
+    Wait(u32),
+    // Check the state of the micromouse at this point of the pattern
+    Assert(TestAssertion),
+}
+
+// X and Y are the position in the current block, as MicromouseState has it: the origin is the
+// corner of the block and y is the direction of travel. They are reset at each block and turn,
+// e.g. x = y = 0.045 (the center of the block) after SPivot.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub enum TestAssertion {
+    X { expected: f32, tolerance: f32 },     // [m]
+    Y { expected: f32, tolerance: f32 },     // [m]
+    Theta { expected: f32, tolerance: f32 }, // [rad]
+    NoFault,                                 // No fault so far in the test
+}
+
+#[derive(Debug, Serialize)]
+struct AssertionResult {
+    assertion: TestAssertion,
+    actual: Option<f32>,
+    passed: bool,
+}
+
+#[derive(Debug, Serialize, Default)]
+struct TestResult {
+    passed: bool,
+    assertions: u32,
+    failures: u32,
+    faults: Vec<String>,
+    results: Vec<AssertionResult>,
+}
+
+impl TestResult {
+    // Every fault of the test comes here, so that NoFault agrees with the result
+    fn fault(&mut self, ctx: &OperationContext, msg: String) {
+        log::error!("Test fault: {}", msg);
+        ctx.command_tx
+            .send(Command::LogTrigger(log_thread::TriggerReason::Fault));
+        self.faults.push(msg);
+    }
+}
+
+pub fn run(ctx: &OperationContext, config: &TestConfig) -> anyhow::Result<()> {
+    let mut result = TestResult::default();
+
+    run_steps(ctx, &config.test_pattern, &mut result);
+
+    result.failures = result.results.iter().filter(|r| !r.passed).count() as u32;
+    result.passed = result.failures == 0 && result.faults.is_empty();
+
+    uprintln!(
+        "Test {}: {}/{} assertions passed, {} faults",
+        if result.passed { "PASSED" } else { "FAILED" },
+        result.assertions - result.failures,
+        result.assertions,
+        result.faults.len()
+    );
+    log::info!(
+        "Test result: passed={}, assertions={}, failures={}, faults={}",
+        result.passed,
+        result.assertions,
+        result.failures,
+        result.faults.len()
+    );
+
+    let file = File::create(RESULT_PATH)?;
+    serde_json::to_writer_pretty(file, &result)?;
+    Ok(())
+}
+
+fn run_steps(ctx: &OperationContext, steps: &[TestStep], result: &mut TestResult) {
+    for step in steps.iter() {
+        match step {
+            TestStep::Command(command) => send_command(ctx, command, result),
+            TestStep::Control(TestControl::Repeat {
+                count,
+                log_interval,
+                log_channels,
+                log_trigger,
+                steps,
+            }) => {
+                if let Some(interval) = log_interval {
+                    match log_thread::channel_mask(log_channels) {
+                        Ok(channels) => send_command(
+                            ctx,
+                            &match log_trigger {
+                                Some(trigger) => {
+                                    Command::StartLogTrigger(*interval, channels, *trigger)
+                                }
+                                None => Command::StartLogChannels(*interval, channels),
+                            },
+                            result,
+                        ),
+                        Err(e) => result.fault(ctx, format!("{}", e)),
+                    }
+                }
+                for i in 0..*count {
+                    log::info!("Repeat {}/{}", i + 1, count);
+                    run_steps(ctx, steps, result);
+                }
+                if log_interval.is_some() {
+                    send_command(ctx, &Command::StopLog, result);
+                }
+            }
+            TestStep::Control(TestControl::Wait(ms)) => {
+                FreeRtos::delay_ms(*ms);
+            }
+            TestStep::Control(TestControl::Assert(assertion)) => {
+                let r = check(ctx, *assertion, result);
+                log::info!(
+                    "Assert {:?}: actual={:?}, passed={}",
+                    assertion,
+                    r.actual,
+                    r.passed
+                );
+                result.assertions += 1;
+                result.results.push(r);
+            }
+        }
+    }
+}
+
+fn send_command(ctx: &OperationContext, command: &Command, result: &mut TestResult) {
+    log::info!("Sending command: {:?}", command);
+    ctx.command_tx.send(command.clone());
+    let response = ctx.wait_response(); // Wait for CommandRequest
+    match response {
+        Response::CommandRequest(_) | Response::ConfigReloaded(true) => {}
+        _ => result.fault(
+            ctx,
+            format!("Unexpected response to {:?}: {:?}", command, response),
+        ),
+    }
+}
+
+fn check(ctx: &OperationContext, assertion: TestAssertion, result: &TestResult) -> AssertionResult {
+    let micromouse = ctx.ods.lock().unwrap().micromouse;
+    let within =
+        |actual: f32, expected: f32, tolerance: f32| (actual - expected).abs() <= tolerance;
+
+    let (actual, passed) = match assertion {
+        TestAssertion::X {
+            expected,
+            tolerance,
+        } => (
+            Some(micromouse.x),
+            within(micromouse.x, expected, tolerance),
+        ),
+        TestAssertion::Y {
+            expected,
+            tolerance,
+        } => (
+            Some(micromouse.y),
+            within(micromouse.y, expected, tolerance),
+        ),
+        TestAssertion::Theta {
+            expected,
+            tolerance,
+        } => (
+            Some(micromouse.theta),
+            within(micromouse.theta, expected, tolerance),
+        ),
+        TestAssertion::NoFault => (Some(result.faults.len() as f32), result.faults.is_empty()),
+    };
+
+    AssertionResult {
+        assertion,
+        actual,
+        passed,
+    }
+}
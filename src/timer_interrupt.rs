@@ -57,7 +57,10 @@ pub fn wait_us(duration_us: u32) {
 }
 
 // Wait until the timer counter is reset to 0
+// This is the end of a control cycle.
 pub fn sync_ms() {
+    crate::alloc_counter::end_cycle();
+    crate::perf::end_cycle();
     let mut prev = get_us();
     loop {
         let now = get_us();
@@ -66,6 +69,7 @@ pub fn sync_ms() {
         }
         prev = now;
     }
+    crate::perf::start_cycle();
 }
 
 // Timer interrupt handler
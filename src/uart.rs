@@ -50,6 +50,7 @@ pub fn send(buf: &[u8]) -> Result<usize, esp_idf_sys::EspError> {
 
 // Macros, like println! and print!
 use core::fmt::{self, Write};
+use std::sync::Mutex;
 
 #[macro_export]
 macro_rules! uprint {
@@ -67,10 +68,25 @@ pub fn _print(args: fmt::Arguments) {
     writer.write_fmt(args).unwrap();
 }
 
+// While capturing, the output of uprint! is kept instead of being sent (console JSON mode)
+static CAPTURE: Mutex<Option<String>> = Mutex::new(None);
+
+pub fn start_capture() {
+    *CAPTURE.lock().unwrap() = Some(String::new());
+}
+
+pub fn end_capture() -> String {
+    CAPTURE.lock().unwrap().take().unwrap_or_default()
+}
+
 pub struct UartWriter {}
 
 impl Write for UartWriter {
     fn write_str(&mut self, s: &str) -> fmt::Result {
+        if let Some(buffer) = CAPTURE.lock().unwrap().as_mut() {
+            buffer.push_str(s);
+            return Ok(());
+        }
         send(s.as_bytes()).unwrap();
         Ok(())
     }
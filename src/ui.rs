@@ -2,6 +2,18 @@ use crate::control_thread;
 use crate::led::LedColor::*;
 use crate::OperationContext;
 use esp_idf_hal::delay::FreeRtos;
+use mm_config::ctrl::MAX_PROFILES;
+
+const HOLD_THRESHOLD: u16 = 950; // Raw value of a side sensor with a hand in front of it
+
+// Blinks of the green LED for the number of an item in select()
+const BLINKS: [&str; MAX_PROFILES] = [
+    "1000000",
+    "101000000",
+    "10101000000",
+    "1010101000000",
+    "101010101000000",
+];
 
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum UserOperation {
@@ -22,11 +34,11 @@ pub fn hold_ws(ctx: &OperationContext, timeout: Option<u16>) -> UserOperation {
             let wall_sensor = ctx.ods.lock().unwrap().wall_sensor;
             (wall_sensor.rs_raw.unwrap(), wall_sensor.ls_raw.unwrap())
         };
-        if ls > 950 {
+        if ls > HOLD_THRESHOLD {
             result = UserOperation::HoldL;
             break;
         }
-        if rs > 950 {
+        if rs > HOLD_THRESHOLD {
             result = UserOperation::HoldR;
             break;
         }
@@ -44,6 +56,43 @@ pub fn hold_ws(ctx: &OperationContext, timeout: Option<u16>) -> UserOperation {
     result
 }
 
+// Wait until the hand is taken away from the side sensors
+fn release(ctx: &OperationContext) {
+    ctx.command_tx
+        .send(control_thread::Command::SetActivateWallSensor(true));
+    FreeRtos::delay_ms(10);
+    loop {
+        let (rs, ls) = {
+            let wall_sensor = ctx.ods.lock().unwrap().wall_sensor;
+            (wall_sensor.rs_raw.unwrap(), wall_sensor.ls_raw.unwrap())
+        };
+        if rs <= HOLD_THRESHOLD && ls <= HOLD_THRESHOLD {
+            break;
+        }
+        FreeRtos::delay_ms(10);
+    }
+    ctx.command_tx
+        .send(control_thread::Command::SetActivateWallSensor(false));
+}
+
+// Pick one of `names`. Hold right for the next one and left to confirm.
+// The green LED blinks the number of the current one.
+pub fn select(ctx: &OperationContext, names: &[String]) -> usize {
+    let mut index = 0;
+    loop {
+        uprintln!("{}: {}", index + 1, names[index]);
+        let blinks = BLINKS[index.min(BLINKS.len() - 1)];
+        ctx.led_tx.send((Green, Some(blinks))).unwrap();
+        release(ctx);
+        match hold_ws(ctx, None) {
+            UserOperation::HoldR => index = (index + 1) % names.len(),
+            _ => break,
+        }
+    }
+    ctx.led_tx.send((Green, Some("0"))).unwrap();
+    index
+}
+
 pub fn wait(ctx: &OperationContext, expected: UserOperation) {
     ctx.led_tx.send((Green, Some("01"))).unwrap();
     assert!(expected == UserOperation::HoldL || expected == UserOperation::HoldR);